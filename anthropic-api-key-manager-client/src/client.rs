@@ -0,0 +1,259 @@
+use serde::de::DeserializeOwned;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::ClientError;
+use crate::protocol::{self, GrantReq, GrantStatus, KeyGrant, KeyPush, NodeSpendRes, SuccessRes, PROTOCOL_VERSION};
+use crate::transport::Transport;
+
+// How long a cached key is trusted before asking the manager again
+const DEFAULT_RECHECK_AFTER_SECS: i64 = 300;
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Gets this node a key from the manager and keeps it current.
+///
+/// Cached keys are handed out until they expire or `recheck_after_secs` has
+/// passed, after which the grant is requested again; the manager returns the
+/// node's current key, so rotations are picked up even if their push was
+/// missed. Route incoming requests through `handle_push` to pick them up
+/// straight away.
+///
+/// Leased grants are renewed on those rechecks. Apps that can go longer than
+/// a lease without asking for the key should call `renew` on a timer.
+pub struct Client<T: Transport> {
+    transport: T,
+    app_name: String,
+    app_version: String,
+    manager_nodes: Vec<String>,
+    manager_process: String,
+    invite_code: Option<String>,
+    recheck_after_secs: i64,
+    manager: Option<String>,  // Discovered manager node
+    grant: Option<KeyGrant>,
+    checked_at: i64,
+}
+
+impl<T: Transport> Client<T> {
+    pub fn new(transport: T, app_name: &str, app_version: &str) -> Self {
+        Client {
+            transport,
+            app_name: app_name.to_string(),
+            app_version: app_version.to_string(),
+            manager_nodes: Vec::new(),
+            manager_process: protocol::MANAGER_PROCESS.to_string(),
+            invite_code: None,
+            recheck_after_secs: DEFAULT_RECHECK_AFTER_SECS,
+            manager: None,
+            grant: None,
+            checked_at: 0,
+        }
+    }
+
+    /// Nodes that may host the manager, tried in order. Defaults to our own node.
+    pub fn manager_nodes(mut self, nodes: Vec<String>) -> Self {
+        self.manager_nodes = nodes;
+        self
+    }
+
+    /// Process the manager runs as, for managers installed from another publisher
+    pub fn manager_process(mut self, process: &str) -> Self {
+        self.manager_process = process.to_string();
+        self
+    }
+
+    /// Invite code to redeem if the manager requires one
+    pub fn invite_code(mut self, code: &str) -> Self {
+        self.invite_code = Some(code.to_string());
+        self
+    }
+
+    pub fn recheck_after_secs(mut self, secs: i64) -> Self {
+        self.recheck_after_secs = secs;
+        self
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// The manager node in use, once discovered
+    pub fn manager(&self) -> Option<&str> {
+        self.manager.as_deref()
+    }
+
+    pub fn cached_grant(&self) -> Option<&KeyGrant> {
+        self.grant.as_ref()
+    }
+
+    /// Find the first candidate node whose manager answers
+    pub fn discover(&mut self) -> Result<String, ClientError> {
+        if let Some(ref node) = self.manager {
+            return Ok(node.clone());
+        }
+
+        let candidates = if self.manager_nodes.is_empty() {
+            vec![self.transport.local_node()]
+        } else {
+            self.manager_nodes.clone()
+        };
+        for node in candidates {
+            match self.call::<KeyGrant>(&node, "GetMyGrant", serde_json::Value::Null) {
+                // Any well-formed answer, even a refusal, means a manager is there
+                Ok(_) | Err(ClientError::Manager(_)) => {
+                    self.manager = Some(node.clone());
+                    return Ok(node);
+                }
+                Err(_) => continue,
+            }
+        }
+        Err(ClientError::NoManager)
+    }
+
+    /// The key to use: the cached one while it's fresh, otherwise whatever the
+    /// manager grants now
+    pub fn api_key(&mut self) -> Result<String, ClientError> {
+        let now = now();
+        if now - self.checked_at < self.recheck_after_secs {
+            if let Some(key) = self.usable_key(now) {
+                return Ok(key);
+            }
+        }
+
+        let leased = self.usable_key(now).is_some()
+            && self.grant.as_ref().is_some_and(|g| g.lease_expires_at.is_some());
+        let result = if leased {
+            // The renewal carries the current key too; only a lapsed lease needs a new grant
+            match self.renew() {
+                Err(ClientError::NotGranted(_)) => self.refresh(),
+                result => result,
+            }
+        } else {
+            self.refresh()
+        };
+
+        match result {
+            // Keep using what we have while the manager is unreachable
+            Err(ClientError::Transport(e)) => self.usable_key(now).ok_or(ClientError::Transport(e)),
+            result => result,
+        }
+    }
+
+    /// Ask the manager for the node's grant, ignoring the cache
+    pub fn refresh(&mut self) -> Result<String, ClientError> {
+        let request = GrantReq {
+            protocol_version: PROTOCOL_VERSION,
+            app_name: self.app_name.clone(),
+            app_version: self.app_version.clone(),
+            invite_code: self.invite_code.clone(),
+        };
+        let grant: KeyGrant = self.call_manager("RequestGrant", serde_json::to_value(request).expect("grant request serializes"))?;
+        self.accept(grant)
+    }
+
+    /// Extend the node's lease and pick up its current terms. Fails with
+    /// `NotGranted` once the lease has lapsed; `refresh` then asks for a new grant.
+    pub fn renew(&mut self) -> Result<String, ClientError> {
+        let grant: KeyGrant = self.call_manager("RenewLease", serde_json::Value::Null)?;
+        self.accept(grant)
+    }
+
+    fn accept(&mut self, grant: KeyGrant) -> Result<String, ClientError> {
+        self.checked_at = now();
+
+        match grant.api_key.clone() {
+            Some(key) if grant.status == GrantStatus::Active => {
+                self.grant = Some(grant);
+                Ok(key)
+            }
+            _ => {
+                self.grant = None;
+                Err(ClientError::from_grant(&grant))
+            }
+        }
+    }
+
+    /// Drop the cached key, e.g. after Anthropic rejects it. The next
+    /// `api_key` call asks the manager.
+    pub fn invalidate(&mut self) {
+        self.grant = None;
+        self.checked_at = 0;
+    }
+
+    /// Where the node stands with the manager, without requesting a key
+    pub fn status(&mut self) -> Result<KeyGrant, ClientError> {
+        self.call_manager("GetMyGrant", serde_json::Value::Null)
+    }
+
+    pub fn spend(&mut self) -> Result<NodeSpendRes, ClientError> {
+        self.call_manager("GetMySpend", serde_json::Value::Null)
+    }
+
+    /// Give the key back so another node can have the slot
+    pub fn relinquish(&mut self) -> Result<(), ClientError> {
+        self.call_manager::<SuccessRes>("RelinquishGrant", serde_json::Value::Null)?;
+        self.invalidate();
+        Ok(())
+    }
+
+    /// Take in a request the app received. Returns the push if it was a key
+    /// pushed by the manager; the app should still send a response to it.
+    pub fn handle_push(&mut self, body: &[u8]) -> Option<KeyPush> {
+        let push = protocol::parse_push(body)?;
+        let grant = self.grant.get_or_insert(KeyGrant {
+            protocol_version: PROTOCOL_VERSION,
+            status: GrantStatus::Active,
+            grant_id: None,
+            api_key: None,
+            key_id: None,
+            issued_at: None,
+            expires_at: None,
+            spend_cap: None,
+            message: None,
+            retry_after_secs: None,
+            waitlist_position: None,
+            lease_expires_at: None,
+        });
+        grant.status = GrantStatus::Active;
+        grant.api_key = Some(push.api_key.clone());
+        grant.issued_at = Some(push.issued_at);
+        grant.grant_id = None;
+        grant.key_id = None;
+        grant.expires_at = None;
+        grant.lease_expires_at = None;
+
+        // Use the key now, but fetch the rest of the new grant on next use
+        self.checked_at = 0;
+        Some(push)
+    }
+
+    fn usable_key(&self, now: i64) -> Option<String> {
+        let grant = self.grant.as_ref()?;
+        let lapsed = |at: Option<i64>| at.is_some_and(|at| at <= now);
+        if grant.status != GrantStatus::Active || lapsed(grant.expires_at) || lapsed(grant.lease_expires_at) {
+            return None;
+        }
+        grant.api_key.clone()
+    }
+
+    fn call_manager<R: DeserializeOwned>(&mut self, method: &str, params: serde_json::Value) -> Result<R, ClientError> {
+        let manager = self.discover()?;
+        let result = self.call(&manager, method, params);
+        if let Err(ClientError::Transport(_)) = result {
+            // Look again next time; the manager may have moved
+            self.manager = None;
+        }
+        result
+    }
+
+    fn call<R: DeserializeOwned>(&mut self, node: &str, method: &str, params: serde_json::Value) -> Result<R, ClientError> {
+        let reply = self.transport.call(node, &self.manager_process, serde_json::json!({ method: params }))?;
+        serde_json::from_value::<Result<R, String>>(reply)
+            .map_err(|e| ClientError::Protocol(e.to_string()))?
+            .map_err(ClientError::Manager)
+    }
+}
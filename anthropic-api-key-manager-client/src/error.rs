@@ -0,0 +1,91 @@
+use std::fmt;
+
+use crate::protocol::{GrantStatus, KeyGrant};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientError {
+    /// None of the candidate nodes runs a manager that answered
+    NoManager,
+    /// The p2p call itself failed: offline node, timeout, ...
+    Transport(String),
+    /// The manager answered with something this client can't read
+    Protocol(String),
+    /// The manager refused the call outright
+    Manager(String),
+    /// No key free right now; the node is queued and the key will be pushed
+    PoolEmpty { waitlist_position: Option<u64>, message: String },
+    Banned(String),
+    RateLimited { retry_after_secs: Option<i64>, message: String },
+    /// Issuance is paused pool-wide after a burst of new nodes
+    IssuancePaused { retry_after_secs: Option<i64>, message: String },
+    InviteRequired(String),
+    InviteInvalid(String),
+    /// The key was retired and the manager won't issue another
+    KeyRetired(String),
+    NotGranted(String),
+}
+
+impl ClientError {
+    /// Worth retrying later without any change on our side
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ClientError::Transport(_)
+                | ClientError::PoolEmpty { .. }
+                | ClientError::RateLimited { .. }
+                | ClientError::IssuancePaused { .. }
+        )
+    }
+
+    /// The error for a grant that carries no usable key
+    pub(crate) fn from_grant(grant: &KeyGrant) -> Self {
+        let message = grant.message.clone().unwrap_or_default();
+        match grant.status {
+            GrantStatus::Active => ClientError::Protocol("Active grant without a key".to_string()),
+            GrantStatus::Waitlisted => ClientError::PoolEmpty {
+                waitlist_position: grant.waitlist_position,
+                message,
+            },
+            GrantStatus::KeyRetired => ClientError::KeyRetired(message),
+            GrantStatus::Banned => ClientError::Banned(message),
+            GrantStatus::RateLimited => ClientError::RateLimited {
+                retry_after_secs: grant.retry_after_secs,
+                message,
+            },
+            GrantStatus::IssuancePaused => ClientError::IssuancePaused {
+                retry_after_secs: grant.retry_after_secs,
+                message,
+            },
+            GrantStatus::InviteRequired => ClientError::InviteRequired(message),
+            GrantStatus::InviteInvalid => ClientError::InviteInvalid(message),
+            GrantStatus::NotGranted => ClientError::NotGranted(message),
+        }
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::NoManager => write!(f, "No key manager found"),
+            ClientError::Transport(e) => write!(f, "Failed to reach key manager: {}", e),
+            ClientError::Protocol(e) => write!(f, "Unexpected reply from key manager: {}", e),
+            ClientError::Manager(e) => write!(f, "Key manager error: {}", e),
+            ClientError::PoolEmpty { waitlist_position: Some(position), .. } => {
+                write!(f, "No API key available; waitlisted at position {}", position)
+            }
+            ClientError::PoolEmpty { .. } => write!(f, "No API key available"),
+            ClientError::Banned(reason) => write!(f, "Banned by the key manager: {}", reason),
+            ClientError::RateLimited { retry_after_secs: Some(secs), .. } => {
+                write!(f, "Rate limited by the key manager; retry in {}s", secs)
+            }
+            ClientError::RateLimited { .. } => write!(f, "Rate limited by the key manager"),
+            ClientError::IssuancePaused { .. } => write!(f, "Key issuance is paused"),
+            ClientError::InviteRequired(e)
+            | ClientError::InviteInvalid(e)
+            | ClientError::KeyRetired(e)
+            | ClientError::NotGranted(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
@@ -0,0 +1,288 @@
+//! An in-process stand-in for the manager, for testing apps that use the
+//! client without a running node. It serves a single caller from a small
+//! pool and lets the test ban, rate-limit, rotate, revoke or lapse leases
+//! on demand.
+
+use serde_json::{json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::ClientError;
+use crate::protocol::{GrantReq, GrantStatus, KeyGrant, KeyPush, NodeSpendRes, SuccessRes, MANAGER_PROCESS, PROTOCOL_VERSION};
+use crate::transport::Transport;
+
+struct FakeGrant {
+    grant_id: String,
+    key: String,
+    issued_at: i64,
+    retired: bool,
+    lease_expires_at: Option<i64>,
+}
+
+pub struct FakeManager {
+    node: String,
+    process: String,
+    keys: Vec<String>,
+    grant: Option<FakeGrant>,
+    banned: Option<String>,
+    rate_limited: Option<i64>,
+    invite_code: Option<String>,
+    reachable: bool,
+    spend: f64,
+    spend_cap: Option<f64>,
+    expires_at: Option<i64>,
+    lease_secs: Option<i64>,
+    lease_lapsed: bool,
+    next_id: u64,
+    calls: Vec<String>,
+    last_request: Option<GrantReq>,
+}
+
+impl FakeManager {
+    /// A manager on `node` with an empty pool
+    pub fn new(node: &str) -> Self {
+        FakeManager {
+            node: node.to_string(),
+            process: MANAGER_PROCESS.to_string(),
+            keys: Vec::new(),
+            grant: None,
+            banned: None,
+            rate_limited: None,
+            invite_code: None,
+            reachable: true,
+            spend: 0.0,
+            spend_cap: None,
+            expires_at: None,
+            lease_secs: None,
+            lease_lapsed: false,
+            next_id: 0,
+            calls: Vec::new(),
+            last_request: None,
+        }
+    }
+
+    /// Answer as `process` instead of the default manager process
+    pub fn with_process(mut self, process: &str) -> Self {
+        self.process = process.to_string();
+        self
+    }
+
+    pub fn with_keys(mut self, keys: &[&str]) -> Self {
+        self.keys.extend(keys.iter().map(|k| k.to_string()));
+        self
+    }
+
+    pub fn add_key(&mut self, key: &str) {
+        self.keys.push(key.to_string());
+    }
+
+    pub fn ban(&mut self, reason: &str) {
+        self.banned = Some(reason.to_string());
+        self.grant = None;
+    }
+
+    /// Refuse new grants until `clear_rate_limit`
+    pub fn rate_limit(&mut self, retry_after_secs: i64) {
+        self.rate_limited = Some(retry_after_secs);
+    }
+
+    pub fn clear_rate_limit(&mut self) {
+        self.rate_limited = None;
+    }
+
+    /// Only issue new grants to requests carrying this code
+    pub fn require_invite(&mut self, code: &str) {
+        self.invite_code = Some(code.to_string());
+    }
+
+    pub fn set_reachable(&mut self, reachable: bool) {
+        self.reachable = reachable;
+    }
+
+    pub fn set_spend(&mut self, spend: f64, spend_cap: Option<f64>) {
+        self.spend = spend;
+        self.spend_cap = spend_cap;
+    }
+
+    pub fn set_expires_at(&mut self, expires_at: Option<i64>) {
+        self.expires_at = expires_at;
+    }
+
+    /// Issue grants as leases of this many seconds
+    pub fn set_lease_secs(&mut self, lease_secs: Option<i64>) {
+        self.lease_secs = lease_secs;
+    }
+
+    /// Take the caller's key back as if it stopped renewing its lease
+    pub fn lapse_lease(&mut self) {
+        if self.grant.take().is_some() {
+            self.lease_lapsed = true;
+        }
+    }
+
+    /// Revoke the caller's key without a replacement
+    pub fn retire_key(&mut self) {
+        if let Some(ref mut grant) = self.grant {
+            grant.retired = true;
+            let key = grant.key.clone();
+            self.keys.retain(|k| *k != key);
+        }
+    }
+
+    /// Take the caller's key out of the pool and move it onto the next one.
+    /// Returns the push the real manager would send, for `Client::handle_push`.
+    pub fn rotate(&mut self) -> Option<Vec<u8>> {
+        let old = self.grant.take()?;
+        self.keys.retain(|k| *k != old.key);
+        let grant = self.issue()?;
+        let push = KeyPush {
+            push_id: format!("push_{}", grant.grant_id),
+            api_key: grant.key.clone(),
+            issued_at: grant.issued_at,
+            reason: "rotation".to_string(),
+        };
+        Some(serde_json::to_vec(&json!({ "ApiKeyPush": push })).unwrap())
+    }
+
+    /// Request variants received, in order
+    pub fn calls(&self) -> &[String] {
+        &self.calls
+    }
+
+    pub fn last_grant_request(&self) -> Option<&GrantReq> {
+        self.last_request.as_ref()
+    }
+
+    fn issue(&mut self) -> Option<&FakeGrant> {
+        let key = self.keys.first()?.clone();
+        self.next_id += 1;
+        self.lease_lapsed = false;
+        self.grant = Some(FakeGrant {
+            grant_id: format!("grant_{}", self.next_id),
+            key,
+            issued_at: self.next_id as i64,
+            retired: false,
+            lease_expires_at: self.lease_from_now(),
+        });
+        self.grant.as_ref()
+    }
+
+    fn lease_from_now(&self) -> Option<i64> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+        self.lease_secs.map(|secs| now + secs)
+    }
+
+    fn key_grant(&self, status: GrantStatus, message: Option<&str>) -> KeyGrant {
+        let grant = self.grant.as_ref();
+        KeyGrant {
+            protocol_version: PROTOCOL_VERSION,
+            api_key: grant.filter(|_| status == GrantStatus::Active).map(|g| g.key.clone()),
+            status,
+            grant_id: grant.map(|g| g.grant_id.clone()),
+            key_id: grant.map(|g| format!("key_{}", g.key)),
+            issued_at: grant.map(|g| g.issued_at),
+            expires_at: self.expires_at,
+            spend_cap: self.spend_cap,
+            message: message.map(|m| m.to_string()),
+            retry_after_secs: None,
+            waitlist_position: None,
+            lease_expires_at: grant.and_then(|g| g.lease_expires_at),
+        }
+    }
+
+    /// Where the caller stands, like `get_my_grant`
+    fn current(&self) -> KeyGrant {
+        if let Some(ref reason) = self.banned {
+            return self.key_grant(GrantStatus::Banned, Some(reason));
+        }
+        match self.grant {
+            Some(ref grant) if grant.retired => self.key_grant(GrantStatus::KeyRetired, Some("Your API key has been revoked")),
+            Some(_) => self.key_grant(GrantStatus::Active, None),
+            None if self.lease_lapsed => self.key_grant(GrantStatus::NotGranted, Some("Your lease expired")),
+            None => self.key_grant(GrantStatus::NotGranted, Some("No key has been granted to this node")),
+        }
+    }
+
+    fn request_grant(&mut self, request: GrantReq) -> KeyGrant {
+        let invite_ok = self.invite_code.is_none() || request.invite_code == self.invite_code;
+        self.last_request = Some(request);
+
+        let current = self.current();
+        if current.status != GrantStatus::NotGranted {
+            return current;
+        }
+        if !invite_ok {
+            return self.key_grant(GrantStatus::InviteRequired, Some("An invite code is required to get a key"));
+        }
+        if let Some(retry_after) = self.rate_limited {
+            let mut grant = self.key_grant(GrantStatus::RateLimited, Some("Issuance limit reached"));
+            grant.retry_after_secs = Some(retry_after);
+            return grant;
+        }
+        if self.issue().is_none() {
+            let mut grant = self.key_grant(GrantStatus::Waitlisted, Some("No active API keys available"));
+            grant.waitlist_position = Some(1);
+            return grant;
+        }
+        self.key_grant(GrantStatus::Active, None)
+    }
+
+    fn respond(&mut self, method: &str, params: Value) -> Result<Value, String> {
+        match method {
+            "GetMyGrant" => Ok(json!(self.current())),
+            "RequestGrant" => {
+                let request: GrantReq = serde_json::from_value(params).map_err(|e| e.to_string())?;
+                Ok(json!(self.request_grant(request)))
+            }
+            "RenewLease" => {
+                let lease_expires_at = self.lease_from_now();
+                if let Some(grant) = self.grant.as_mut().filter(|g| !g.retired) {
+                    grant.lease_expires_at = lease_expires_at;
+                }
+                Ok(json!(self.current()))
+            }
+            "GetMySpend" => {
+                let grant = self.grant.as_ref().ok_or("No key has been granted to this node")?;
+                Ok(json!(NodeSpendRes {
+                    key_id: format!("key_{}", grant.key),
+                    window_secs: 7 * 24 * 3600,
+                    recent_spend: self.spend,
+                    total_spend: self.spend,
+                    spend_cap: self.spend_cap,
+                    remaining_allowance: self.spend_cap.map(|cap| (cap - self.spend).max(0.0)),
+                    shared_with: 0,
+                }))
+            }
+            "RelinquishGrant" => {
+                self.grant.take().ok_or("No key has been granted to this node")?;
+                Ok(json!(SuccessRes {
+                    success: true,
+                    message: "Key given back".to_string(),
+                }))
+            }
+            _ => Err(format!("Unknown request {}", method)),
+        }
+    }
+}
+
+impl Transport for FakeManager {
+    fn call(&mut self, node: &str, process: &str, body: Value) -> Result<Value, ClientError> {
+        if !self.reachable || node != self.node || process != self.process {
+            return Err(ClientError::Transport(format!("{}@{} is unreachable", node, process)));
+        }
+
+        let (method, params) = body.as_object()
+            .and_then(|o| o.iter().next())
+            .map(|(method, params)| (method.clone(), params.clone()))
+            .ok_or_else(|| ClientError::Protocol("Request is not a variant".to_string()))?;
+        self.calls.push(method.clone());
+
+        Ok(match self.respond(&method, params) {
+            Ok(value) => json!({ "Ok": value }),
+            Err(e) => json!({ "Err": e }),
+        })
+    }
+
+    fn local_node(&self) -> String {
+        self.node.clone()
+    }
+}
@@ -0,0 +1,24 @@
+//! Client for apps that get their Anthropic API key from an
+//! anthropic-api-key-manager.
+//!
+//! ```ignore
+//! let mut keys = Client::new(P2pTransport::default(), "spider", "0.1.0")
+//!     .manager_nodes(vec!["keys.hypr".to_string()]);
+//! let api_key = keys.api_key()?;
+//! ```
+//!
+//! Apps should also pass incoming requests to `Client::handle_push`, so keys
+//! handed out from the waitlist or after a rotation are picked up as soon as
+//! the manager sends them.
+
+mod client;
+mod error;
+#[cfg(any(test, feature = "test-util"))]
+pub mod fake;
+pub mod protocol;
+pub mod transport;
+
+pub use client::Client;
+pub use error::ClientError;
+pub use protocol::{GrantStatus, KeyGrant, KeyPush, NodeSpendRes};
+pub use transport::{P2pTransport, Transport};
@@ -0,0 +1,89 @@
+//! Wire types shared with the manager. These mirror the manager's own
+//! definitions and must serialize identically; the manager's tests round-trip
+//! its types through these to keep them in step.
+
+use serde::{Deserialize, Serialize};
+
+// Grant protocol version this client was built against
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Process the manager runs as on whichever node hosts it, unless the client
+// is pointed elsewhere with `Client::manager_process`
+pub const MANAGER_PROCESS: &str = "anthropic-api-key-manager:anthropic-api-key-manager:ware.hypr";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum GrantStatus {
+    Active,
+    Waitlisted,
+    KeyRetired,
+    Banned,
+    RateLimited,
+    IssuancePaused,
+    InviteRequired,
+    InviteInvalid,
+    NotGranted,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GrantReq {
+    pub protocol_version: u32,
+    pub app_name: String,
+    pub app_version: String,
+    pub invite_code: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyGrant {
+    pub protocol_version: u32,
+    pub status: GrantStatus,
+    pub grant_id: Option<String>,
+    pub api_key: Option<String>,
+    pub key_id: Option<String>,
+    pub issued_at: Option<i64>,
+    pub expires_at: Option<i64>,
+    pub spend_cap: Option<f64>,
+    pub message: Option<String>,
+    pub retry_after_secs: Option<i64>,
+    pub waitlist_position: Option<u64>,
+    /// Renew before then or the key is taken back; None for permanent grants
+    #[serde(default)]
+    pub lease_expires_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NodeSpendRes {
+    pub key_id: String,
+    pub window_secs: i64,
+    pub recent_spend: f64,
+    pub total_spend: f64,
+    pub spend_cap: Option<f64>,
+    pub remaining_allowance: Option<f64>,
+    pub shared_with: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SuccessRes {
+    pub success: bool,
+    pub message: String,
+}
+
+/// Sent by the manager, unprompted, as `{"ApiKeyPush": KeyPush}` when a node
+/// gets a key off the waitlist or its key is rotated
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyPush {
+    pub push_id: String,
+    pub api_key: String,
+    pub issued_at: i64,
+    pub reason: String,
+}
+
+/// Parse a request from the manager, if it is a key push
+pub fn parse_push(body: &[u8]) -> Option<KeyPush> {
+    #[derive(Deserialize)]
+    enum Incoming {
+        ApiKeyPush(KeyPush),
+    }
+    match serde_json::from_slice::<Incoming>(body).ok()? {
+        Incoming::ApiKeyPush(push) => Some(push),
+    }
+}
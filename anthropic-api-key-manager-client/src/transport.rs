@@ -0,0 +1,48 @@
+//! How the client reaches a manager. `P2pTransport` is the real thing;
+//! `fake::FakeManager` stands in for it in tests.
+
+use hyperware_process_lib::{our, Address, Request};
+
+use crate::error::ClientError;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+
+pub trait Transport {
+    /// Send `body` to `process` on `node` and return its reply
+    fn call(&mut self, node: &str, process: &str, body: serde_json::Value) -> Result<serde_json::Value, ClientError>;
+
+    /// The node we run on, tried first when no manager nodes are configured
+    fn local_node(&self) -> String;
+}
+
+/// Calls the manager over p2p, blocking until it answers
+pub struct P2pTransport {
+    pub timeout_secs: u64,
+}
+
+impl Default for P2pTransport {
+    fn default() -> Self {
+        P2pTransport { timeout_secs: DEFAULT_TIMEOUT_SECS }
+    }
+}
+
+impl Transport for P2pTransport {
+    fn call(&mut self, node: &str, process: &str, body: serde_json::Value) -> Result<serde_json::Value, ClientError> {
+        let target: Address = format!("{}@{}", node, process)
+            .parse()
+            .map_err(|e| ClientError::Transport(format!("Invalid manager address: {:?}", e)))?;
+
+        let response = Request::to(target)
+            .body(serde_json::to_vec(&body).expect("request body serializes"))
+            .send_and_await_response(self.timeout_secs)
+            .map_err(|e| ClientError::Transport(e.to_string()))?
+            .map_err(|e| ClientError::Transport(format!("{:?}", e.kind)))?;
+
+        serde_json::from_slice(response.body())
+            .map_err(|e| ClientError::Protocol(format!("Reply is not JSON: {}", e)))
+    }
+
+    fn local_node(&self) -> String {
+        our().node.clone()
+    }
+}
@@ -0,0 +1,207 @@
+use anthropic_api_key_manager_client::fake::FakeManager;
+use anthropic_api_key_manager_client::{Client, ClientError, GrantStatus};
+
+const MANAGER: &str = "keys.os";
+
+fn client_for(fake: FakeManager) -> Client<FakeManager> {
+    Client::new(fake, "test-app", "1.0.0").manager_nodes(vec!["gone.os".to_string(), MANAGER.to_string()])
+}
+
+#[test]
+fn discovers_first_answering_manager() {
+    let mut client = client_for(FakeManager::new(MANAGER).with_keys(&["sk-a"]));
+    assert_eq!(client.discover(), Ok(MANAGER.to_string()));
+    assert_eq!(client.manager(), Some(MANAGER));
+}
+
+#[test]
+fn no_manager_when_nobody_answers() {
+    let mut client = Client::new(FakeManager::new(MANAGER), "test-app", "1.0.0")
+        .manager_nodes(vec!["gone.os".to_string()]);
+    assert_eq!(client.api_key(), Err(ClientError::NoManager));
+}
+
+#[test]
+fn defaults_to_local_node() {
+    let mut client = Client::new(FakeManager::new(MANAGER).with_keys(&["sk-a"]), "test-app", "1.0.0");
+    assert_eq!(client.api_key(), Ok("sk-a".to_string()));
+}
+
+#[test]
+fn caches_the_grant() {
+    let mut client = client_for(FakeManager::new(MANAGER).with_keys(&["sk-a"]));
+    assert_eq!(client.api_key(), Ok("sk-a".to_string()));
+    assert_eq!(client.api_key(), Ok("sk-a".to_string()));
+
+    let calls = client.transport_mut().calls();
+    assert_eq!(calls.iter().filter(|c| *c == "RequestGrant").count(), 1);
+    let request = client.transport_mut().last_grant_request().unwrap();
+    assert_eq!(request.app_name, "test-app");
+    assert_eq!(request.app_version, "1.0.0");
+}
+
+#[test]
+fn expired_grant_is_refreshed() {
+    let mut client = client_for(FakeManager::new(MANAGER).with_keys(&["sk-a"]));
+    client.transport_mut().set_expires_at(Some(1));
+    client.api_key().unwrap();
+    client.api_key().unwrap();
+
+    let calls = client.transport_mut().calls();
+    assert_eq!(calls.iter().filter(|c| *c == "RequestGrant").count(), 2);
+}
+
+#[test]
+fn pool_empty_is_typed() {
+    let mut client = client_for(FakeManager::new(MANAGER));
+    match client.api_key() {
+        Err(ClientError::PoolEmpty { waitlist_position, .. }) => assert_eq!(waitlist_position, Some(1)),
+        other => panic!("expected PoolEmpty, got {:?}", other),
+    }
+    assert!(client.api_key().unwrap_err().is_transient());
+}
+
+#[test]
+fn banned_is_typed() {
+    let mut client = client_for(FakeManager::new(MANAGER).with_keys(&["sk-a"]));
+    client.api_key().unwrap();
+    client.transport_mut().ban("abuse");
+    client.invalidate();
+    assert_eq!(client.api_key(), Err(ClientError::Banned("abuse".to_string())));
+}
+
+#[test]
+fn rate_limited_is_typed() {
+    let mut client = client_for(FakeManager::new(MANAGER).with_keys(&["sk-a"]));
+    client.transport_mut().rate_limit(120);
+    match client.api_key() {
+        Err(ClientError::RateLimited { retry_after_secs, .. }) => assert_eq!(retry_after_secs, Some(120)),
+        other => panic!("expected RateLimited, got {:?}", other),
+    }
+
+    client.transport_mut().clear_rate_limit();
+    assert_eq!(client.api_key(), Ok("sk-a".to_string()));
+}
+
+#[test]
+fn invite_code_is_sent() {
+    let mut fake = FakeManager::new(MANAGER).with_keys(&["sk-a"]);
+    fake.require_invite("ABCD-EFGH-JKMN");
+    let mut client = client_for(fake);
+    assert!(matches!(client.api_key(), Err(ClientError::InviteRequired(_))));
+
+    let mut fake = FakeManager::new(MANAGER).with_keys(&["sk-a"]);
+    fake.require_invite("ABCD-EFGH-JKMN");
+    let mut client = client_for(fake).invite_code("ABCD-EFGH-JKMN");
+    assert_eq!(client.api_key(), Ok("sk-a".to_string()));
+}
+
+#[test]
+fn pushed_rotation_replaces_the_cached_key() {
+    let mut client = client_for(FakeManager::new(MANAGER).with_keys(&["sk-a", "sk-b"]));
+    assert_eq!(client.api_key(), Ok("sk-a".to_string()));
+
+    let push = client.transport_mut().rotate().unwrap();
+    let received = client.handle_push(&push).unwrap();
+    assert_eq!(received.reason, "rotation");
+    assert_eq!(client.api_key(), Ok("sk-b".to_string()));
+    assert!(client.cached_grant().unwrap().grant_id.is_some());
+}
+
+#[test]
+fn missed_rotation_is_picked_up_on_recheck() {
+    let mut client = client_for(FakeManager::new(MANAGER).with_keys(&["sk-a", "sk-b"])).recheck_after_secs(0);
+    assert_eq!(client.api_key(), Ok("sk-a".to_string()));
+    client.transport_mut().rotate();
+    assert_eq!(client.api_key(), Ok("sk-b".to_string()));
+}
+
+#[test]
+fn revoked_key_is_reported() {
+    let mut client = client_for(FakeManager::new(MANAGER).with_keys(&["sk-a"])).recheck_after_secs(0);
+    client.api_key().unwrap();
+    client.transport_mut().retire_key();
+    assert!(matches!(client.api_key(), Err(ClientError::KeyRetired(_))));
+    assert!(client.cached_grant().is_none());
+}
+
+#[test]
+fn cached_key_survives_an_unreachable_manager() {
+    let mut client = client_for(FakeManager::new(MANAGER).with_keys(&["sk-a"])).recheck_after_secs(0);
+    client.api_key().unwrap();
+    client.transport_mut().set_reachable(false);
+    assert_eq!(client.api_key(), Ok("sk-a".to_string()));
+}
+
+#[test]
+fn other_requests_are_not_pushes() {
+    let mut client = client_for(FakeManager::new(MANAGER));
+    assert!(client.handle_push(br#"{"SomethingElse": 1}"#).is_none());
+}
+
+#[test]
+fn spend_and_relinquish() {
+    let mut client = client_for(FakeManager::new(MANAGER).with_keys(&["sk-a"]));
+    client.transport_mut().set_spend(4.0, Some(10.0));
+    client.api_key().unwrap();
+
+    let spend = client.spend().unwrap();
+    assert_eq!(spend.remaining_allowance, Some(6.0));
+
+    client.relinquish().unwrap();
+    assert!(client.cached_grant().is_none());
+    assert_eq!(client.status().unwrap().status, GrantStatus::NotGranted);
+    assert!(matches!(client.relinquish(), Err(ClientError::Manager(_))));
+}
+
+#[test]
+fn leased_grant_is_renewed_on_recheck() {
+    let mut fake = FakeManager::new(MANAGER).with_keys(&["sk-a"]);
+    fake.set_lease_secs(Some(3600));
+    let mut client = client_for(fake).recheck_after_secs(0);
+    assert_eq!(client.api_key(), Ok("sk-a".to_string()));
+    assert_eq!(client.api_key(), Ok("sk-a".to_string()));
+
+    let calls = client.transport_mut().calls();
+    assert_eq!(calls.iter().filter(|c| *c == "RequestGrant").count(), 1);
+    assert_eq!(calls.iter().filter(|c| *c == "RenewLease").count(), 1);
+}
+
+#[test]
+fn renew_returns_lease_terms() {
+    let mut fake = FakeManager::new(MANAGER).with_keys(&["sk-a"]);
+    fake.set_lease_secs(Some(3600));
+    let mut client = client_for(fake);
+    client.api_key().unwrap();
+
+    assert_eq!(client.renew(), Ok("sk-a".to_string()));
+    assert!(client.cached_grant().unwrap().lease_expires_at.is_some());
+}
+
+#[test]
+fn lapsed_lease_gets_a_new_grant() {
+    let mut fake = FakeManager::new(MANAGER).with_keys(&["sk-a"]);
+    fake.set_lease_secs(Some(3600));
+    let mut client = client_for(fake).recheck_after_secs(0);
+    let first = client.api_key().unwrap();
+    let first_grant = client.cached_grant().unwrap().grant_id.clone();
+
+    client.transport_mut().lapse_lease();
+    assert!(matches!(client.renew(), Err(ClientError::NotGranted(_))));
+    assert_eq!(client.api_key(), Ok(first));
+    assert_ne!(client.cached_grant().unwrap().grant_id, first_grant);
+}
+
+#[test]
+fn manager_process_is_configurable() {
+    let fake = || FakeManager::new(MANAGER)
+        .with_process("anthropic-api-key-manager:anthropic-api-key-manager:other.os")
+        .with_keys(&["sk-a"]);
+
+    let mut client = client_for(fake());
+    assert_eq!(client.api_key(), Err(ClientError::NoManager));
+
+    let mut client = client_for(fake())
+        .manager_process("anthropic-api-key-manager:anthropic-api-key-manager:other.os");
+    assert_eq!(client.api_key(), Ok("sk-a".to_string()));
+}
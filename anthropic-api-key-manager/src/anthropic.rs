@@ -0,0 +1,351 @@
+//! Client for the Anthropic Admin API. Every call to Anthropic goes through
+//! `AdminClient` so headers, retries and error mapping live in one place.
+
+use hyperware_process_lib::{
+    println,
+    http::client::send_request_await_response,
+    hyperapp::sleep,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use chrono::Utc;
+use url::Url;
+
+const ANTHROPIC_API_BASE: &str = "https://api.anthropic.com/v1/organizations";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+
+const MAX_ATTEMPTS: u32 = 4;
+const BASE_BACKOFF_MS: u64 = 1000;
+const MAX_BACKOFF_MS: u64 = 60000;
+
+// Consecutive 5xx/transport failures before we stop calling Anthropic for a while
+const BREAKER_THRESHOLD: u32 = 5;
+const BREAKER_COOLDOWN_SECS: i64 = 300;
+
+// Anthropic API structures
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AnthropicCostReport {
+    pub data: Vec<CostReportData>,
+    pub has_more: bool,
+    pub next_page: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CostReportData {
+    pub starting_at: String,
+    pub ending_at: String,
+    pub results: Vec<CostReportResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CostReportResult {
+    pub currency: String,              // Always "USD"
+    pub amount: String,                // Amount in cents as decimal string (e.g., "123.45" = $1.2345)
+    pub workspace_id: Option<String>,
+    pub description: Option<String>,   // Made optional since it can be null when not grouping by description
+    pub cost_type: Option<String>,     // Made optional
+    pub context_window: Option<String>,
+    pub model: Option<String>,
+    pub service_tier: Option<String>,
+    pub token_type: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AnthropicApiKey {
+    pub id: String,
+    pub name: String,
+    pub status: String,               // "active", "inactive" or "archived"
+    pub created_at: String,
+    pub workspace_id: Option<String>, // null for the default workspace
+    #[serde(default)]
+    pub partial_key_hint: Option<String>,  // e.g. "sk-ant-api03-R2D...igAA"
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AnthropicApiKeyList {
+    data: Vec<AnthropicApiKey>,
+    has_more: bool,
+    first_id: Option<String>,
+    last_id: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum AdminApiError {
+    NotConfigured,
+    Unauthorized(String),
+    Forbidden(String),
+    RateLimited { retry_after_secs: Option<u64> },
+    Server { status: u16, body: String },
+    Client { status: u16, body: String },
+    Transport(String),
+    Decode(String),
+    CircuitOpen { retry_in_secs: i64 },
+}
+
+impl fmt::Display for AdminApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminApiError::NotConfigured => write!(f, "Admin API key not configured"),
+            AdminApiError::Unauthorized(body) => write!(f, "Admin API key rejected (401): {}", body),
+            AdminApiError::Forbidden(body) => write!(f, "Admin API key lacks permission (403): {}", body),
+            AdminApiError::RateLimited { retry_after_secs: Some(secs) } => {
+                write!(f, "Rate limited by Anthropic; retry after {} seconds", secs)
+            }
+            AdminApiError::RateLimited { retry_after_secs: None } => write!(f, "Rate limited by Anthropic"),
+            AdminApiError::Server { status, body } => write!(f, "Anthropic server error {}: {}", status, body),
+            AdminApiError::Client { status, body } => write!(f, "API returned status {}: {}", status, body),
+            AdminApiError::Transport(e) => write!(f, "HTTP request failed: {}", e),
+            AdminApiError::Decode(e) => write!(f, "Failed to parse response: {}", e),
+            AdminApiError::CircuitOpen { retry_in_secs } => {
+                write!(f, "Anthropic API unavailable after repeated failures; retrying in {} seconds", retry_in_secs)
+            }
+        }
+    }
+}
+
+impl From<AdminApiError> for String {
+    fn from(e: AdminApiError) -> Self {
+        e.to_string()
+    }
+}
+
+#[derive(Default)]
+struct CircuitBreaker {
+    consecutive_failures: u32,
+    open_until: Option<i64>,
+}
+
+thread_local! {
+    // Shared by all clients so that every caller backs off together
+    static BREAKER: RefCell<CircuitBreaker> = RefCell::new(CircuitBreaker::default());
+}
+
+fn breaker_check() -> Result<(), AdminApiError> {
+    BREAKER.with(|b| {
+        let b = b.borrow();
+        match b.open_until {
+            Some(until) if until > Utc::now().timestamp() => Err(AdminApiError::CircuitOpen {
+                retry_in_secs: until - Utc::now().timestamp(),
+            }),
+            // Past the cooldown the next request goes through as a probe
+            _ => Ok(()),
+        }
+    })
+}
+
+fn breaker_record_success() {
+    BREAKER.with(|b| *b.borrow_mut() = CircuitBreaker::default());
+}
+
+fn breaker_record_failure() {
+    BREAKER.with(|b| {
+        let mut b = b.borrow_mut();
+        b.consecutive_failures += 1;
+        if b.consecutive_failures >= BREAKER_THRESHOLD {
+            println!("Anthropic API failed {} times in a row; pausing calls for {} seconds",
+                     b.consecutive_failures, BREAKER_COOLDOWN_SECS);
+            b.open_until = Some(Utc::now().timestamp() + BREAKER_COOLDOWN_SECS);
+        }
+    });
+}
+
+/// Parse `retry-after`, which is either delay-seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<u64> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(secs);
+    }
+    chrono::DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|at| (at.timestamp() - Utc::now().timestamp()).max(0) as u64)
+}
+
+fn backoff_ms(attempt: u32) -> u64 {
+    (BASE_BACKOFF_MS * 2_u64.pow(attempt.saturating_sub(1))).min(MAX_BACKOFF_MS)
+}
+
+pub struct AdminClient {
+    admin_key: String,
+}
+
+impl AdminClient {
+    pub fn new(admin_key: Option<&String>) -> Result<Self, AdminApiError> {
+        admin_key
+            .map(|key| AdminClient { admin_key: key.clone() })
+            .ok_or(AdminApiError::NotConfigured)
+    }
+
+    fn headers(&self) -> HashMap<String, String> {
+        let mut headers = HashMap::new();
+        headers.insert("anthropic-version".to_string(), ANTHROPIC_VERSION.to_string());
+        headers.insert("content-type".to_string(), "application/json".to_string());
+        headers.insert("x-api-key".to_string(), self.admin_key.clone());
+        headers
+    }
+
+    /// Send a request, retrying rate limits always and server/transport
+    /// failures only when `idempotent` (a retried create could run twice)
+    async fn send(
+        &self,
+        method: http::Method,
+        path_and_query: &str,
+        body: Vec<u8>,
+        idempotent: bool,
+    ) -> Result<Vec<u8>, AdminApiError> {
+        breaker_check()?;
+
+        let url = Url::parse(&format!("{}{}", ANTHROPIC_API_BASE, path_and_query))
+            .map_err(|e| AdminApiError::Transport(format!("Invalid URL: {}", e)))?;
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+
+            let result = send_request_await_response(
+                method.clone(),
+                url.clone(),
+                Some(self.headers()),
+                REQUEST_TIMEOUT_SECS * 1000,
+                body.clone(),
+            ).await;
+
+            let (error, wait_ms) = match result {
+                Ok(response) => {
+                    let status = response.status();
+                    if status.is_success() {
+                        breaker_record_success();
+                        return Ok(response.body().clone());
+                    }
+
+                    let body_text = String::from_utf8_lossy(response.body()).to_string();
+                    match status {
+                        http::StatusCode::UNAUTHORIZED => return Err(AdminApiError::Unauthorized(body_text)),
+                        http::StatusCode::FORBIDDEN => return Err(AdminApiError::Forbidden(body_text)),
+                        http::StatusCode::TOO_MANY_REQUESTS => {
+                            let retry_after_secs = response.headers()
+                                .get("retry-after")
+                                .and_then(|v| v.to_str().ok())
+                                .and_then(parse_retry_after);
+                            let error = AdminApiError::RateLimited { retry_after_secs };
+                            match retry_after_secs.map(|secs| secs.saturating_mul(1000)) {
+                                // Retrying before Anthropic asked us to would only be refused again;
+                                // hand the wait back to the caller instead
+                                Some(wait_ms) if wait_ms > MAX_BACKOFF_MS => return Err(error),
+                                Some(wait_ms) => (error, wait_ms),
+                                None => (error, backoff_ms(attempt)),
+                            }
+                        }
+                        status if status.is_server_error() => {
+                            breaker_record_failure();
+                            let error = AdminApiError::Server { status: status.as_u16(), body: body_text };
+                            if !idempotent {
+                                return Err(error);
+                            }
+                            (error, backoff_ms(attempt))
+                        }
+                        status => return Err(AdminApiError::Client { status: status.as_u16(), body: body_text }),
+                    }
+                }
+                Err(e) => {
+                    breaker_record_failure();
+                    let error = AdminApiError::Transport(format!("{:?}", e));
+                    if !idempotent {
+                        return Err(error);
+                    }
+                    (error, backoff_ms(attempt))
+                }
+            };
+
+            if attempt >= MAX_ATTEMPTS {
+                return Err(error);
+            }
+            // Don't keep hammering once the breaker has tripped
+            breaker_check()?;
+
+            println!("Anthropic request {} {} failed (attempt {}/{}): {}; retrying in {}ms",
+                     method, path_and_query, attempt, MAX_ATTEMPTS, error, wait_ms);
+            let _ = sleep(wait_ms).await;
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path_and_query: &str) -> Result<T, AdminApiError> {
+        let body = self.send(http::Method::GET, path_and_query, vec![], true).await?;
+        serde_json::from_slice(&body).map_err(|e| AdminApiError::Decode(e.to_string()))
+    }
+
+    async fn post<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        body: &serde_json::Value,
+        idempotent: bool,
+    ) -> Result<T, AdminApiError> {
+        let body = self.send(http::Method::POST, path, body.to_string().into_bytes(), idempotent).await?;
+        serde_json::from_slice(&body).map_err(|e| AdminApiError::Decode(e.to_string()))
+    }
+
+    pub async fn cost_report(&self, starting_at: &str, page: Option<&str>) -> Result<AnthropicCostReport, AdminApiError> {
+        let mut path = format!(
+            "/cost_report?starting_at={}&group_by[]=workspace_id&group_by[]=description&limit=30",
+            starting_at
+        );
+        if let Some(page) = page {
+            // Page tokens are opaque and may contain characters that aren't query-safe
+            let page: String = url::form_urlencoded::byte_serialize(page.as_bytes()).collect();
+            path.push_str(&format!("&page={}", page));
+        }
+        self.get(&path).await
+    }
+
+    /// List the organization's API keys, walking every page
+    pub async fn list_api_keys(
+        &self,
+        workspace_id: Option<&str>,
+        status: Option<&str>,
+    ) -> Result<Vec<AnthropicApiKey>, AdminApiError> {
+        let mut keys = Vec::new();
+        let mut after_id: Option<String> = None;
+
+        // Reconciliation needs the whole organization, not just the first page
+        loop {
+            let mut path = "/api_keys?limit=100".to_string();
+            if let Some(status) = status {
+                path.push_str(&format!("&status={}", status));
+            }
+            if let Some(ws_id) = workspace_id {
+                path.push_str(&format!("&workspace_id={}", ws_id));
+            }
+            if let Some(ref after) = after_id {
+                path.push_str(&format!("&after_id={}", after));
+            }
+
+            let page: AnthropicApiKeyList = self.get(&path).await?;
+            keys.extend(page.data);
+
+            match page.last_id {
+                Some(last_id) if page.has_more => after_id = Some(last_id),
+                _ => break,
+            }
+        }
+
+        Ok(keys)
+    }
+
+    pub async fn update_api_key(
+        &self,
+        key_id: &str,
+        status: Option<&str>,
+        name: Option<&str>,
+    ) -> Result<AnthropicApiKey, AdminApiError> {
+        let mut body = serde_json::Map::new();
+        if let Some(status) = status {
+            body.insert("status".to_string(), status.into());
+        }
+        if let Some(name) = name {
+            body.insert("name".to_string(), name.into());
+        }
+        self.post(&format!("/api_keys/{}", key_id), &serde_json::Value::Object(body), true).await
+    }
+}
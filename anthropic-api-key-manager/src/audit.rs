@@ -0,0 +1,341 @@
+//! Append-only audit log of administrative actions and key issuance.
+//!
+//! Each entry carries the hash of the one before it, so editing, dropping or
+//! reordering persisted entries breaks the chain from that point on. Hashes
+//! are HMACs under a key derived from the node's sealing key, so the chain
+//! can't be recomputed by someone who only has the state file. A separate
+//! anchor MACs the first and last links, which catches entries cut off either
+//! end of the log.
+//!
+//! Nothing is dropped unless the admin sets a retention limit. Pruning to
+//! that limit then appends an entry naming the last pruned seq and its hash,
+//! and the anchor moves with the oldest kept entry, so pruning doesn't read
+//! as tampering.
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::AuditEntry;
+
+// Actor recorded for actions the process takes on its own (expiry, spend caps, ...)
+pub const SYSTEM_ACTOR: &str = "system";
+
+// `prev_hash` of the first entry
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+pub const REDACTED: &str = "[redacted]";
+
+// Label for the HMAC key derived from the sealing key
+pub const KEY_LABEL: &[u8] = b"anthropic-api-key-manager/audit";
+
+// Fewest entries a retention limit may keep
+pub const MIN_RETENTION: u64 = 1000;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What happened, before it gets a place in the chain
+pub struct AuditEvent {
+    pub actor: String,
+    pub action: String,
+    pub target_key: Option<String>,   // key_id, never the key itself
+    pub target_node: Option<String>,
+    pub params: Vec<(String, String)>,
+}
+
+impl AuditEvent {
+    pub fn new(actor: &str, action: &str) -> Self {
+        AuditEvent {
+            actor: actor.to_string(),
+            action: action.to_string(),
+            target_key: None,
+            target_node: None,
+            params: Vec::new(),
+        }
+    }
+
+    pub fn key(mut self, key_id: &str) -> Self {
+        self.target_key = Some(key_id.to_string());
+        self
+    }
+
+    pub fn node(mut self, node_id: &str) -> Self {
+        self.target_node = Some(node_id.to_string());
+        self
+    }
+
+    pub fn param(mut self, name: &str, value: impl ToString) -> Self {
+        self.params.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn param_opt<T: std::fmt::Display>(self, name: &str, value: &Option<T>) -> Self {
+        match value {
+            Some(value) => self.param(name, value),
+            None => self.param(name, "none"),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn keyed_hash(key: Option<&[u8; 32]>, content: &str) -> String {
+    match key {
+        Some(key) => {
+            let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+            mac.update(content.as_bytes());
+            hex(&mac.finalize().into_bytes())
+        }
+        // Logs written before the chain was keyed; only used to migrate them
+        None => hex(&Sha256::digest(content.as_bytes())),
+    }
+}
+
+fn entry_hash(key: Option<&[u8; 32]>, entry: &AuditEntry) -> String {
+    // Everything but the hash itself, in a fixed order
+    let content = serde_json::to_string(&(
+        entry.seq,
+        entry.timestamp,
+        &entry.actor,
+        &entry.action,
+        &entry.target_key,
+        &entry.target_node,
+        &entry.params,
+        &entry.result,
+        &entry.error,
+        &entry.prev_hash,
+    )).expect("audit entry fields serialize");
+    keyed_hash(key, &content)
+}
+
+/// MAC over where the log starts and ends
+fn anchor(key: &[u8; 32], log: &[AuditEntry]) -> String {
+    let ends = log.first().zip(log.last())
+        .map(|(first, last)| (first.seq, &first.prev_hash, last.seq, &last.hash));
+    let content = serde_json::to_string(&ends).expect("audit anchor fields serialize");
+    keyed_hash(Some(key), &content)
+}
+
+/// Chain a new entry onto the log
+pub fn append(
+    log: &mut Vec<AuditEntry>,
+    log_anchor: &mut Option<String>,
+    key: &[u8; 32],
+    event: AuditEvent,
+    timestamp: i64,
+    error: Option<String>,
+) {
+    let (seq, prev_hash) = match log.last() {
+        Some(last) => (last.seq + 1, last.hash.clone()),
+        None => (0, GENESIS_HASH.to_string()),
+    };
+
+    let mut entry = AuditEntry {
+        seq,
+        timestamp,
+        actor: event.actor,
+        action: event.action,
+        target_key: event.target_key,
+        target_node: event.target_node,
+        params: event.params,
+        result: if error.is_some() { "error" } else { "ok" }.to_string(),
+        error,
+        prev_hash,
+        hash: String::new(),
+    };
+    entry.hash = entry_hash(Some(key), &entry);
+    log.push(entry);
+    *log_anchor = Some(anchor(key, log));
+}
+
+/// Drop the oldest entries so that `keep` remain, counting the entry that
+/// records the pruning. Returns how many were dropped.
+pub fn prune(
+    log: &mut Vec<AuditEntry>,
+    log_anchor: &mut Option<String>,
+    key: &[u8; 32],
+    keep: usize,
+    timestamp: i64,
+) -> usize {
+    let excess = (log.len() + 1).saturating_sub(keep.max(1));
+    if excess == 0 {
+        return 0;
+    }
+
+    let pruned: Vec<AuditEntry> = log.drain(..excess).collect();
+    let last = pruned.last().expect("at least one entry pruned");
+    let event = AuditEvent::new(SYSTEM_ACTOR, "prune_audit_log")
+        .param("pruned", excess)
+        .param("through_seq", last.seq)
+        .param("through_hash", &last.hash)
+        .param("keep", keep);
+    append(log, log_anchor, key, event, timestamp, None);
+    excess
+}
+
+/// Sequence number of the first entry that doesn't chain correctly, if any.
+/// A log whose ends don't match the anchor reports the seq after its last
+/// entry: something was cut off, and nothing from there on can be trusted.
+pub fn first_broken_link(log: &[AuditEntry], log_anchor: Option<&str>, key: &[u8; 32]) -> Option<u64> {
+    let first_seq = log.first().map_or(0, |first| first.seq);
+    let mut expected_prev = log.first().map(|first| first.prev_hash.clone());
+    for (i, entry) in log.iter().enumerate() {
+        if entry.seq != first_seq + i as u64
+            || Some(&entry.prev_hash) != expected_prev.as_ref()
+            || entry.hash != entry_hash(Some(key), entry)
+        {
+            return Some(entry.seq);
+        }
+        expected_prev = Some(entry.hash.clone());
+    }
+
+    let anchored = match log_anchor {
+        Some(log_anchor) => log_anchor == anchor(key, log),
+        None => log.is_empty(),
+    };
+    if !anchored {
+        return Some(log.last().map_or(0, |last| last.seq + 1));
+    }
+    None
+}
+
+/// Re-hash a log chained with plain SHA-256 under the audit key. Refuses, and
+/// returns the first broken seq, if the old chain doesn't verify; re-keying
+/// it would launder whatever broke it.
+pub fn rekey_legacy(log: &mut [AuditEntry], log_anchor: &mut Option<String>, key: &[u8; 32]) -> Result<(), u64> {
+    let mut expected_prev = GENESIS_HASH.to_string();
+    for (i, entry) in log.iter().enumerate() {
+        if entry.seq != i as u64 || entry.prev_hash != expected_prev || entry.hash != entry_hash(None, entry) {
+            return Err(i as u64);
+        }
+        expected_prev = entry.hash.clone();
+    }
+
+    let mut prev_hash = GENESIS_HASH.to_string();
+    for entry in log.iter_mut() {
+        entry.prev_hash = prev_hash;
+        entry.hash = entry_hash(Some(key), entry);
+        prev_hash = entry.hash.clone();
+    }
+    *log_anchor = Some(anchor(key, log));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn log_of(n: usize) -> (Vec<AuditEntry>, Option<String>) {
+        let mut log = Vec::new();
+        let mut log_anchor = None;
+        for i in 0..n {
+            let event = AuditEvent::new("admin.os", "add_api_key").param("i", i);
+            append(&mut log, &mut log_anchor, &KEY, event, i as i64, None);
+        }
+        (log, log_anchor)
+    }
+
+    #[test]
+    fn intact_log_verifies() {
+        let (log, log_anchor) = log_of(5);
+        assert_eq!(first_broken_link(&log, log_anchor.as_deref(), &KEY), None);
+        assert_eq!(first_broken_link(&[], None, &KEY), None);
+    }
+
+    #[test]
+    fn edited_entry_breaks_the_chain() {
+        let (mut log, log_anchor) = log_of(5);
+        log[2].actor = "mallory.os".to_string();
+        assert_eq!(first_broken_link(&log, log_anchor.as_deref(), &KEY), Some(2));
+    }
+
+    #[test]
+    fn dropped_entry_breaks_the_chain() {
+        let (mut log, log_anchor) = log_of(5);
+        log.remove(2);
+        assert_eq!(first_broken_link(&log, log_anchor.as_deref(), &KEY), Some(3));
+    }
+
+    #[test]
+    fn recomputed_chain_without_the_key_is_rejected() {
+        let (mut log, log_anchor) = log_of(3);
+        log[1].actor = "mallory.os".to_string();
+        let mut prev_hash = log[0].hash.clone();
+        for entry in log.iter_mut().skip(1) {
+            entry.prev_hash = prev_hash;
+            entry.hash = entry_hash(None, entry);
+            prev_hash = entry.hash.clone();
+        }
+        assert_eq!(first_broken_link(&log, log_anchor.as_deref(), &KEY), Some(1));
+    }
+
+    #[test]
+    fn truncated_tail_is_detected() {
+        let (mut log, log_anchor) = log_of(5);
+        log.truncate(3);
+        assert_eq!(first_broken_link(&log, log_anchor.as_deref(), &KEY), Some(3));
+        assert_eq!(first_broken_link(&log, None, &KEY), Some(3));
+    }
+
+    #[test]
+    fn append_never_drops_entries() {
+        let (log, _) = log_of(20);
+        assert_eq!(log.len(), 20);
+        assert_eq!(log[0].seq, 0);
+    }
+
+    #[test]
+    fn pruning_is_recorded_and_keeps_the_log_valid() {
+        let (mut log, mut log_anchor) = log_of(10);
+        let through_hash = log[6].hash.clone();
+
+        assert_eq!(prune(&mut log, &mut log_anchor, &KEY, 4, 100), 7);
+        assert_eq!(log.len(), 4);
+        assert_eq!(log[0].seq, 7);
+        assert_eq!(first_broken_link(&log, log_anchor.as_deref(), &KEY), None);
+
+        let record = log.last().unwrap();
+        assert_eq!((record.actor.as_str(), record.action.as_str()), (SYSTEM_ACTOR, "prune_audit_log"));
+        assert!(record.params.contains(&("through_seq".to_string(), "6".to_string())));
+        assert!(record.params.contains(&("through_hash".to_string(), through_hash)));
+
+        assert_eq!(prune(&mut log, &mut log_anchor, &KEY, 5, 200), 0);
+        assert_eq!(log.len(), 4);
+    }
+
+    #[test]
+    fn legacy_log_is_rekeyed_only_if_intact() {
+        let mut legacy = Vec::new();
+        let mut prev_hash = GENESIS_HASH.to_string();
+        for seq in 0..3 {
+            let mut entry = AuditEntry {
+                seq,
+                timestamp: seq as i64,
+                actor: "admin.os".to_string(),
+                action: "add_api_key".to_string(),
+                target_key: None,
+                target_node: None,
+                params: Vec::new(),
+                result: "ok".to_string(),
+                error: None,
+                prev_hash,
+                hash: String::new(),
+            };
+            entry.hash = entry_hash(None, &entry);
+            prev_hash = entry.hash.clone();
+            legacy.push(entry);
+        }
+
+        let mut tampered = legacy.clone();
+        tampered[1].action = "remove_api_key".to_string();
+        let mut log_anchor = None;
+        assert_eq!(rekey_legacy(&mut tampered, &mut log_anchor, &KEY), Err(1));
+        assert_eq!(log_anchor, None);
+
+        assert_eq!(rekey_legacy(&mut legacy, &mut log_anchor, &KEY), Ok(()));
+        assert_eq!(first_broken_link(&legacy, log_anchor.as_deref(), &KEY), None);
+    }
+}
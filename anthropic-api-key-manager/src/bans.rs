@@ -0,0 +1,85 @@
+//! Matching nodes against the admin ban list.
+//!
+//! A ban pattern is either an exact node id or a glob where `*` stands for any
+//! run of characters, so `*.spam.os` covers every node under that namespace.
+
+use crate::NodeBan;
+
+pub fn pattern_matches(pattern: &str, node_id: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == node_id;
+    }
+
+    // Anchor the first and last pieces, then find the middle ones in order
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if node_id.len() < first.len() + last.len() || !node_id.starts_with(first) || !node_id.ends_with(last) {
+        return false;
+    }
+    let mut rest = &node_id[first.len()..node_id.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// The first unexpired ban covering the node
+pub fn active_ban<'a>(bans: &'a [NodeBan], node_id: &str, now: i64) -> Option<&'a NodeBan> {
+    bans.iter().find(|ban| {
+        ban.expires_at.map_or(true, |at| at > now) && pattern_matches(&ban.pattern, node_id)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ban(pattern: &str, expires_at: Option<i64>) -> NodeBan {
+        NodeBan {
+            ban_id: format!("ban_{}", pattern),
+            pattern: pattern.to_string(),
+            reason: "test".to_string(),
+            banned_by: "admin.os".to_string(),
+            banned_at: 0,
+            expires_at,
+        }
+    }
+
+    #[test]
+    fn exact_patterns_match_only_that_node() {
+        assert!(pattern_matches("alice.os", "alice.os"));
+        assert!(!pattern_matches("alice.os", "malice.os"));
+        assert!(!pattern_matches("alice.os", "alice.os.evil"));
+    }
+
+    #[test]
+    fn namespace_globs() {
+        assert!(pattern_matches("*.spam.os", "bot7.spam.os"));
+        assert!(pattern_matches("*.spam.os", "a.b.spam.os"));
+        assert!(!pattern_matches("*.spam.os", "spam.os"));
+        assert!(!pattern_matches("*.spam.os", "bot7.spam.os.evil"));
+        assert!(pattern_matches("*", "anything.os"));
+    }
+
+    #[test]
+    fn globs_match_pieces_in_order_without_overlap() {
+        assert!(pattern_matches("bot*.spam*.os", "bot12.spam-farm.os"));
+        assert!(pattern_matches("a*b*c", "abc"));
+        assert!(!pattern_matches("a*b*c", "acb"));
+        // The anchored ends can't share characters
+        assert!(!pattern_matches("ab*ba", "aba"));
+        assert!(!pattern_matches("a*b*a", "ab"));
+    }
+
+    #[test]
+    fn expired_bans_are_ignored() {
+        let bans = [ban("*.spam.os", Some(100)), ban("bot7.spam.os", None)];
+        assert_eq!(active_ban(&bans, "bot1.spam.os", 50).map(|b| b.ban_id.as_str()), Some("ban_*.spam.os"));
+        assert!(active_ban(&bans, "bot1.spam.os", 100).is_none());
+        assert_eq!(active_ban(&bans, "bot7.spam.os", 100).map(|b| b.ban_id.as_str()), Some("ban_bot7.spam.os"));
+        assert!(active_ban(&bans, "alice.os", 50).is_none());
+    }
+}
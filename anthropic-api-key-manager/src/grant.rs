@@ -0,0 +1,82 @@
+//! The typed key grant protocol spoken by `request_grant`, and how refusals
+//! map back onto the plain string errors of the older endpoints.
+
+use crate::rate_limit;
+use crate::GrantStatus;
+
+// Version of the KeyGrant/GrantReq shapes. Bump on breaking changes; clients
+// compare it against the version they were built for.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// How a grant ends when its key is retired and the node is given another
+pub const REVOKED: &str = "revoked";
+
+// How a grant ends when the node stops renewing its lease
+pub const LEASE_EXPIRED: &str = "lease_expired";
+
+// How a grant ends when the node gives its key back
+pub const RELINQUISHED: &str = "relinquished";
+
+// How a grant ends when its key is rotated out and the node moves to the replacement
+pub const ROTATED: &str = "rotated";
+
+// How a grant ends when the node is banned
+pub const BANNED: &str = "banned";
+
+pub fn new_grant_id() -> String {
+    format!("grant_{:016x}", rand::random::<u64>())
+}
+
+/// Why a node didn't get a usable key
+pub struct Refusal {
+    pub status: GrantStatus,
+    pub message: String,
+    pub limit: Option<String>,           // Which rate limit refused the request
+    pub retry_after_secs: Option<i64>,
+    pub waitlist_position: Option<u64>,  // 1-based
+}
+
+impl Refusal {
+    pub fn new(status: GrantStatus, message: impl Into<String>) -> Self {
+        Refusal {
+            status,
+            message: message.into(),
+            limit: None,
+            retry_after_secs: None,
+            waitlist_position: None,
+        }
+    }
+
+    pub fn from_denial(denial: rate_limit::Denial) -> Self {
+        let status = if denial.code == rate_limit::ISSUANCE_PAUSED {
+            GrantStatus::IssuancePaused
+        } else {
+            GrantStatus::RateLimited
+        };
+        Refusal {
+            status,
+            message: denial.message,
+            limit: Some(denial.limit),
+            retry_after_secs: denial.retry_after_secs,
+            waitlist_position: None,
+        }
+    }
+
+    /// The error string `request_api_key` and `redeem_invite` have always
+    /// returned: rate-limit denials as JSON, everything else as plain text
+    pub fn legacy_error(self) -> String {
+        match self.status {
+            GrantStatus::RateLimited | GrantStatus::IssuancePaused => rate_limit::Denial {
+                code: if self.status == GrantStatus::IssuancePaused {
+                    rate_limit::ISSUANCE_PAUSED
+                } else {
+                    rate_limit::RATE_LIMITED
+                }.to_string(),
+                limit: self.limit.unwrap_or_default(),
+                retry_after_secs: self.retry_after_secs,
+                message: self.message,
+            }.to_error(),
+            _ => self.message,
+        }
+    }
+}
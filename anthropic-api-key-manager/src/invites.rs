@@ -0,0 +1,120 @@
+//! Invite codes that gate key issuance to new nodes.
+
+use rand::Rng;
+
+use crate::InviteCode;
+
+// No 0/O or 1/I/L, so codes survive being read aloud or retyped
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+const CODE_GROUPS: usize = 3;
+const CODE_GROUP_LEN: usize = 4;
+
+// Most codes minted in one batch
+pub const MAX_BATCH: u32 = 1000;
+
+/// A code like `K7QM-3XPA-ZR9D`
+pub fn generate_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..CODE_GROUPS)
+        .map(|_| {
+            (0..CODE_GROUP_LEN)
+                .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Codes are matched case-insensitively and without separators or spaces
+pub fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+/// "active", "used_up", "expired" or "revoked"
+pub fn status(invite: &InviteCode, now: i64) -> &'static str {
+    if invite.revoked_at.is_some() {
+        "revoked"
+    } else if invite.expires_at.map_or(false, |at| at <= now) {
+        "expired"
+    } else if invite.redemptions.len() as u32 >= invite.max_uses {
+        "used_up"
+    } else {
+        "active"
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// One row per code, for handing out codes outside the UI
+pub fn to_csv(invites: &[&InviteCode], now: i64) -> String {
+    let mut csv = String::from("code,batch_id,campaign,max_uses,uses,status,expires_at,redeemed_by\n");
+    for invite in invites {
+        let redeemed_by: Vec<&str> = invite.redemptions.iter().map(|r| r.node_id.as_str()).collect();
+        csv.push_str(&[
+            csv_field(&invite.code),
+            csv_field(&invite.batch_id),
+            csv_field(invite.campaign.as_deref().unwrap_or("")),
+            invite.max_uses.to_string(),
+            invite.redemptions.len().to_string(),
+            status(invite, now).to_string(),
+            invite.expires_at.map(|at| at.to_string()).unwrap_or_default(),
+            csv_field(&redeemed_by.join(" ")),
+        ].join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InviteRedemption;
+
+    fn invite(max_uses: u32, uses: usize, expires_at: Option<i64>, revoked_at: Option<i64>) -> InviteCode {
+        InviteCode {
+            code: "K7QM-3XPA-ZR9D".to_string(),
+            batch_id: "batch_1".to_string(),
+            campaign: None,
+            max_uses,
+            created_at: 0,
+            expires_at,
+            revoked_at,
+            redemptions: (0..uses)
+                .map(|i| InviteRedemption { node_id: format!("n{}.os", i), redeemed_at: 0, waitlisted: false })
+                .collect(),
+            status: String::new(),
+        }
+    }
+
+    #[test]
+    fn status_of_codes() {
+        assert_eq!(status(&invite(1, 0, None, None), 50), "active");
+        assert_eq!(status(&invite(2, 1, Some(100), None), 50), "active");
+        assert_eq!(status(&invite(1, 1, None, None), 50), "used_up");
+        assert_eq!(status(&invite(1, 0, Some(100), None), 100), "expired");
+    }
+
+    #[test]
+    fn revocation_outranks_expiry_and_use() {
+        assert_eq!(status(&invite(1, 1, Some(10), Some(5)), 50), "revoked");
+        assert_eq!(status(&invite(1, 1, Some(10), None), 50), "expired");
+    }
+
+    #[test]
+    fn generated_codes_normalize_to_their_letters() {
+        let code = generate_code();
+        assert_eq!(code.len(), CODE_GROUPS * (CODE_GROUP_LEN + 1) - 1);
+        assert_eq!(normalize(&code).len(), CODE_GROUPS * CODE_GROUP_LEN);
+        assert!(normalize(&code).bytes().all(|b| CODE_ALPHABET.contains(&b)));
+        assert_eq!(normalize(" k7qm-3xpa zr9d "), "K7QM3XPAZR9D");
+    }
+}
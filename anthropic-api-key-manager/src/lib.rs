@@ -2,40 +2,146 @@ use hyperware_process_lib::{
     our,
     println,
     homepage::add_to_homepage,
-    http::client::send_request_await_response,
     hyperapp::{source, SaveOptions, spawn, sleep},
-    timer::set_timer,
+    Address,
+    Request,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use rand::seq::SliceRandom;
+use std::collections::{HashMap, HashSet, VecDeque};
 use chrono::Utc;
-use base64::Engine as _;
-use base64::engine::general_purpose::STANDARD as BASE64;
-use url::Url;
+use sha2::{Digest, Sha256};
 
+mod anthropic;
+use anthropic::{AdminClient, AnthropicApiKey, AnthropicCostReport};
+
+mod scheduler;
+use scheduler::{JobResult, ScheduledJob};
+
+mod sealed;
+
+mod audit;
+use audit::AuditEvent;
+
+mod pool_health;
+use pool_health::KeyLoad;
+
+mod selection;
+use selection::Candidate;
+
+mod push;
+use push::KeyPush;
+
+mod bans;
+
+mod rate_limit;
+
+mod invites;
+
+mod grant;
+use grant::Refusal;
+
+// Bump when stored cost records can't be upserted against fresh reports
+const COST_SCHEMA_VERSION: u32 = 1;
+
+// How long a rotated-out key keeps working upstream unless the policy says otherwise
+const DEFAULT_ROTATION_GRACE_SECS: i64 = 24 * 3600;
+
+// Secrets (the admin key and anything holding a pooled key, including map keys)
+// are sealed with a node-local key when state is persisted; see `sealed`
 #[derive(Default, Serialize, Deserialize)]
 pub struct AnthropicApiKeyManagerState {
-    #[serde(default)]
+    #[serde(default, with = "sealed::option")]
     admin_api_key: Option<String>,
-    #[serde(default)]
+    #[serde(default, with = "sealed::set")]
     active_keys: HashSet<String>,
-    #[serde(default)]
+    #[serde(default, with = "sealed::set")]
     historical_keys: HashSet<String>,
-    #[serde(default)]
+    #[serde(default, with = "sealed::map_keys")]
     key_to_nodes: HashMap<String, Vec<String>>,
     #[serde(default)]
-    node_issue_times: HashMap<String, i64>,
+    node_issue_times: HashMap<String, i64>,  // Superseded by node_grants; folded into it at startup
     #[serde(default)]
+    node_selection: HashMap<String, SelectionStrategyKind>,  // Strategy that picked each node's key
+    #[serde(default, with = "sealed::map_keys")]
     key_costs: HashMap<String, Vec<CostRecord>>,
+    #[serde(default, with = "sealed::map_keys")]
+    key_metadata: HashMap<String, KeyMetadata>,  // Per-key bookkeeping (workspace mapping, ...)
     #[serde(default)]
     all_costs: Vec<CostRecord>,  // Store all costs globally
     #[serde(default)]
     last_cost_check: Option<i64>,
     #[serde(default)]
-    last_cost_query_date: Option<String>,  // Store the last date we queried up to (RFC3339 format)
+    last_cost_query_date: Option<String>,  // Start of the latest bucket we have seen (RFC3339 format)
+    #[serde(default)]
+    cost_schema_version: u32,
+    #[serde(default)]
+    secrets_sealed: bool,  // False until state written before encryption at rest has been re-saved
+    #[serde(default)]
+    sealing_version: u32,  // Sealed format the state was last saved with; see `sealed::SEALING_VERSION`
+    #[serde(default)]
+    key_reveals: Vec<KeyReveal>,  // Who revealed which full key, and when
+    #[serde(default)]
+    audit_log: Vec<AuditEntry>,   // Append-only and hash-chained; see `audit`
+    #[serde(default)]
+    audit_anchor: Option<String>, // MAC over the log's first and last links
+    #[serde(default)]
+    audit_retention: Option<u64>, // Entries the audit_prune job keeps; None keeps everything
+    #[serde(default)]
+    last_reconciliation: Option<ReconcileReport>,
+    #[serde(default)]
+    pool_thresholds: PoolThresholds,
+    #[serde(default)]
+    alert_webhook_url: Option<String>,  // Alerts are POSTed here as JSON when raised
+    #[serde(default)]
+    pool_alerts: Vec<PoolAlert>,        // Open alerts and recently resolved ones
+    #[serde(default)]
+    selection_strategy: SelectionStrategyKind,
+    #[serde(default)]
+    selection_cursor: u64,              // Round-robin position
+    #[serde(default)]
+    waitlist: VecDeque<WaitlistEntry>,  // Nodes refused a key, served first-come first-served
+    #[serde(default)]
+    key_deliveries: HashMap<String, KeyDelivery>,  // Latest key push to each node
     #[serde(default)]
-    ui_auth_token: Option<String>,
+    node_addresses: HashMap<String, String>,  // Address each node last requested a key from
+    #[serde(default)]
+    rotation_policy: RotationPolicy,
+    #[serde(default)]
+    rotations: Vec<KeyRotation>,
+    #[serde(default)]
+    bans: Vec<NodeBan>,
+    #[serde(default)]
+    rate_limits: RateLimits,
+    #[serde(default)]
+    recent_issuances: VecDeque<Issuance>,       // New-node issuances over the last day
+    #[serde(default)]
+    recent_denials: VecDeque<RateLimitDenial>,  // Rate-limited requests over the last day
+    #[serde(default)]
+    issuance_paused_until: Option<i64>,         // Set by the burst detector
+    #[serde(default)]
+    burst_counted_from: i64,                    // Issuances before this don't count toward a burst
+    #[serde(default)]
+    invite_codes: Vec<InviteCode>,
+    #[serde(default)]
+    invite_required: bool,  // New nodes must redeem an invite code to get a key
+    #[serde(default)]
+    node_grants: HashMap<String, NodeGrant>,  // Current grant of each node holding a key
+    #[serde(default)]
+    grant_history: HashMap<String, Vec<NodeGrant>>,  // Ended grants of each node, oldest first
+    #[serde(default)]
+    reissue_policy: ReissuePolicy,
+    #[serde(default)]
+    lease_policy: LeasePolicy,
+    #[serde(default)]
+    pool_mode: PoolMode,
+    #[serde(default)]
+    default_key_ttl_secs: Option<i64>,  // Lifetime applied to keys added without an explicit TTL
+    #[serde(default)]
+    default_spend_cap: Option<f64>,     // Dollar cap for keys without their own cap
+    #[serde(default)]
+    default_max_nodes: Option<u64>,     // Node limit for keys without their own limit
+    #[serde(default)]
+    scheduled_jobs: HashMap<String, ScheduledJob>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -44,30 +150,160 @@ struct CostRecord {
     amount: f64,        // Amount in dollars (converted from API's cents)
     currency: String,
     description: String,
+    #[serde(default)]
+    workspace_id: Option<String>,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    token_type: Option<String>,
+}
+
+impl CostRecord {
+    /// Identity of the report line this record came from. A bucket that is
+    /// re-reported replaces the record with the same key instead of adding one.
+    fn bucket_key(&self) -> String {
+        format!("{}|{}|{}|{}|{}",
+            self.timestamp,
+            self.workspace_id.as_deref().unwrap_or(""),
+            self.description,
+            self.model.as_deref().unwrap_or(""),
+            self.token_type.as_deref().unwrap_or(""))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+struct KeyMetadata {
+    // Anthropic workspace the key was created in; costs reported for this
+    // workspace are attributed to the key
+    #[serde(default)]
+    workspace_id: Option<String>,
+    #[serde(default)]
+    added_at: i64,
+    #[serde(default)]
+    expires_at: Option<i64>,
+    // Anthropic's id for the key (`apikey_...`), resolved lazily from the key hint
+    #[serde(default)]
+    anthropic_key_id: Option<String>,
+    #[serde(default)]
+    retired_at: Option<i64>,
+    #[serde(default)]
+    retired_reason: Option<String>,
+    #[serde(default)]
+    deactivated_upstream: bool,
+    #[serde(default)]
+    spend_cap: Option<f64>,  // Dollars; falls back to the default cap when unset
+    #[serde(default)]
+    weight: Option<u32>,     // Share of new nodes under the weighted strategy
+    #[serde(default)]
+    max_nodes: Option<u64>,  // Falls back to the default node limit when unset
+    // Rotated-out keys stay live upstream until this time so nodes can switch over
+    #[serde(default)]
+    deactivate_after: Option<i64>,
+    #[serde(default)]
+    upstream_name: Option<String>,  // Name we last gave the key upstream, in dedicated mode
+}
+
+// How request_api_key picks a key for a new node; see `selection`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+enum SelectionStrategyKind {
+    Random,
+    #[default]
+    LeastAssigned,
+    RoundRobin,
+    LowestRecentSpend,
+    Weighted,
+}
+
+// Whether nodes share keys, or each key goes to exactly one node. In
+// Dedicated mode unassigned keys wait in reserve and are named upstream after
+// their node, so per-node cost is just the key's cost.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+enum PoolMode {
+    #[default]
+    Shared,
+    Dedicated,
 }
 
+// Listings never carry full secrets: keys are referred to by `key_id`, a
+// stable fingerprint, and shown as a masked `key_hint`
 #[derive(Debug, Serialize, Deserialize, Clone)]
 struct ApiKeyInfo {
-    key: String,
+    key_id: String,
+    key_hint: String,
     status: String,
     total_cost: f64,
     assigned_nodes: Vec<String>,
     created_at: i64,
+    expires_at: Option<i64>,
+    spend_cap: Option<f64>,
+    workspace_id: Option<String>,
+    weight: u32,
+    max_nodes: Option<u64>,  // With assigned_nodes, the key's fill level
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct AddKeyReq {
     api_key: String,
+    workspace_id: Option<String>,  // If not given, looked up from the organization's keys (requires admin key)
+    ttl_seconds: Option<i64>,      // If not given, the default TTL (if any) applies
+    spend_cap: Option<f64>,        // If not given, the default spend cap (if any) applies
+    max_nodes: Option<u64>,        // If not given, the default node limit (if any) applies
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SetKeyTtlReq {
+    key_id: Option<String>,    // If not given, sets the default TTL for newly added keys
+    ttl_seconds: Option<i64>,  // None disables expiry
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SetSpendCapReq {
+    key_id: Option<String>,   // If not given, sets the default cap for keys without their own
+    spend_cap: Option<f64>,   // Dollars; None removes the cap
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SetKeyWorkspaceReq {
+    key_id: String,
+    workspace_id: Option<String>,  // If not given, looked up from the organization's keys
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SetMaxNodesReq {
+    key_id: Option<String>,   // If not given, sets the default limit for keys without their own
+    max_nodes: Option<u64>,   // None removes the limit
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SetKeyWeightReq {
+    key_id: String,
+    weight: Option<u32>,  // None restores the default weight
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SetSelectionStrategyReq {
+    strategy: SelectionStrategyKind,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SetPoolModeReq {
+    mode: PoolMode,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct RemoveKeyReq {
-    api_key: String,
+    key_id: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct KeyStatusReq {
-    api_key: String,
+    key_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RevealKeyReq {
+    key_id: String,
+    reason: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -78,7 +314,7 @@ struct CostRangeReq {
 
 #[derive(Debug, Serialize, Deserialize)]
 struct KeyCostReq {
-    api_key: String,
+    key_id: String,
     start_date: Option<String>,
     end_date: Option<String>,
 }
@@ -88,207 +324,898 @@ struct SetAdminKeyParams {
     admin_key: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct JobReq {
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UpdateJobReq {
+    name: String,
+    interval_secs: Option<u64>,
+    jitter_secs: Option<u64>,
+    enabled: Option<bool>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct NodeAssignment {
     node_id: String,
-    api_key: String,
+    key_id: String,
+    key_hint: String,
     issued_at: i64,
+    strategy: Option<SelectionStrategyKind>,  // None for nodes assigned before strategies were recorded
+    cost: Option<f64>,  // Spend since issue when the node has the key to itself; None on shared keys
+    lease_expires_at: Option<i64>,  // None for permanent grants
+    expired: bool,      // The node let its lease run out and no longer holds the key
 }
 
-// Response types
-#[derive(Debug, Serialize, Deserialize)]
-struct SuccessRes {
-    success: bool,
-    message: String,
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct KeyReveal {
+    key_id: String,
+    key_hint: String,
+    actor: String,
+    reason: Option<String>,
+    revealed_at: i64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct AdminKeyStatusRes {
-    has_admin_key: bool,
-    key_prefix: Option<String>,
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct AuditEntry {
+    seq: u64,
+    timestamp: i64,
+    actor: String,                  // Node that made the change, or "system"
+    action: String,
+    target_key: Option<String>,     // key_id
+    target_node: Option<String>,
+    params: Vec<(String, String)>,  // Secrets are never recorded
+    result: String,                 // "ok" or "error"
+    error: Option<String>,
+    prev_hash: String,
+    hash: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct AuthRes {
-    token: String,
-    has_admin_key: bool,
+struct AuditLogReq {
+    offset: Option<u64>,
+    limit: Option<u64>,
+    action: Option<String>,
+    actor: Option<String>,
+    key_id: Option<String>,
+    node_id: Option<String>,
+    since: Option<i64>,
+    until: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct KeyStatusRes {
-    status: String,
-    assigned_nodes: Vec<String>,
-    total_cost: f64,
+struct SetAuditRetentionReq {
+    max_entries: Option<u64>,  // None keeps the whole log
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct TotalCostsRes {
-    total_cost: f64,
-    cost_by_key: Vec<(String, f64)>,  // Changed from HashMap to Vec of tuples for TypeScript compatibility
-    currency: String,
+// One difference between the pool and the Anthropic organization. `kind` is one of
+//   "inactive_upstream"   pooled key that is inactive or archived upstream (fix: retire it)
+//   "missing_upstream"    pooled key not found in the organization (fix: retire it)
+//   "active_but_retired"  retired key still active upstream (fix: deactivate upstream)
+//   "workspace_mismatch"  pooled key mapped to a different workspace (fix: take upstream's)
+//   "unpooled_upstream"   active org key the pool doesn't know (fix: deactivate upstream;
+//                         only applied when asked for by id, since it may be in use elsewhere)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct DriftItem {
+    id: String,
+    kind: String,
+    key_id: Option<String>,
+    key_hint: Option<String>,
+    anthropic_key_id: Option<String>,
+    name: Option<String>,
+    upstream_status: Option<String>,
+    workspace_id: Option<String>,  // Upstream's workspace for the key
+    fix: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct KeyCostsRes {
-    api_key: String,
-    costs: Vec<CostRecord>,
-    total: f64,
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct ReconcileReport {
+    generated_at: i64,
+    upstream_keys: u64,
+    matched_keys: u64,
+    drift: Vec<DriftItem>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct CostsRefreshRes {
-    success: bool,
+// Thresholds are off when unset; an empty pool always raises an alert
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct PoolThresholds {
+    min_active_keys: Option<u64>,
+    max_nodes_per_key: Option<u64>,
+    min_spend_headroom: Option<f64>,  // Dollars left across capped keys
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct PoolAlert {
+    kind: String,
     message: String,
-    timestamp: i64,
+    raised_at: i64,
+    resolved_at: Option<i64>,
+    notified: bool,
 }
 
-// Anthropic API structures
-#[derive(Serialize, Deserialize, Debug)]
-struct AnthropicCostReport {
-    data: Vec<CostReportData>,
-    has_more: bool,
-    next_page: Option<String>,
+#[derive(Debug, Serialize, Deserialize)]
+struct SetPoolThresholdsReq {
+    thresholds: PoolThresholds,
+    alert_webhook_url: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct CostReportData {
-    starting_at: String,
-    ending_at: String,
-    results: Vec<CostReportResult>,
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct WaitlistEntry {
+    node_id: String,
+    address: String,    // Where to push the key once one is assigned
+    requested_at: i64,
+    reason: String,     // Why no key could be issued at the time
+    #[serde(default)]
+    invite_code: Option<String>,  // Redeemed once the node is served
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct CostReportResult {
-    currency: String,              // Always "USD"
-    amount: String,                // Amount in cents as decimal string (e.g., "123.45" = $1.2345)
-    workspace_id: Option<String>,
-    description: Option<String>,   // Made optional since it can be null when not grouping by description
-    cost_type: Option<String>,     // Made optional
-    context_window: Option<String>,
-    model: Option<String>,
-    service_tier: Option<String>,
-    token_type: Option<String>,
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct KeyDelivery {
+    node_id: String,
+    push_id: String,
+    key_id: String,
+    reason: String,
+    sent_at: i64,
+    status: String,  // "pending", "delivered" or "failed"
+    delivered_at: Option<i64>,
+    error: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct AnthropicWorkspace {
-    id: String,
-    name: String,
-    created_at: String,
-    archived_at: Option<String>,
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyDeliveryReport {
+    node_id: String,
+    push_id: String,
+    error: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct AnthropicApiKey {
-    id: String,
-    name: String,
-    status: String,
-    created_at: String,
-    workspace_id: String,
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct RotationPolicy {
+    grace_secs: Option<i64>,        // Defaults to a day
+    max_key_age_secs: Option<i64>,  // Keys older than this are rotated on schedule; None disables
 }
 
-#[hyperapp_macro::hyperapp(
-    name = "Anthropic API Key Manager",
-    ui = Some(HttpBindingConfig::default()),
-    endpoints = vec![
-        Binding::Http {
-            path: "/api",
-            config: HttpBindingConfig::new(false, false, false, None)
-        },
-        Binding::Ws {
-            path: "/ws",
-            config: WsBindingConfig::default()
-        }
-    ],
-    save_config = SaveOptions::OnDiff,
-    wit_world = "anthropic-api-key-manager-v0"
-)]
-impl AnthropicApiKeyManagerState {
-    #[init]
-    async fn initialize(&mut self) {
-        add_to_homepage("API Key Manager", None, Some("/"), None);
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct RotationMove {
+    node_id: String,
+    new_key_id: Option<String>,   // None if the node was waitlisted instead
+    push_id: Option<String>,
+    delivery_status: String,      // "pending", "delivered", "failed" or "waitlisted"
+    error: Option<String>,
+}
 
-        if self.ui_auth_token.is_none() {
-            let token = BASE64.encode(format!("{:x}", rand::random::<u128>()));
-            self.ui_auth_token = Some(token.clone());
-            println!("Generated UI auth token: {}", token);
-        }
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct KeyRotation {
+    rotation_id: String,
+    old_key_id: String,
+    old_key_hint: String,
+    trigger: String,              // "manual", "scheduled" or "ban"
+    started_at: i64,
+    deactivate_after: i64,
+    completed_at: Option<i64>,    // When the old key was deactivated upstream
+    moves: Vec<RotationMove>,
+}
 
-        // Clone admin_api_key for the spawn task (if it exists)
-        let admin_key = self.admin_api_key.clone();
+#[derive(Debug, Serialize, Deserialize)]
+struct RotateKeyReq {
+    key_id: String,
+    grace_secs: Option<i64>,  // 0 cuts the old key off immediately
+}
 
-        // Spawn a task to periodically refresh costs
-        spawn(async move {
-            loop {
-                // Wait 1 hour between cost refreshes
-                let _ = sleep(3600000).await;
-
-                // Only attempt to refresh if we have an admin key
-                if admin_key.is_some() {
-                    println!("Periodic cost refresh triggered");
-                    // Note: In the spawned task we can't directly call methods on self
-                    // The timer handler will still work for now as a fallback
-                }
-            }
-        });
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct NodeBan {
+    ban_id: String,
+    pattern: String,           // Exact node id, or a glob like "*.spam.os"; see `bans`
+    reason: String,
+    banned_by: String,
+    banned_at: i64,
+    expires_at: Option<i64>,   // None bans for good
+}
 
-        println!("Anthropic API Key Manager initialized on node: {}", our().node);
-    }
+#[derive(Debug, Serialize, Deserialize)]
+struct BanNodeReq {
+    pattern: String,
+    reason: String,
+    expires_in_secs: Option<i64>,
+    rotate_keys: bool,  // Also rotate the keys the banned nodes held, cutting them off upstream
+}
 
-    #[remote]
-    async fn request_api_key(&mut self) -> Result<String, String> {
-        let node_id = source().node;
+#[derive(Debug, Serialize, Deserialize)]
+struct BanNodeRes {
+    ban: NodeBan,
+    revoked_nodes: Vec<String>,
+    rotations: Vec<KeyRotation>,
+}
 
-        if let Some(existing_key) = self.find_key_for_node(&node_id) {
-            return Ok(existing_key);
-        }
+#[derive(Debug, Serialize, Deserialize)]
+struct UnbanNodeReq {
+    ban_id: String,
+}
 
-        if self.active_keys.is_empty() {
-            return Err("No active API keys available".to_string());
-        }
+// Limits on issuing keys to new nodes; None turns a limit off. See `rate_limit`
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct RateLimits {
+    global_per_hour: Option<u64>,
+    global_per_day: Option<u64>,
+    namespace_per_hour: Option<u64>,  // Per parent namespace of the requesting node
+    namespace_per_day: Option<u64>,
+    top_level_per_hour: Option<u64>,  // Names directly under e.g. .os; the namespace limits apply if unset
+    top_level_per_day: Option<u64>,
+    burst_max: Option<u64>,           // This many issuances within burst_window_secs pauses issuance
+    burst_window_secs: Option<i64>,
+    burst_pause_secs: Option<i64>,    // Defaults to an hour
+}
 
-        let keys: Vec<String> = self.active_keys.iter().cloned().collect();
-        let selected_key = keys
-            .choose(&mut rand::thread_rng())
-            .ok_or("Failed to select random key")?
-            .clone();
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Issuance {
+    at: i64,
+    node_id: String,
+    namespace: String,
+}
 
-        self.key_to_nodes
-            .entry(selected_key.clone())
-            .or_insert_with(Vec::new)
-            .push(node_id.clone());
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct RateLimitDenial {
+    at: i64,
+    node_id: String,
+    limit: String,
+}
 
-        self.node_issue_times.insert(node_id, Utc::now().timestamp());
+#[derive(Debug, Serialize, Deserialize)]
+struct RateLimitStatus {
+    limits: RateLimits,
+    issued_last_hour: u64,
+    issued_last_day: u64,
+    paused_until: Option<i64>,
+    namespaces: Vec<(String, u64)>,       // Issuances per namespace over the last day, busiest first
+    denials_last_day: Vec<(String, u64)>, // Denials per limit over the last day
+}
 
-        Ok(selected_key)
-    }
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct InviteRedemption {
+    node_id: String,
+    redeemed_at: i64,
+    waitlisted: bool,  // The pool was full; the key came off the waitlist later
+}
 
-    #[http]
-    async fn add_api_key(&mut self, request: AddKeyReq) -> Result<SuccessRes, String> {
-        if self.active_keys.contains(&request.api_key) {
-            return Err("API key already exists".to_string());
-        }
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct InviteCode {
+    code: String,
+    batch_id: String,
+    campaign: Option<String>,
+    max_uses: u32,
+    created_at: i64,
+    expires_at: Option<i64>,
+    revoked_at: Option<i64>,
+    redemptions: Vec<InviteRedemption>,
+    status: String,  // Filled in when listed; see `invites::status`
+}
 
-        self.active_keys.insert(request.api_key.clone());
+#[derive(Debug, Serialize, Deserialize)]
+struct MintInvitesReq {
+    count: u32,
+    max_uses: Option<u32>,  // Defaults to single-use
+    expires_in_secs: Option<i64>,
+    campaign: Option<String>,
+}
 
-        Ok(SuccessRes {
-            success: true,
-            message: "API key added successfully".to_string(),
-        })
-    }
+#[derive(Debug, Serialize, Deserialize)]
+struct ListInvitesReq {
+    batch_id: Option<String>,
+    campaign: Option<String>,
+    status: Option<String>,
+}
 
-    #[http]
-    async fn remove_api_key(&mut self, request: RemoveKeyReq) -> Result<SuccessRes, String> {
-        if !self.active_keys.contains(&request.api_key) {
-            return Err("API key not found".to_string());
-        }
+#[derive(Debug, Serialize, Deserialize)]
+struct RevokeInvitesReq {
+    codes: Option<Vec<String>>,
+    batch_id: Option<String>,
+}
 
-        self.active_keys.remove(&request.api_key);
-        self.historical_keys.insert(request.api_key.clone());
+#[derive(Debug, Serialize, Deserialize)]
+struct SetInviteRequiredReq {
+    required: bool,
+}
+
+// Outcome of a key request under the grant protocol; see `grant`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+enum GrantStatus {
+    Active,
+    Waitlisted,
+    KeyRetired,      // The node's key was expired, rotated out or revoked
+    Banned,
+    RateLimited,
+    IssuancePaused,
+    InviteRequired,
+    InviteInvalid,
+    NotGranted,      // Only from get_my_grant: the node holds no key and isn't waiting for one
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GrantReq {
+    protocol_version: u32,           // Version the client was built against
+    app_name: String,
+    app_version: String,
+    invite_code: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct KeyGrant {
+    protocol_version: u32,
+    status: GrantStatus,
+    grant_id: Option<String>,        // Set whenever the node holds a key, usable or not
+    api_key: Option<String>,         // Only when Active
+    key_id: Option<String>,
+    issued_at: Option<i64>,
+    expires_at: Option<i64>,
+    spend_cap: Option<f64>,
+    message: Option<String>,         // Why the node has no usable key
+    retry_after_secs: Option<i64>,
+    waitlist_position: Option<u64>,  // 1-based
+    #[serde(default)]
+    lease_expires_at: Option<i64>,   // Renew with renew_lease before then; None if the grant is permanent
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct NodeSpendRes {
+    key_id: String,
+    window_secs: i64,
+    recent_spend: f64,               // Spend on the key over the window
+    total_spend: f64,
+    spend_cap: Option<f64>,
+    remaining_allowance: Option<f64>,  // Cap minus total spend; None if uncapped
+    shared_with: u64,                // Other nodes on the same key, whose spend is included
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct NodeGrant {
+    grant_id: String,
+    key_id: String,
+    issued_at: i64,
+    app_name: Option<String>,        // As reported by request_grant
+    app_version: Option<String>,
+    #[serde(default)]
+    reissue: bool,                   // Replaced a key revoked out from under the node
+    #[serde(default)]
+    ended_at: Option<i64>,
+    #[serde(default)]
+    end_reason: Option<String>,      // "relinquished", "banned", "rotated", "revoked" or "lease_expired"
+    #[serde(default)]
+    lease_expires_at: Option<i64>,
+    #[serde(default)]
+    renewed_at: Option<i64>,
+}
+
+// Grants are leases the node has to renew, or permanent when `lease_secs` is None
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct LeasePolicy {
+    lease_secs: Option<i64>,
+}
+
+// Whether nodes whose key was revoked or expired may get a new one
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct ReissuePolicy {
+    allow_reissue: bool,
+    max_reissues: Option<u32>,       // Per node, over its whole history; None is unlimited
+    cooldown_secs: Option<i64>,      // Wait after the key was retired before re-issuing
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GrantHistoryReq {
+    node_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MoveWaitlistReq {
+    node_id: String,
+    position: u64,  // 0 is the front of the queue
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PruneWaitlistReq {
+    node_ids: Option<Vec<String>>,   // Remove these nodes
+    older_than_secs: Option<i64>,    // Remove entries queued longer than this
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ApplyReconcileReq {
+    drift_ids: Option<Vec<String>>,  // If not given, every fix except deactivating unpooled keys
+}
+
+// Response types
+#[derive(Debug, Serialize, Deserialize)]
+struct SuccessRes {
+    success: bool,
+    message: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AdminKeyStatusRes {
+    has_admin_key: bool,
+    key_prefix: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyStatusRes {
+    status: String,
+    assigned_nodes: Vec<String>,
+    total_cost: f64,
+    workspace_id: Option<String>,
+    added_at: i64,
+    expires_at: Option<i64>,
+    retired_at: Option<i64>,
+    retired_reason: Option<String>,
+    spend_cap: Option<f64>,
+    max_nodes: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TotalCostsRes {
+    total_cost: f64,
+    cost_by_key: Vec<(String, f64)>,  // (key_id, cost); Vec of tuples for TypeScript compatibility
+    currency: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyCostsRes {
+    key_id: String,
+    costs: Vec<CostRecord>,
+    total: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JobInfo {
+    name: String,
+    interval_secs: u64,
+    jitter_secs: u64,
+    enabled: bool,
+    running: bool,
+    next_run_at: i64,
+    last_run_at: Option<i64>,
+    last_duration_ms: Option<u64>,
+    last_outcome: Option<String>,
+    last_message: Option<String>,
+    last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RevealKeyRes {
+    key_id: String,
+    api_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AuditLogRes {
+    entries: Vec<AuditEntry>,  // Newest first
+    total: u64,                // Entries matching the filters
+    chain_valid: bool,
+    first_invalid_seq: Option<u64>,
+    retention: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PoolHealthRes {
+    active_keys: u64,
+    assigned_nodes: u64,
+    spend_headroom: Option<f64>,        // None when some active key is uncapped
+    projected_capacity: Option<u64>,    // More nodes the pool can absorb; None if unbounded
+    thresholds: PoolThresholds,
+    default_max_nodes: Option<u64>,     // Hard node limit for keys without their own
+    pool_mode: PoolMode,
+    alert_webhook_url: Option<String>,
+    open_alerts: Vec<PoolAlert>,
+    resolved_alerts: Vec<PoolAlert>,    // Newest first
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ApplyReconcileRes {
+    applied: Vec<String>,
+    failed: Vec<(String, String)>,  // (drift id, error)
+    report: ReconcileReport,        // Drift remaining after the fixes
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CostsRefreshRes {
+    success: bool,
+    message: String,
+    timestamp: i64,
+}
+
+#[hyperapp_macro::hyperapp(
+    name = "Anthropic API Key Manager",
+    ui = Some(HttpBindingConfig::default()),
+    endpoints = vec![
+        // Every API call needs the node owner's login session; requests without
+        // it are rejected by http-server before they reach us. Nodes request
+        // keys over p2p (#[remote]), not through this binding.
+        Binding::Http {
+            path: "/api",
+            config: HttpBindingConfig::new(true, false, false, None)
+        },
+        Binding::Ws {
+            path: "/ws",
+            config: WsBindingConfig::default().authenticated(true)
+        }
+    ],
+    save_config = SaveOptions::OnDiff,
+    wit_world = "anthropic-api-key-manager-v0"
+)]
+impl AnthropicApiKeyManagerState {
+    #[init]
+    async fn initialize(&mut self) {
+        add_to_homepage("API Key Manager", None, Some("/"), None);
+
+        if let Err(e) = sealed::ensure_key() {
+            println!("ERROR: sealing key unavailable, state can't be saved: {}", e);
+        }
+
+        // Plaintext secrets from older state were accepted on load; flipping the
+        // flag makes OnDiff save, which writes them back sealed
+        if !self.secrets_sealed {
+            println!("Encrypting secrets in persisted state");
+            self.secrets_sealed = true;
+        }
+        if self.sealing_version < sealed::SEALING_VERSION {
+            self.sealing_version = sealed::SEALING_VERSION;
+        }
+
+        // Logs from before the chain was keyed are re-hashed once, if they still verify
+        if self.audit_anchor.is_none() && !self.audit_log.is_empty() {
+            match sealed::derive_subkey(audit::KEY_LABEL) {
+                Ok(key) => match audit::rekey_legacy(&mut self.audit_log, &mut self.audit_anchor, &key) {
+                    Ok(()) => println!("Re-keyed {} audit entries", self.audit_log.len()),
+                    Err(seq) => println!("WARNING: audit log is broken at seq {}; leaving it as it is", seq),
+                },
+                Err(e) => println!("Can't re-key audit log: {}", e),
+            }
+        }
+
+        let unsealed_failures = sealed::take_unseal_failures();
+        if unsealed_failures > 0 {
+            println!("WARNING: {} stored secrets could not be decrypted and are kept sealed; \
+                      they won't be used until the original sealing key is restored", unsealed_failures);
+        }
+
+        // Assignments used to be dropped without forgetting how they were picked
+        let assigned: HashSet<&String> = self.key_to_nodes.values().flatten().collect();
+        self.node_selection.retain(|node, _| assigned.contains(node));
+
+        // Top-level names used to count as their own namespace
+        for issuance in self.recent_issuances.iter_mut() {
+            issuance.namespace = rate_limit::parent_namespace(&issuance.node_id);
+        }
+
+        // Keys added before expiry tracking get their lifetime counted from now
+        let now = Utc::now().timestamp();
+        for key in &self.active_keys {
+            let meta = self.key_metadata.entry(key.clone()).or_default();
+            if meta.added_at == 0 {
+                meta.added_at = now;
+            }
+        }
+
+        scheduler::ensure_default_jobs(&mut self.scheduled_jobs, now);
+
+        // Assignments from before grants were tracked become grants dated by
+        // their old issue time
+        let holders: Vec<(String, String)> = self.key_to_nodes.iter()
+            .flat_map(|(key, nodes)| nodes.iter().map(move |node| (key.clone(), node.clone())))
+            .collect();
+        for (key, node) in holders {
+            self.current_grant(&node, &key);
+        }
+        self.node_issue_times.clear();
+
+        // Records stored before per-bucket upserts were appended blindly, so a
+        // re-reported bucket may appear more than once; keep its latest report
+        if self.cost_schema_version < COST_SCHEMA_VERSION {
+            let removed = self.dedupe_cost_records();
+            if removed > 0 {
+                println!("Removed {} duplicate cost records from an older format", removed);
+            }
+            self.rebuild_key_costs();
+            self.cost_schema_version = COST_SCHEMA_VERSION;
+        }
+
+        // Spawn the scheduler tick. The task can't touch state, so it asks us
+        // to run due jobs through a local request; jobs read the admin key and
+        // settings at run time, so changes after startup are picked up.
+        spawn(async move {
+            loop {
+                let _ = sleep(scheduler::SCHEDULER_TICK_MS).await;
+
+                let body = serde_json::json!({ "RunDueJobs": Utc::now().timestamp() });
+                if let Err(e) = Request::to(our())
+                    .body(serde_json::to_vec(&body).unwrap())
+                    .send()
+                {
+                    println!("Failed to trigger scheduled jobs: {:?}", e);
+                }
+            }
+        });
+
+        println!("Anthropic API Key Manager initialized on node: {}", our().node);
+    }
+
+    // Kept for clients that predate request_grant
+    #[remote]
+    async fn request_api_key(&mut self) -> Result<String, String> {
+        let node_id = source().node;
+        self.grant_key(&node_id, None).await.map_err(Refusal::legacy_error)
+    }
+
+    #[remote]
+    async fn redeem_invite(&mut self, code: String) -> Result<String, String> {
+        let node_id = source().node;
+        self.grant_key(&node_id, Some(&code)).await.map_err(Refusal::legacy_error)
+    }
+
+    #[remote]
+    async fn get_my_grant(&mut self) -> Result<KeyGrant, String> {
+        let node_id = source().node;
+        if self.expire_leases(Utc::now().timestamp()) > 0 {
+            self.serve_waitlist();
+        }
+        let state = self.grant_state(&node_id);
+        Ok(self.key_grant(&node_id, state))
+    }
+
+    #[remote]
+    async fn renew_lease(&mut self) -> Result<KeyGrant, String> {
+        let node_id = source().node;
+        let now = Utc::now().timestamp();
+        if self.expire_leases(now) > 0 {
+            self.serve_waitlist();
+        }
+
+        let state = self.grant_state(&node_id);
+        if let Ok(ref key) = state {
+            self.current_grant(&node_id, key);
+            let lease_expires_at = self.lease_policy.lease_secs.map(|secs| now + secs);
+            if let Some(grant) = self.node_grants.get_mut(&node_id) {
+                grant.lease_expires_at = lease_expires_at;
+                grant.renewed_at = Some(now);
+            }
+        }
+        Ok(self.key_grant(&node_id, state))
+    }
+
+    #[remote]
+    async fn get_my_spend(&self) -> Result<NodeSpendRes, String> {
+        let node_id = source().node;
+        let key = self.find_key_for_node(&node_id)
+            .ok_or_else(|| "No key has been granted to this node".to_string())?;
+
+        let window_start = format_timestamp(Utc::now().timestamp() - selection::RECENT_SPEND_WINDOW_SECS);
+        let shared_with = self.key_to_nodes.get(&key).map(|n| n.len() as u64).unwrap_or(1).saturating_sub(1);
+        // A dedicated key's spend since issue is exactly this node's spend
+        let since = (self.pool_mode == PoolMode::Dedicated && shared_with == 0)
+            .then(|| self.node_grants.get(&node_id).map(|g| format_timestamp(g.issued_at)))
+            .flatten();
+        let total_spend = self.key_total_cost(&key, &since, &None);
+        // The cap is on the key's lifetime spend, whoever spent it
+        let key_spend = self.key_total_cost(&key, &None, &None);
+        let spend_cap = self.effective_spend_cap(&key);
+        Ok(NodeSpendRes {
+            key_id: key_id(&key),
+            window_secs: selection::RECENT_SPEND_WINDOW_SECS,
+            recent_spend: self.key_total_cost(&key, &Some(window_start), &None),
+            total_spend,
+            spend_cap,
+            remaining_allowance: spend_cap.map(|cap| (cap - key_spend).max(0.0)),
+            shared_with,
+        })
+    }
+
+    #[remote]
+    async fn relinquish_grant(&mut self) -> Result<SuccessRes, String> {
+        let node_id = source().node;
+        let held_key = self.find_key_for_node(&node_id);
+        let was_waiting = self.waitlist.iter().any(|e| e.node_id == node_id);
+
+        let mut event = AuditEvent::new(&node_id, "relinquish_grant").node(&node_id);
+        if let Some(ref key) = held_key {
+            event = event.key(&key_id(key));
+        }
+        let result = match (held_key, was_waiting) {
+            (Some(key), _) => {
+                self.relinquish_key(&node_id, &key);
+                Ok(SuccessRes {
+                    success: true,
+                    message: "Key given back; request a new grant to use the pool again".to_string(),
+                })
+            }
+            (None, true) => {
+                self.waitlist.retain(|e| e.node_id != node_id);
+                Ok(SuccessRes {
+                    success: true,
+                    message: "Left the waitlist".to_string(),
+                })
+            }
+            (None, false) => Err("No key has been granted to this node".to_string()),
+        };
+        self.audit(event, &result);
+
+        if result.is_ok() {
+            self.serve_waitlist();
+            self.check_pool_health().await;
+        }
+        result
+    }
+
+    #[remote]
+    async fn request_grant(&mut self, request: GrantReq) -> Result<KeyGrant, String> {
+        let node_id = source().node;
+        let result = self.grant_key(&node_id, request.invite_code.as_deref()).await;
+
+        if result.is_ok() {
+            if let Some(grant) = self.node_grants.get_mut(&node_id) {
+                grant.app_name = Some(request.app_name);
+                grant.app_version = Some(request.app_version);
+            }
+        }
+        if request.protocol_version != grant::PROTOCOL_VERSION {
+            println!("{} speaks grant protocol v{}, we speak v{}", node_id, request.protocol_version, grant::PROTOCOL_VERSION);
+        }
+
+        Ok(self.key_grant(&node_id, result))
+    }
+
+    #[http]
+    async fn add_api_key(&mut self, request: AddKeyReq) -> Result<SuccessRes, String> {
+        let event = AuditEvent::new(&our().node, "add_api_key")
+            .key(&key_id(&request.api_key))
+            .param_opt("workspace_id", &request.workspace_id)
+            .param_opt("ttl_seconds", &request.ttl_seconds)
+            .param_opt("spend_cap", &request.spend_cap)
+            .param_opt("max_nodes", &request.max_nodes);
+        let result = self.add_key(request).await;
+        self.audit(event, &result);
+        self.serve_waitlist();
+        self.check_pool_health().await;
+        result
+    }
+
+    #[http]
+    async fn set_key_workspace(&mut self, request: SetKeyWorkspaceReq) -> Result<SuccessRes, String> {
+        let event = AuditEvent::new(&our().node, "set_key_workspace")
+            .key(&request.key_id)
+            .param_opt("workspace_id", &request.workspace_id);
+        let result = self.map_key_workspace(request).await;
+        self.audit(event, &result);
+        result
+    }
+
+    #[http]
+    async fn set_key_ttl(&mut self, request: SetKeyTtlReq) -> Result<SuccessRes, String> {
+        let mut event = AuditEvent::new(&our().node, "set_key_ttl")
+            .param_opt("ttl_seconds", &request.ttl_seconds);
+        if let Some(ref key_id) = request.key_id {
+            event = event.key(key_id);
+        }
+        let result = self.apply_key_ttl(request).await;
+        self.audit(event, &result);
+        result
+    }
+
+    #[http]
+    async fn set_spend_cap(&mut self, request: SetSpendCapReq) -> Result<SuccessRes, String> {
+        let mut event = AuditEvent::new(&our().node, "set_spend_cap")
+            .param_opt("spend_cap", &request.spend_cap);
+        if let Some(ref key_id) = request.key_id {
+            event = event.key(key_id);
+        }
+        let result = self.apply_spend_cap(request).await;
+        self.audit(event, &result);
+        result
+    }
+
+    #[http]
+    async fn set_max_nodes(&mut self, request: SetMaxNodesReq) -> Result<SuccessRes, String> {
+        if request.max_nodes == Some(0) {
+            return Err("Node limit must be at least 1".to_string());
+        }
+
+        let mut event = AuditEvent::new(&our().node, "set_max_nodes")
+            .param_opt("max_nodes", &request.max_nodes);
+        if let Some(ref key_id) = request.key_id {
+            event = event.key(key_id);
+        }
+
+        // Keys already over a lowered limit keep their nodes; they just take no new ones
+        let result = match request.key_id {
+            None => {
+                self.default_max_nodes = request.max_nodes;
+                Ok(match request.max_nodes {
+                    Some(max) => format!("Default node limit set to {}", max),
+                    None => "Default node limit cleared".to_string(),
+                })
+            }
+            Some(ref key_id) => self.resolve_key_id(key_id).map(|api_key| {
+                self.key_metadata.entry(api_key).or_default().max_nodes = request.max_nodes;
+                match request.max_nodes {
+                    Some(max) => format!("Node limit set to {}", max),
+                    None => "Node limit cleared".to_string(),
+                }
+            }),
+        };
+        self.audit(event, &result);
+        self.serve_waitlist();
+        self.check_pool_health().await;
 
         Ok(SuccessRes {
             success: true,
-            message: "API key removed successfully".to_string(),
+            message: result?,
+        })
+    }
+
+    #[http]
+    async fn remove_api_key(&mut self, request: RemoveKeyReq) -> Result<SuccessRes, String> {
+        let event = AuditEvent::new(&our().node, "remove_api_key").key(&request.key_id);
+        let result = self.remove_key(request).await;
+        self.audit(event, &result);
+        self.check_pool_health().await;
+        result
+    }
+
+    #[http]
+    async fn set_key_weight(&mut self, request: SetKeyWeightReq) -> Result<SuccessRes, String> {
+        let event = AuditEvent::new(&our().node, "set_key_weight")
+            .key(&request.key_id)
+            .param_opt("weight", &request.weight);
+        let result = self.resolve_key_id(&request.key_id).map(|api_key| {
+            self.key_metadata.entry(api_key).or_default().weight = request.weight;
+        });
+        self.audit(event, &result);
+        result?;
+        self.serve_waitlist();
+
+        Ok(SuccessRes {
+            success: true,
+            message: format!("Key weight set to {}", request.weight.unwrap_or(selection::DEFAULT_WEIGHT)),
+        })
+    }
+
+    #[http]
+    async fn get_selection_strategy(&self) -> Result<SelectionStrategyKind, String> {
+        Ok(self.selection_strategy.clone())
+    }
+
+    #[http]
+    async fn set_selection_strategy(&mut self, request: SetSelectionStrategyReq) -> Result<SuccessRes, String> {
+        let event = AuditEvent::new(&our().node, "set_selection_strategy")
+            .param("strategy", format!("{:?}", request.strategy));
+        self.selection_strategy = request.strategy;
+        self.audit_ok(event);
+        self.serve_waitlist();
+
+        Ok(SuccessRes {
+            success: true,
+            message: format!("Key selection strategy set to {:?}", self.selection_strategy),
+        })
+    }
+
+    #[http]
+    async fn set_pool_mode(&mut self, request: SetPoolModeReq) -> Result<SuccessRes, String> {
+        let event = AuditEvent::new(&our().node, "set_pool_mode")
+            .param("mode", format!("{:?}", request.mode));
+        self.pool_mode = request.mode;
+        self.audit_ok(event);
+
+        // Keys already shared keep their nodes; new nodes only get empty keys
+        let shared = self.key_to_nodes.values().filter(|nodes| nodes.len() > 1).count();
+        self.serve_waitlist();
+        self.name_dedicated_keys().await;
+        self.check_pool_health().await;
+
+        let note = if self.pool_mode == PoolMode::Dedicated && shared > 0 {
+            format!("; {} keys stay shared until their nodes leave", shared)
+        } else {
+            String::new()
+        };
+        Ok(SuccessRes {
+            success: true,
+            message: format!("Pool mode set to {:?}{}", self.pool_mode, note),
         })
     }
 
@@ -301,15 +1228,20 @@ impl AnthropicApiKeyManagerState {
                     .map(|n| n.clone())
                     .unwrap_or_default();
 
-                // We cannot track costs per individual API key from the Anthropic API
-                let total_cost = 0.0;
+                let total_cost = self.key_total_cost(key, &None, &None);
 
                 ApiKeyInfo {
-                    key: key.clone(),
+                    key_id: key_id(key),
+                    key_hint: mask_key(key),
                     status: "active".to_string(),
                     total_cost,
                     assigned_nodes: nodes,
-                    created_at: 0,
+                    created_at: self.key_metadata.get(key).map(|m| m.added_at).unwrap_or(0),
+                    expires_at: self.key_metadata.get(key).and_then(|m| m.expires_at),
+                    spend_cap: self.effective_spend_cap(key),
+                    workspace_id: self.key_workspace(key),
+                    weight: self.key_weight(key),
+                    max_nodes: self.effective_max_nodes(key),
                 }
             })
             .collect();
@@ -319,9 +1251,10 @@ impl AnthropicApiKeyManagerState {
 
     #[http]
     async fn get_key_status(&self, request: KeyStatusReq) -> Result<KeyStatusRes, String> {
+        let api_key = self.resolve_key_id(&request.key_id)?;
 
-        let is_active = self.active_keys.contains(&request.api_key);
-        let is_historical = self.historical_keys.contains(&request.api_key);
+        let is_active = self.active_keys.contains(&api_key);
+        let is_historical = self.historical_keys.contains(&api_key);
 
         let status = if is_active {
             "active"
@@ -331,17 +1264,24 @@ impl AnthropicApiKeyManagerState {
             "unknown"
         };
 
-        let nodes = self.key_to_nodes.get(&request.api_key)
+        let nodes = self.key_to_nodes.get(&api_key)
             .map(|n| n.clone())
             .unwrap_or_default();
 
-        // We cannot track costs per individual API key from the Anthropic API
-        let total_cost = 0.0;
+        let total_cost = self.key_total_cost(&api_key, &None, &None);
+        let meta = self.key_metadata.get(&api_key).cloned().unwrap_or_default();
 
         Ok(KeyStatusRes {
             status: status.to_string(),
             assigned_nodes: nodes,
             total_cost,
+            workspace_id: meta.workspace_id,
+            added_at: meta.added_at,
+            expires_at: meta.expires_at,
+            retired_at: meta.retired_at,
+            retired_reason: meta.retired_reason,
+            spend_cap: self.effective_spend_cap(&api_key),
+            max_nodes: self.effective_max_nodes(&api_key),
         })
     }
 
@@ -354,186 +1294,2401 @@ impl AnthropicApiKeyManagerState {
             .map(|c| c.amount)
             .sum();
 
-        // We cannot track costs per individual API key from the Anthropic API
-        let cost_by_key: Vec<(String, f64)> = Vec::new();
+        let mut cost_by_key: Vec<(String, f64)> = self.key_costs.keys()
+            .map(|key| (key_id(key), self.key_total_cost(key, &request.start_date, &request.end_date)))
+            .collect();
+        cost_by_key.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        Ok(TotalCostsRes {
+            total_cost,
+            cost_by_key,
+            currency: "USD".to_string(),
+        })
+    }
+
+    #[http]
+    async fn get_key_costs(&self, request: KeyCostReq) -> Result<KeyCostsRes, String> {
+        let api_key = self.resolve_key_id(&request.key_id)?;
+
+        let costs: Vec<CostRecord> = self.key_costs.get(&api_key)
+            .map(|costs| costs.iter()
+                .filter(|c| self.filter_by_date(c.timestamp, &request.start_date, &request.end_date))
+                .cloned()
+                .collect())
+            .unwrap_or_default();
+        let total: f64 = costs.iter().map(|c| c.amount).sum();
+
+        Ok(KeyCostsRes {
+            key_id: request.key_id,
+            costs,
+            total,
+        })
+    }
+
+    #[http]
+    async fn get_node_history(&self) -> Result<Vec<NodeAssignment>, String> {
+        let mut assignments: Vec<NodeAssignment> = Vec::new();
+
+        for (key, nodes) in &self.key_to_nodes {
+            for node in nodes {
+                let issued_at = self.node_grants.get(node).map(|g| g.issued_at).unwrap_or(0);
+                assignments.push(NodeAssignment {
+                    node_id: node.clone(),
+                    key_id: key_id(key),
+                    key_hint: mask_key(key),
+                    issued_at,
+                    strategy: self.node_selection.get(node).cloned(),
+                    cost: (nodes.len() == 1).then(|| self.key_total_cost(key, &Some(format_timestamp(issued_at)), &None)),
+                    lease_expires_at: self.node_grants.get(node).and_then(|g| g.lease_expires_at),
+                    expired: false,
+                });
+            }
+        }
+
+        // Lapsed leases stay listed so quiet nodes can be told apart from departed ones
+        for (node, grants) in &self.grant_history {
+            for grant in grants.iter().filter(|g| g.end_reason.as_deref() == Some(grant::LEASE_EXPIRED)) {
+                let key = self.resolve_key_id(&grant.key_id).ok();
+                assignments.push(NodeAssignment {
+                    node_id: node.clone(),
+                    key_id: grant.key_id.clone(),
+                    key_hint: key.as_deref().map(mask_key).unwrap_or_else(|| grant.key_id.clone()),
+                    issued_at: grant.issued_at,
+                    strategy: None,
+                    cost: None,
+                    lease_expires_at: grant.ended_at,
+                    expired: true,
+                });
+            }
+        }
+
+        assignments.sort_by_key(|a| a.issued_at);
+
+        Ok(assignments)
+    }
+
+    /// The only endpoint that returns a full pooled key. Every call is recorded.
+    #[http]
+    async fn reveal_api_key(&mut self, request: RevealKeyReq) -> Result<RevealKeyRes, String> {
+        let event = AuditEvent::new(&our().node, "reveal_api_key")
+            .key(&request.key_id)
+            .param_opt("reason", &request.reason);
+        let api_key = self.resolve_key_id(&request.key_id);
+        self.audit(event, &api_key);
+        let api_key = api_key?;
+
+        // The /api binding only admits the node owner's session
+        let actor = our().node;
+        println!("API key {} revealed by {}", request.key_id, actor);
+        self.key_reveals.push(KeyReveal {
+            key_id: request.key_id.clone(),
+            key_hint: mask_key(&api_key),
+            actor,
+            reason: request.reason,
+            revealed_at: Utc::now().timestamp(),
+        });
+
+        Ok(RevealKeyRes {
+            key_id: request.key_id,
+            api_key,
+        })
+    }
+
+    #[http]
+    async fn get_key_reveals(&self) -> Result<Vec<KeyReveal>, String> {
+        let mut reveals = self.key_reveals.clone();
+        reveals.sort_by(|a, b| b.revealed_at.cmp(&a.revealed_at));
+        Ok(reveals)
+    }
+
+    #[http]
+    async fn get_audit_log(&self, request: AuditLogReq) -> Result<AuditLogRes, String> {
+        let key = sealed::derive_subkey(audit::KEY_LABEL)
+            .map_err(|e| format!("Can't verify the audit log: {}", e))?;
+        let first_invalid_seq = audit::first_broken_link(&self.audit_log, self.audit_anchor.as_deref(), &key);
+
+        let matching: Vec<&AuditEntry> = self.audit_log.iter()
+            .rev()
+            .filter(|e| request.action.as_ref().map_or(true, |a| &e.action == a))
+            .filter(|e| request.actor.as_ref().map_or(true, |a| &e.actor == a))
+            .filter(|e| request.key_id.as_ref().map_or(true, |k| e.target_key.as_ref() == Some(k)))
+            .filter(|e| request.node_id.as_ref().map_or(true, |n| e.target_node.as_ref() == Some(n)))
+            .filter(|e| request.since.map_or(true, |since| e.timestamp >= since))
+            .filter(|e| request.until.map_or(true, |until| e.timestamp <= until))
+            .collect();
+
+        let offset = request.offset.unwrap_or(0) as usize;
+        let limit = request.limit.unwrap_or(50).min(500) as usize;
+
+        Ok(AuditLogRes {
+            total: matching.len() as u64,
+            entries: matching.into_iter().skip(offset).take(limit).cloned().collect(),
+            chain_valid: first_invalid_seq.is_none(),
+            first_invalid_seq,
+            retention: self.audit_retention,
+        })
+    }
+
+    #[http]
+    async fn set_audit_retention(&mut self, request: SetAuditRetentionReq) -> Result<SuccessRes, String> {
+        if matches!(request.max_entries, Some(max) if max < audit::MIN_RETENTION) {
+            return Err(format!("Retention must keep at least {} entries", audit::MIN_RETENTION));
+        }
+
+        let event = AuditEvent::new(&our().node, "set_audit_retention")
+            .param_opt("max_entries", &request.max_entries);
+        self.audit_retention = request.max_entries;
+        self.audit_ok(event);
+
+        Ok(SuccessRes {
+            success: true,
+            message: match request.max_entries {
+                Some(max) => format!("Audit log will be pruned to its newest {} entries", max),
+                None => "Audit log will be kept in full".to_string(),
+            },
+        })
+    }
+
+    #[http]
+    async fn set_admin_key(&mut self, request: SetAdminKeyParams) -> Result<SuccessRes, String> {
+        self.admin_api_key = Some(request.admin_key.clone());
+
+        // Log for debugging
+        println!("Admin key set: {}", if request.admin_key.starts_with("sk-") { "sk-***" } else { "invalid format" });
+
+        let event = AuditEvent::new(&our().node, "set_admin_key").param("admin_key", audit::REDACTED);
+        self.audit_ok(event);
+
+        Ok(SuccessRes {
+            success: true,
+            message: "Admin key set successfully".to_string(),
+        })
+    }
+
+    #[http]
+    async fn check_admin_key(&self) -> Result<AdminKeyStatusRes, String> {
+        Ok(AdminKeyStatusRes {
+            has_admin_key: self.admin_api_key.is_some(),
+            key_prefix: self.admin_api_key.as_ref().map(|k| {
+                if k.starts_with("sk-") {
+                    "sk-***".to_string()
+                } else {
+                    "invalid".to_string()
+                }
+            }),
+        })
+    }
+
+    #[http]
+    async fn get_all_costs(&self) -> Result<Vec<CostRecord>, String> {
+        println!("get_all_costs called. Returning {} cost records", self.all_costs.len());
+
+        // Debug: print first few records if any exist
+        if !self.all_costs.is_empty() {
+            println!("Sample cost records:");
+            for (i, cost) in self.all_costs.iter().take(3).enumerate() {
+                println!("  [{}] {} {} - {}", i, cost.amount, cost.currency, cost.description);
+            }
+        }
+
+        Ok(self.all_costs.clone())
+    }
+
+    #[http]
+    async fn refresh_costs(&mut self) -> Result<CostsRefreshRes, String> {
+        if self.admin_api_key.is_none() {
+            return Err("Admin API key not configured".to_string());
+        }
+
+        let now = Utc::now().timestamp();
+
+        // Debug logging
+        println!("refresh_costs called. Current time: {}, Last check: {:?}", now, self.last_cost_check);
+
+        if let Some(last_check) = self.last_cost_check {
+            let time_since_last = now - last_check;
+            println!("Time since last check: {} seconds", time_since_last);
+
+            // Only rate limit if less than 60 seconds (not 3600)
+            // But allow negative time_since_last (which means timestamp is in future - a bug we're fixing)
+            if time_since_last < 60 && time_since_last > 0 {
+                println!("Rate limiting: costs were refreshed {} seconds ago", time_since_last);
+                return Ok(CostsRefreshRes {
+                    success: false,
+                    message: format!("Costs were recently refreshed {} seconds ago", time_since_last),
+                    timestamp: last_check,
+                });
+            }
+
+            // If time_since_last is negative, clear the bad timestamp
+            if time_since_last < 0 {
+                println!("WARNING: last_cost_check is in the future! Clearing it.");
+                self.last_cost_check = None;
+            }
+        }
+
+        // Fetch real costs from Anthropic API
+        match self.fetch_costs_from_anthropic().await {
+            Ok(costs_added) => {
+                self.last_cost_check = Some(now);
+                Ok(CostsRefreshRes {
+                    success: true,
+                    message: format!("Costs refreshed successfully. Added or updated {} cost records", costs_added),
+                    timestamp: now,
+                })
+            }
+            Err(e) => {
+                println!("Failed to fetch costs from Anthropic: {}", e);
+                Err(format!("Failed to fetch costs: {}", e))
+            }
+        }
+    }
+
+    #[http]
+    async fn reset_costs(&mut self) -> Result<SuccessRes, String> {
+        let event = AuditEvent::new(&our().node, "reset_costs")
+            .param("records", self.all_costs.len());
+        let result = self.clear_costs();
+        self.audit(event, &result);
+        result
+    }
+
+    #[http]
+    async fn get_pool_health(&self) -> Result<PoolHealthRes, String> {
+        let loads = self.key_loads();
+        let mut resolved_alerts: Vec<PoolAlert> = self.pool_alerts.iter()
+            .filter(|a| a.resolved_at.is_some())
+            .cloned()
+            .collect();
+        resolved_alerts.sort_by(|a, b| b.resolved_at.cmp(&a.resolved_at));
+
+        Ok(PoolHealthRes {
+            active_keys: self.active_keys.len() as u64,
+            assigned_nodes: loads.iter().map(|l| l.nodes).sum(),
+            spend_headroom: pool_health::spend_headroom(&loads),
+            projected_capacity: pool_health::projected_capacity(&loads),
+            thresholds: self.pool_thresholds.clone(),
+            default_max_nodes: self.default_max_nodes,
+            pool_mode: self.pool_mode.clone(),
+            alert_webhook_url: self.alert_webhook_url.clone(),
+            open_alerts: self.pool_alerts.iter().filter(|a| a.resolved_at.is_none()).cloned().collect(),
+            resolved_alerts,
+        })
+    }
+
+    #[http]
+    async fn set_pool_thresholds(&mut self, request: SetPoolThresholdsReq) -> Result<SuccessRes, String> {
+        if let Some(ref url) = request.alert_webhook_url {
+            url::Url::parse(url).map_err(|e| format!("Invalid webhook URL: {}", e))?;
+        }
+
+        let event = AuditEvent::new(&our().node, "set_pool_thresholds")
+            .param_opt("min_active_keys", &request.thresholds.min_active_keys)
+            .param_opt("max_nodes_per_key", &request.thresholds.max_nodes_per_key)
+            .param_opt("min_spend_headroom", &request.thresholds.min_spend_headroom)
+            .param_opt("alert_webhook_url", &request.alert_webhook_url);
+        self.pool_thresholds = request.thresholds;
+        self.alert_webhook_url = request.alert_webhook_url;
+        self.audit_ok(event);
+
+        self.check_pool_health().await;
+
+        Ok(SuccessRes {
+            success: true,
+            message: "Pool thresholds updated".to_string(),
+        })
+    }
+
+    #[http]
+    async fn rotate_api_key(&mut self, request: RotateKeyReq) -> Result<KeyRotation, String> {
+        if matches!(request.grace_secs, Some(grace) if grace < 0) {
+            return Err("Grace period can't be negative".to_string());
+        }
+
+        let grace_secs = request.grace_secs.or(self.rotation_policy.grace_secs).unwrap_or(DEFAULT_ROTATION_GRACE_SECS);
+        let event = AuditEvent::new(&our().node, "rotate_api_key")
+            .key(&request.key_id)
+            .param("grace_secs", grace_secs);
+        let result = match self.resolve_key_id(&request.key_id) {
+            Ok(api_key) => self.rotate_key(&api_key, grace_secs, "manual").await,
+            Err(e) => Err(e),
+        };
+        self.audit(event, &result);
+        self.check_pool_health().await;
+        result
+    }
+
+    #[http]
+    async fn get_rotations(&self) -> Result<Vec<KeyRotation>, String> {
+        let mut rotations = self.rotations.clone();
+        rotations.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+        Ok(rotations)
+    }
+
+    #[http]
+    async fn get_rotation_policy(&self) -> Result<RotationPolicy, String> {
+        Ok(self.rotation_policy.clone())
+    }
+
+    #[http]
+    async fn set_rotation_policy(&mut self, request: RotationPolicy) -> Result<SuccessRes, String> {
+        if matches!(request.grace_secs, Some(grace) if grace < 0) {
+            return Err("Grace period can't be negative".to_string());
+        }
+        if matches!(request.max_key_age_secs, Some(age) if age <= 0) {
+            return Err("Maximum key age must be positive".to_string());
+        }
+
+        let event = AuditEvent::new(&our().node, "set_rotation_policy")
+            .param_opt("grace_secs", &request.grace_secs)
+            .param_opt("max_key_age_secs", &request.max_key_age_secs);
+        self.rotation_policy = request;
+        self.audit_ok(event);
+
+        Ok(SuccessRes {
+            success: true,
+            message: "Rotation policy updated".to_string(),
+        })
+    }
+
+    #[http]
+    async fn get_reissue_policy(&self) -> Result<ReissuePolicy, String> {
+        Ok(self.reissue_policy.clone())
+    }
+
+    #[http]
+    async fn set_reissue_policy(&mut self, request: ReissuePolicy) -> Result<SuccessRes, String> {
+        if matches!(request.cooldown_secs, Some(cooldown) if cooldown < 0) {
+            return Err("Cooldown can't be negative".to_string());
+        }
+
+        let event = AuditEvent::new(&our().node, "set_reissue_policy")
+            .param("allow_reissue", request.allow_reissue)
+            .param_opt("max_reissues", &request.max_reissues)
+            .param_opt("cooldown_secs", &request.cooldown_secs);
+        self.reissue_policy = request;
+        self.audit_ok(event);
+
+        Ok(SuccessRes {
+            success: true,
+            message: "Re-issue policy updated".to_string(),
+        })
+    }
+
+    #[http]
+    async fn get_lease_policy(&self) -> Result<LeasePolicy, String> {
+        Ok(self.lease_policy.clone())
+    }
+
+    #[http]
+    async fn set_lease_policy(&mut self, request: LeasePolicy) -> Result<SuccessRes, String> {
+        if matches!(request.lease_secs, Some(secs) if secs <= 0) {
+            return Err("Lease duration must be positive".to_string());
+        }
+
+        let event = AuditEvent::new(&our().node, "set_lease_policy")
+            .param_opt("lease_secs", &request.lease_secs);
+        self.lease_policy = request;
+        self.audit_ok(event);
+
+        // Current grants move onto the new terms from now, so nodes that went
+        // quiet under permanent grants are found too
+        let lease_expires_at = self.lease_policy.lease_secs.map(|secs| Utc::now().timestamp() + secs);
+        for grant in self.node_grants.values_mut() {
+            grant.lease_expires_at = lease_expires_at;
+        }
+
+        Ok(SuccessRes {
+            success: true,
+            message: match self.lease_policy.lease_secs {
+                Some(secs) => format!("Grants are now leases of {} seconds; {} current grants must renew", secs, self.node_grants.len()),
+                None => "Grants are now permanent".to_string(),
+            },
+        })
+    }
+
+    #[http]
+    async fn get_grant_history(&self, request: GrantHistoryReq) -> Result<Vec<NodeGrant>, String> {
+        Ok(self.node_grant_history(request.node_id.trim()))
+    }
+
+    #[http]
+    async fn ban_node(&mut self, request: BanNodeReq) -> Result<BanNodeRes, String> {
+        let event = AuditEvent::new(&our().node, "ban_node")
+            .param("pattern", &request.pattern)
+            .param("reason", &request.reason)
+            .param_opt("expires_in_secs", &request.expires_in_secs)
+            .param("rotate_keys", request.rotate_keys);
+        let result = self.add_ban(request).await;
+        self.audit(event, &result);
+        self.serve_waitlist();
+        self.check_pool_health().await;
+        result
+    }
+
+    #[http]
+    async fn unban_node(&mut self, request: UnbanNodeReq) -> Result<SuccessRes, String> {
+        let event = AuditEvent::new(&our().node, "unban_node")
+            .param("ban_id", &request.ban_id);
+        let result = match self.bans.iter().position(|ban| ban.ban_id == request.ban_id) {
+            Some(index) => {
+                let ban = self.bans.remove(index);
+                Ok(SuccessRes {
+                    success: true,
+                    message: format!("Lifted ban on {}", ban.pattern),
+                })
+            }
+            None => Err(format!("No ban with id {}", request.ban_id)),
+        };
+        self.audit(event, &result);
+        result
+    }
+
+    #[http]
+    async fn list_bans(&self) -> Result<Vec<NodeBan>, String> {
+        let mut bans = self.bans.clone();
+        bans.sort_by(|a, b| b.banned_at.cmp(&a.banned_at));
+        Ok(bans)
+    }
+
+    #[http]
+    async fn get_rate_limits(&self) -> Result<RateLimitStatus, String> {
+        let now = Utc::now().timestamp();
+        let mut denials: HashMap<&str, u64> = HashMap::new();
+        for denial in self.recent_denials.iter().filter(|d| d.at > now - rate_limit::DAY_SECS) {
+            *denials.entry(&denial.limit).or_default() += 1;
+        }
+        let mut denials_last_day: Vec<(String, u64)> = denials.into_iter()
+            .map(|(limit, count)| (limit.to_string(), count))
+            .collect();
+        denials_last_day.sort();
+
+        Ok(RateLimitStatus {
+            limits: self.rate_limits.clone(),
+            issued_last_hour: rate_limit::count_in_window(&self.recent_issuances, rate_limit::HOUR_SECS, now),
+            issued_last_day: rate_limit::count_in_window(&self.recent_issuances, rate_limit::DAY_SECS, now),
+            paused_until: self.issuance_paused_until.filter(|until| *until > now),
+            namespaces: rate_limit::namespace_counts(&self.recent_issuances, now),
+            denials_last_day,
+        })
+    }
+
+    #[http]
+    async fn set_rate_limits(&mut self, request: RateLimits) -> Result<SuccessRes, String> {
+        if request.burst_max.is_some() != request.burst_window_secs.is_some() {
+            return Err("Set both burst_max and burst_window_secs, or neither".to_string());
+        }
+        if [request.burst_window_secs, request.burst_pause_secs].iter().any(|secs| matches!(secs, Some(s) if *s <= 0)) {
+            return Err("Burst window and pause must be positive".to_string());
+        }
+
+        let event = AuditEvent::new(&our().node, "set_rate_limits")
+            .param_opt("global_per_hour", &request.global_per_hour)
+            .param_opt("global_per_day", &request.global_per_day)
+            .param_opt("namespace_per_hour", &request.namespace_per_hour)
+            .param_opt("namespace_per_day", &request.namespace_per_day)
+            .param_opt("top_level_per_hour", &request.top_level_per_hour)
+            .param_opt("top_level_per_day", &request.top_level_per_day)
+            .param_opt("burst_max", &request.burst_max)
+            .param_opt("burst_window_secs", &request.burst_window_secs)
+            .param_opt("burst_pause_secs", &request.burst_pause_secs);
+        self.rate_limits = request;
+        self.audit_ok(event);
+
+        Ok(SuccessRes {
+            success: true,
+            message: "Rate limits updated".to_string(),
+        })
+    }
+
+    #[http]
+    async fn resume_issuance(&mut self) -> Result<SuccessRes, String> {
+        let now = Utc::now().timestamp();
+        let was_paused = self.issuance_paused_until.take().map_or(false, |until| until > now);
+        self.burst_counted_from = now;
+        self.audit_ok(AuditEvent::new(&our().node, "resume_issuance")
+            .param("was_paused", was_paused));
+
+        self.serve_waitlist();
+        self.check_pool_health().await;
+
+        Ok(SuccessRes {
+            success: true,
+            message: if was_paused { "Issuance resumed" } else { "Issuance was not paused" }.to_string(),
+        })
+    }
+
+    #[http]
+    async fn mint_invite_codes(&mut self, request: MintInvitesReq) -> Result<Vec<InviteCode>, String> {
+        if request.count == 0 || request.count > invites::MAX_BATCH {
+            return Err(format!("Mint between 1 and {} codes at a time", invites::MAX_BATCH));
+        }
+        if request.max_uses == Some(0) {
+            return Err("Codes need at least one use".to_string());
+        }
+        if matches!(request.expires_in_secs, Some(secs) if secs <= 0) {
+            return Err("Code expiry must be in the future".to_string());
+        }
+
+        let now = Utc::now().timestamp();
+        let batch_id = format!("batch_{:016x}", rand::random::<u64>());
+        let campaign = request.campaign.map(|c| c.trim().to_string()).filter(|c| !c.is_empty());
+        let mut minted = Vec::new();
+        while minted.len() < request.count as usize {
+            let code = invites::generate_code();
+            if self.invite_codes.iter().chain(minted.iter()).any(|i: &InviteCode| i.code == code) {
+                continue;
+            }
+            minted.push(InviteCode {
+                code,
+                batch_id: batch_id.clone(),
+                campaign: campaign.clone(),
+                max_uses: request.max_uses.unwrap_or(1),
+                created_at: now,
+                expires_at: request.expires_in_secs.map(|secs| now + secs),
+                revoked_at: None,
+                redemptions: Vec::new(),
+                status: "active".to_string(),
+            });
+        }
+        self.invite_codes.extend(minted.iter().cloned());
+
+        // The codes themselves stay out of the audit log
+        let event = AuditEvent::new(&our().node, "mint_invite_codes")
+            .param("batch_id", &batch_id)
+            .param("count", request.count)
+            .param_opt("max_uses", &request.max_uses)
+            .param_opt("expires_in_secs", &request.expires_in_secs)
+            .param_opt("campaign", &campaign);
+        self.audit_ok(event);
+
+        Ok(minted)
+    }
+
+    #[http]
+    async fn list_invite_codes(&self, request: ListInvitesReq) -> Result<Vec<InviteCode>, String> {
+        let now = Utc::now().timestamp();
+        Ok(self.matching_invites(&request, now)
+            .into_iter()
+            .map(|invite| InviteCode {
+                status: invites::status(invite, now).to_string(),
+                ..invite.clone()
+            })
+            .collect())
+    }
+
+    #[http]
+    async fn export_invite_codes(&self, request: ListInvitesReq) -> Result<String, String> {
+        let now = Utc::now().timestamp();
+        Ok(invites::to_csv(&self.matching_invites(&request, now), now))
+    }
+
+    #[http]
+    async fn revoke_invite_codes(&mut self, request: RevokeInvitesReq) -> Result<SuccessRes, String> {
+        if request.codes.is_none() && request.batch_id.is_none() {
+            return Err("Give codes or batch_id".to_string());
+        }
+
+        let now = Utc::now().timestamp();
+        let codes: Vec<String> = request.codes.iter().flatten().map(|c| invites::normalize(c)).collect();
+        let mut revoked = 0;
+        for invite in self.invite_codes.iter_mut().filter(|i| i.revoked_at.is_none()) {
+            if codes.contains(&invites::normalize(&invite.code)) || request.batch_id.as_ref() == Some(&invite.batch_id) {
+                invite.revoked_at = Some(now);
+                revoked += 1;
+            }
+        }
+
+        let event = AuditEvent::new(&our().node, "revoke_invite_codes")
+            .param("revoked", revoked)
+            .param("codes", codes.len())
+            .param_opt("batch_id", &request.batch_id);
+        self.audit_ok(event);
+
+        Ok(SuccessRes {
+            success: true,
+            message: format!("Revoked {} invite codes", revoked),
+        })
+    }
+
+    #[http]
+    async fn get_invite_required(&self) -> Result<bool, String> {
+        Ok(self.invite_required)
+    }
+
+    #[http]
+    async fn set_invite_required(&mut self, request: SetInviteRequiredReq) -> Result<SuccessRes, String> {
+        self.invite_required = request.required;
+        self.audit_ok(AuditEvent::new(&our().node, "set_invite_required")
+            .param("required", request.required));
+
+        Ok(SuccessRes {
+            success: true,
+            message: if request.required {
+                "New nodes now need an invite code"
+            } else {
+                "New nodes can request a key without an invite"
+            }.to_string(),
+        })
+    }
+
+    #[http]
+    async fn get_waitlist(&self) -> Result<Vec<WaitlistEntry>, String> {
+        Ok(self.waitlist.iter().cloned().collect())
+    }
+
+    #[http]
+    async fn move_waitlist_entry(&mut self, request: MoveWaitlistReq) -> Result<Vec<WaitlistEntry>, String> {
+        let index = self.waitlist.iter()
+            .position(|e| e.node_id == request.node_id)
+            .ok_or_else(|| format!("{} is not on the waitlist", request.node_id))?;
+
+        let entry = self.waitlist.remove(index).unwrap();
+        let position = (request.position as usize).min(self.waitlist.len());
+        self.waitlist.insert(position, entry);
+
+        let event = AuditEvent::new(&our().node, "move_waitlist_entry")
+            .node(&request.node_id)
+            .param("from", index)
+            .param("to", position);
+        self.audit_ok(event);
+
+        Ok(self.waitlist.iter().cloned().collect())
+    }
+
+    #[http]
+    async fn prune_waitlist(&mut self, request: PruneWaitlistReq) -> Result<SuccessRes, String> {
+        if request.node_ids.is_none() && request.older_than_secs.is_none() {
+            return Err("Give node_ids or older_than_secs".to_string());
+        }
+
+        let cutoff = request.older_than_secs.map(|secs| Utc::now().timestamp() - secs);
+        let before = self.waitlist.len();
+        self.waitlist.retain(|e| {
+            let listed = request.node_ids.as_ref().map_or(false, |ids| ids.contains(&e.node_id));
+            let stale = cutoff.map_or(false, |cutoff| e.requested_at < cutoff);
+            !listed && !stale
+        });
+        let removed = before - self.waitlist.len();
+
+        let mut event = AuditEvent::new(&our().node, "prune_waitlist")
+            .param("removed", removed)
+            .param_opt("older_than_secs", &request.older_than_secs);
+        if let Some(ref ids) = request.node_ids {
+            event = event.param("node_ids", ids.join(","));
+        }
+        self.audit_ok(event);
+
+        Ok(SuccessRes {
+            success: true,
+            message: format!("Removed {} nodes from the waitlist", removed),
+        })
+    }
+
+    #[http]
+    async fn get_key_deliveries(&self) -> Result<Vec<KeyDelivery>, String> {
+        let mut deliveries: Vec<KeyDelivery> = self.key_deliveries.values().cloned().collect();
+        deliveries.sort_by(|a, b| b.sent_at.cmp(&a.sent_at));
+        Ok(deliveries)
+    }
+
+    #[local]
+    async fn record_key_delivery(&mut self, report: KeyDeliveryReport) -> Result<(), String> {
+        let Some(delivery) = self.key_deliveries.get_mut(&report.node_id) else {
+            return Ok(());
+        };
+        // A newer push to the same node supersedes this one
+        if delivery.push_id != report.push_id {
+            return Ok(());
+        }
+
+        match report.error {
+            None => {
+                delivery.status = "delivered".to_string();
+                delivery.delivered_at = Some(Utc::now().timestamp());
+                delivery.error = None;
+            }
+            Some(ref error) => {
+                println!("Key push to {} failed: {}", report.node_id, error);
+                delivery.status = "failed".to_string();
+                delivery.error = Some(error.clone());
+            }
+        }
+
+        let status = delivery.status.clone();
+        for rotation_move in self.rotations.iter_mut()
+            .flat_map(|r| r.moves.iter_mut())
+            .filter(|m| m.push_id.as_deref() == Some(report.push_id.as_str()))
+        {
+            rotation_move.delivery_status = status.clone();
+            rotation_move.error = report.error.clone();
+        }
+        Ok(())
+    }
+
+    /// Compare the pool with the organization's keys and store the drift report
+    #[http]
+    async fn reconcile_keys(&mut self) -> Result<ReconcileReport, String> {
+        self.reconcile().await
+    }
+
+    #[http]
+    async fn get_reconciliation(&self) -> Result<Option<ReconcileReport>, String> {
+        Ok(self.last_reconciliation.clone())
+    }
+
+    #[http]
+    async fn apply_reconciliation(&mut self, request: ApplyReconcileReq) -> Result<ApplyReconcileRes, String> {
+        // Work from fresh data so we never act on drift that has since resolved
+        let report = self.reconcile().await?;
+
+        let selected: Vec<DriftItem> = report.drift.iter()
+            .filter(|item| match request.drift_ids {
+                Some(ref ids) => ids.contains(&item.id),
+                None => item.kind != "unpooled_upstream",
+            })
+            .cloned()
+            .collect();
+
+        let mut applied = Vec::new();
+        let mut failed = Vec::new();
+        if let Some(ref ids) = request.drift_ids {
+            for id in ids.iter().filter(|id| !report.drift.iter().any(|item| &item.id == *id)) {
+                failed.push((id.clone(), "No longer drifting".to_string()));
+            }
+        }
+
+        for item in selected {
+            let mut event = AuditEvent::new(&our().node, "reconcile_fix")
+                .param("kind", &item.kind)
+                .param("fix", &item.fix);
+            if let Some(ref key_id) = item.key_id {
+                event = event.key(key_id);
+            }
+            if let Some(ref anthropic_key_id) = item.anthropic_key_id {
+                event = event.param("anthropic_key_id", anthropic_key_id);
+            }
+
+            let result = self.apply_drift_fix(&item).await;
+            self.audit(event, &result);
+            match result {
+                Ok(()) => applied.push(item.id),
+                Err(e) => failed.push((item.id, e)),
+            }
+        }
+
+        let report = self.reconcile().await?;
+        Ok(ApplyReconcileRes { applied, failed, report })
+    }
+
+    #[local]
+    async fn run_due_jobs(&mut self, now: i64) -> Result<usize, String> {
+        let due: Vec<String> = self.scheduled_jobs.values()
+            .filter(|job| job.is_due(now))
+            .map(|job| job.name.clone())
+            .collect();
+
+        for name in &due {
+            self.run_job(name).await?;
+        }
+
+        // Costs, expiry and reconciliation all move the pool; catch whatever changed
+        self.serve_waitlist();
+        self.name_dedicated_keys().await;
+        self.check_pool_health().await;
+
+        Ok(due.len())
+    }
+
+    #[http]
+    async fn list_jobs(&self) -> Result<Vec<JobInfo>, String> {
+        let mut jobs: Vec<JobInfo> = self.scheduled_jobs.values().map(job_info).collect();
+        jobs.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(jobs)
+    }
+
+    #[http]
+    async fn trigger_job(&mut self, request: JobReq) -> Result<JobInfo, String> {
+        match self.scheduled_jobs.get(&request.name) {
+            None => return Err(format!("Unknown job: {}", request.name)),
+            Some(job) if job.running => return Err(format!("Job {} is already running", request.name)),
+            Some(_) => {}
+        }
+
+        self.run_job(&request.name).await?;
+
+        let event = AuditEvent::new(&our().node, "trigger_job").param("name", &request.name);
+        self.audit_ok(event);
+
+        Ok(job_info(&self.scheduled_jobs[&request.name]))
+    }
+
+    #[http]
+    async fn update_job(&mut self, request: UpdateJobReq) -> Result<JobInfo, String> {
+        let job = self.scheduled_jobs.get_mut(&request.name)
+            .ok_or_else(|| format!("Unknown job: {}", request.name))?;
+
+        let interval_secs = request.interval_secs.unwrap_or(job.interval_secs);
+        let jitter_secs = request.jitter_secs.unwrap_or(job.jitter_secs);
+        scheduler::validate_timing(interval_secs, jitter_secs)?;
+        job.interval_secs = interval_secs;
+        job.jitter_secs = jitter_secs;
+        if let Some(enabled) = request.enabled {
+            job.enabled = enabled;
+        }
+        job.schedule_next(Utc::now().timestamp());
+        let info = job_info(job);
+
+        let event = AuditEvent::new(&our().node, "update_job")
+            .param("name", &request.name)
+            .param_opt("interval_secs", &request.interval_secs)
+            .param_opt("jitter_secs", &request.jitter_secs)
+            .param_opt("enabled", &request.enabled);
+        self.audit_ok(event);
+
+        Ok(info)
+    }
+
+}
+
+impl AnthropicApiKeyManagerState {
+    async fn add_key(&mut self, request: AddKeyReq) -> Result<SuccessRes, String> {
+        if self.active_keys.contains(&request.api_key) || self.historical_keys.contains(&request.api_key) {
+            return Err("API key already exists".to_string());
+        }
+
+        // Costs are reported per workspace, so a pooled key is only attributable
+        // if we know which workspace it was created in. Look it up before pooling
+        // the key so that a key we can't attribute never silently reports $0
+        let workspace_id = match request.workspace_id {
+            Some(workspace_id) => self.check_workspace_free(&request.api_key, &workspace_id).map(|()| Some(workspace_id)),
+            None if self.admin_api_key.is_some() => self.resolve_key_workspace(&request.api_key).await.map(Some),
+            None => Ok(None),
+        };
+        let workspace_id = match workspace_id {
+            Ok(workspace_id) => workspace_id,
+            Err(e) => {
+                return Ok(SuccessRes {
+                    success: false,
+                    message: format!("API key not added: {}", e),
+                });
+            }
+        };
+
+        self.active_keys.insert(request.api_key.clone());
+
+        let now = Utc::now().timestamp();
+        let ttl = request.ttl_seconds.or(self.default_key_ttl_secs);
+        let meta = self.key_metadata.entry(request.api_key.clone()).or_default();
+        meta.added_at = now;
+        meta.expires_at = ttl.map(|ttl| now + ttl);
+        meta.spend_cap = request.spend_cap;
+        meta.max_nodes = request.max_nodes;
+        meta.workspace_id = workspace_id.clone();
+
+        let message = match workspace_id {
+            Some(workspace_id) => format!("API key {} added successfully (workspace {})", key_id(&request.api_key), workspace_id),
+            None => format!("API key {} added successfully (no admin key, so costs will not be tracked)", key_id(&request.api_key)),
+        };
+
+        Ok(SuccessRes {
+            success: true,
+            message,
+        })
+    }
+
+    async fn map_key_workspace(&mut self, request: SetKeyWorkspaceReq) -> Result<SuccessRes, String> {
+        let api_key = self.resolve_key_id(&request.key_id)?;
+
+        let workspace_id = match request.workspace_id {
+            Some(workspace_id) => {
+                self.check_workspace_free(&api_key, &workspace_id)?;
+                self.key_metadata.entry(api_key).or_default().workspace_id = Some(workspace_id.clone());
+                workspace_id
+            }
+            None => self.resolve_key_workspace(&api_key).await?,
+        };
+
+        // Attribute any costs we already hold for this workspace
+        self.rebuild_key_costs();
+
+        Ok(SuccessRes {
+            success: true,
+            message: format!("API key mapped to workspace {}", workspace_id),
+        })
+    }
+
+    async fn apply_key_ttl(&mut self, request: SetKeyTtlReq) -> Result<SuccessRes, String> {
+        if matches!(request.ttl_seconds, Some(ttl) if ttl <= 0) {
+            return Err("TTL must be positive".to_string());
+        }
+
+        let Some(key_id) = request.key_id else {
+            self.default_key_ttl_secs = request.ttl_seconds;
+            return Ok(SuccessRes {
+                success: true,
+                message: match request.ttl_seconds {
+                    Some(ttl) => format!("Default key TTL set to {} seconds", ttl),
+                    None => "Default key TTL cleared".to_string(),
+                },
+            });
+        };
+
+        let api_key = self.resolve_key_id(&key_id)?;
+        if !self.active_keys.contains(&api_key) {
+            return Err("API key is no longer active".to_string());
+        }
+
+        // The TTL counts from when the key was added, not from now
+        let meta = self.key_metadata.entry(api_key).or_default();
+        meta.expires_at = request.ttl_seconds.map(|ttl| meta.added_at + ttl);
+
+        Ok(SuccessRes {
+            success: true,
+            message: match meta.expires_at {
+                Some(expires_at) => format!("API key expires at {}", format_timestamp(expires_at)),
+                None => "API key expiry cleared".to_string(),
+            },
+        })
+    }
+
+    async fn apply_spend_cap(&mut self, request: SetSpendCapReq) -> Result<SuccessRes, String> {
+        if matches!(request.spend_cap, Some(cap) if cap <= 0.0) {
+            return Err("Spend cap must be positive".to_string());
+        }
+
+        let Some(key_id) = request.key_id else {
+            self.default_spend_cap = request.spend_cap;
+            self.enforce_spend_caps().await;
+            return Ok(SuccessRes {
+                success: true,
+                message: match request.spend_cap {
+                    Some(cap) => format!("Default spend cap set to ${:.2}", cap),
+                    None => "Default spend cap cleared".to_string(),
+                },
+            });
+        };
+
+        let api_key = self.resolve_key_id(&key_id)?;
+        if !self.active_keys.contains(&api_key) {
+            return Err("API key is no longer active".to_string());
+        }
+
+        self.key_metadata.entry(api_key).or_default().spend_cap = request.spend_cap;
+
+        // Lowering a cap below what the key already spent retires it right away
+        self.enforce_spend_caps().await;
+
+        Ok(SuccessRes {
+            success: true,
+            message: match request.spend_cap {
+                Some(cap) => format!("Spend cap set to ${:.2}", cap),
+                None => "Spend cap cleared".to_string(),
+            },
+        })
+    }
+
+    async fn remove_key(&mut self, request: RemoveKeyReq) -> Result<SuccessRes, String> {
+        let api_key = self.resolve_key_id(&request.key_id)?;
+        if !self.active_keys.contains(&api_key) {
+            return Err("API key is no longer active".to_string());
+        }
+
+        self.active_keys.remove(&api_key);
+        self.historical_keys.insert(api_key.clone());
+
+        // Dated so the re-issue cooldown can run from it
+        let meta = self.key_metadata.entry(api_key).or_default();
+        meta.retired_at = Some(Utc::now().timestamp());
+        meta.retired_reason = Some("removed".to_string());
+
+        Ok(SuccessRes {
+            success: true,
+            message: "API key removed successfully".to_string(),
+        })
+    }
+
+    fn clear_costs(&mut self) -> Result<SuccessRes, String> {
+        if self.admin_api_key.is_none() {
+            return Err("Admin API key not configured".to_string());
+        }
+
+        // Clear all cost data
+        self.all_costs.clear();
+        self.key_costs.clear();
+        self.last_cost_query_date = None;
+        self.last_cost_check = None;
+
+        println!("Cost data reset. All historical cost data cleared.");
+
+        Ok(SuccessRes {
+            success: true,
+            message: "Cost data reset successfully. All historical data cleared.".to_string(),
+        })
+    }
+
+    /// Assign a key from the pool to a node that doesn't have one yet
+    fn issue_key(&mut self, node_id: &str) -> Result<String, String> {
+        if self.active_keys.is_empty() {
+            return Err("No active API keys available".to_string());
+        }
+
+        let now = Utc::now().timestamp();
+        // Keys that couldn't be decrypted on load are unusable until the sealing key is back
+        let issuable: Vec<&String> = self.active_keys.iter().filter(|key| !sealed::is_sealed(key)).collect();
+        if issuable.is_empty() {
+            return Err("No active API keys available".to_string());
+        }
+        let full_keys = issuable.iter().filter(|key| self.key_is_full(key)).count();
+        if full_keys == issuable.len() {
+            return Err(format!("Pool at capacity: all {} active keys have reached their node limit", full_keys));
+        }
+        let recent_start = format_timestamp(now - selection::RECENT_SPEND_WINDOW_SECS);
+
+        // Sorted so round-robin walks the pool in a stable order
+        let mut candidates: Vec<Candidate> = issuable.into_iter()
+            .filter(|key| !self.key_is_full(key))
+            .map(|key| Candidate {
+                key: key.clone(),
+                nodes: self.key_to_nodes.get(key).map(|n| n.len() as u64).unwrap_or(0),
+                recent_spend: self.key_total_cost(key, &Some(recent_start.clone()), &None),
+                weight: self.key_weight(key),
+            })
+            .collect();
+        candidates.sort_by_key(|c| key_id(&c.key));
+
+        let strategy = self.selection_strategy.clone();
+        let index = selection::strategy(&strategy)
+            .pick(&candidates, &mut self.selection_cursor)
+            .ok_or_else(|| format!("{:?} strategy found no eligible key", strategy))?;
+        let selected_key = candidates.swap_remove(index).key;
+
+        self.key_to_nodes
+            .entry(selected_key.clone())
+            .or_insert_with(Vec::new)
+            .push(node_id.to_string());
+
+        self.node_selection.insert(node_id.to_string(), strategy);
+
+        // A new grant, keeping what the node told us about its app
+        let previous = self.grant_history.get(node_id).and_then(|h| h.last());
+        let grant = NodeGrant {
+            grant_id: grant::new_grant_id(),
+            key_id: key_id(&selected_key),
+            issued_at: now,
+            app_name: previous.and_then(|g| g.app_name.clone()),
+            app_version: previous.and_then(|g| g.app_version.clone()),
+            reissue: previous.map_or(false, |g| g.end_reason.as_deref() == Some(grant::REVOKED)),
+            ended_at: None,
+            end_reason: None,
+            lease_expires_at: self.lease_policy.lease_secs.map(|secs| now + secs),
+            renewed_at: None,
+        };
+        self.node_grants.insert(node_id.to_string(), grant);
+
+        Ok(selected_key)
+    }
+
+    /// Add a node to the back of the waitlist, or keep its place if it is
+    /// already queued. Returns its position.
+    fn enqueue_waitlist(&mut self, node_id: &str, address: String, reason: &str, invite_code: Option<String>) -> usize {
+        if let Some(position) = self.waitlist.iter().position(|e| e.node_id == node_id) {
+            self.waitlist[position].address = address;
+            return position;
+        }
+
+        println!("Waitlisting {}: {}", node_id, reason);
+        self.waitlist.push_back(WaitlistEntry {
+            node_id: node_id.to_string(),
+            address,
+            requested_at: Utc::now().timestamp(),
+            reason: reason.to_string(),
+            invite_code,
+        });
+        self.waitlist.len() - 1
+    }
+
+    /// Assign keys to waiting nodes in order, for as long as the pool has room,
+    /// and push each key to its node
+    fn serve_waitlist(&mut self) {
+        if self.issuance_paused_until.map_or(false, |until| until > Utc::now().timestamp()) {
+            return;
+        }
+
+        while let Some(entry) = self.waitlist.front().cloned() {
+            // Served some other way in the meantime
+            if self.find_key_for_node(&entry.node_id).is_some() {
+                self.waitlist.pop_front();
+                continue;
+            }
+
+            // Pool-wide shortage; nobody behind this node can be served either
+            let Ok(key) = self.issue_key(&entry.node_id) else {
+                break;
+            };
+            self.waitlist.pop_front();
+
+            let event = AuditEvent::new(audit::SYSTEM_ACTOR, "issue_api_key")
+                .node(&entry.node_id)
+                .key(&key_id(&key))
+                .param("strategy", format!("{:?}", self.selection_strategy))
+                .param("via", "waitlist");
+            self.audit_ok(event);
+
+            println!("Assigned a key to waitlisted node {}", entry.node_id);
+            if let Some(ref code) = entry.invite_code {
+                self.record_redemption(code, &entry.node_id, true);
+            }
+            self.record_issuance(&entry.node_id);
+            self.node_addresses.entry(entry.node_id.clone()).or_insert(entry.address);
+            self.push_key(&entry.node_id, &key, "waitlist");
+        }
+    }
+
+    /// Invite codes matching the list filters, newest first
+    fn matching_invites(&self, request: &ListInvitesReq, now: i64) -> Vec<&InviteCode> {
+        let mut matching: Vec<&InviteCode> = self.invite_codes.iter()
+            .filter(|i| request.batch_id.as_ref().map_or(true, |b| *b == i.batch_id))
+            .filter(|i| request.campaign.is_none() || request.campaign == i.campaign)
+            .filter(|i| request.status.as_deref().map_or(true, |s| s == invites::status(i, now)))
+            .collect();
+        matching.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        matching
+    }
+
+    /// Hand a node its key: the one it already holds, or a new one if it's
+    /// allowed one. `invite_code` is redeemed only when a new key is needed.
+    async fn grant_key(&mut self, node_id: &str, invite_code: Option<&str>) -> Result<String, Refusal> {
+        if let Some(ban) = bans::active_ban(&self.bans, node_id, Utc::now().timestamp()).cloned() {
+            let message = format!("Node is banned: {}", ban.reason);
+            let event = AuditEvent::new(node_id, "issue_api_key")
+                .node(node_id)
+                .param("ban_id", &ban.ban_id);
+            self.audit::<()>(event, &Err(message.clone()));
+            return Err(Refusal::new(GrantStatus::Banned, message));
+        }
+
+        self.node_addresses.insert(node_id.to_string(), source().to_string());
+
+        // A lease that ran out since the last sweep doesn't count as a held key,
+        // and its slot may go to this node if it is waiting
+        if self.expire_leases(Utc::now().timestamp()) > 0 {
+            self.serve_waitlist();
+        }
+
+        if let Some(existing_key) = self.find_key_for_node(node_id) {
+            if self.active_keys.contains(&existing_key) {
+                if sealed::is_sealed(&existing_key) {
+                    let message = "This node's key can't be decrypted by the manager right now".to_string();
+                    self.audit::<()>(AuditEvent::new(node_id, "issue_api_key").node(node_id), &Err(message.clone()));
+                    return Err(Refusal::new(GrantStatus::IssuancePaused, message));
+                }
+                return Ok(existing_key);
+            }
+
+            // The node was let in before, so a re-issue skips invites and rate limits
+            self.check_reissue(node_id, &existing_key)?;
+            self.release_retired_key(node_id, &existing_key);
+            return self.admit_node(node_id, None).await;
+        }
+
+        // Nodes already waiting were let in when they joined the queue
+        if let Some(refusal) = self.waitlist_refusal(node_id) {
+            return Err(refusal);
+        }
+
+        // Nodes that let their lease lapse or gave their key back were let in
+        // before, so they come back without an invite or a rate limit check
+        if self.is_returning_node(node_id) {
+            return self.admit_node(node_id, None).await;
+        }
+
+        let invite = match invite_code {
+            Some(code) => Some(self.check_invite(node_id, code)?),
+            None if self.invite_required => {
+                let message = "An invite code is required to get a key".to_string();
+                self.audit::<()>(AuditEvent::new(node_id, "issue_api_key").node(node_id), &Err(message.clone()));
+                return Err(Refusal::new(GrantStatus::InviteRequired, message));
+            }
+            None => {
+                if let Some(denial) = self.rate_limit_denial(node_id) {
+                    let event = AuditEvent::new(node_id, "issue_api_key")
+                        .node(node_id)
+                        .param("limit", &denial.limit);
+                    self.audit::<()>(event, &Err(denial.message.clone()));
+                    self.check_pool_health().await;
+                    return Err(Refusal::from_denial(denial));
+                }
+                None
+            }
+        };
+
+        self.admit_node(node_id, invite).await
+    }
+
+    /// Whether the node's last grant ended on its own terms rather than being taken away
+    fn is_returning_node(&self, node_id: &str) -> bool {
+        self.grant_history.get(node_id)
+            .and_then(|h| h.last())
+            .and_then(|g| g.end_reason.as_deref())
+            .map_or(false, |reason| reason == grant::LEASE_EXPIRED || reason == grant::RELINQUISHED)
+    }
+
+    /// Refuse a new key to a node whose key was retired unless the re-issue
+    /// policy allows one now
+    fn check_reissue(&self, node_id: &str, retired_key: &str) -> Result<(), Refusal> {
+        let policy = &self.reissue_policy;
+        let message = self.retired_key_message(retired_key);
+        if !policy.allow_reissue {
+            return Err(Refusal::new(GrantStatus::KeyRetired, message));
+        }
+
+        let reissues = self.node_grant_history(node_id).iter().filter(|g| g.reissue).count() as u32;
+        if let Some(max) = policy.max_reissues.filter(|max| reissues >= *max) {
+            return Err(Refusal::new(GrantStatus::KeyRetired,
+                format!("{}; this node has had all {} replacement keys it is allowed", message, max)));
+        }
+
+        let retired_at = self.key_metadata.get(retired_key).and_then(|m| m.retired_at);
+        if let (Some(cooldown), Some(retired_at)) = (policy.cooldown_secs, retired_at) {
+            let wait = retired_at + cooldown - Utc::now().timestamp();
+            if wait > 0 {
+                let mut refusal = Refusal::new(GrantStatus::KeyRetired,
+                    format!("{}; a replacement can be requested in {} seconds", message, wait));
+                refusal.retry_after_secs = Some(wait);
+                return Err(refusal);
+            }
+        }
+        Ok(())
+    }
+
+    /// Detach a node from its retired key, ending the grant as of the key's retirement
+    fn release_retired_key(&mut self, node_id: &str, retired_key: &str) {
+        if let Some(nodes) = self.key_to_nodes.get_mut(retired_key) {
+            nodes.retain(|n| n != node_id);
+        }
+        let meta = self.key_metadata.get(retired_key);
+        let ended_at = meta.and_then(|m| m.retired_at).unwrap_or_else(|| Utc::now().timestamp());
+        self.current_grant(node_id, retired_key);
+        self.end_grant(node_id, grant::REVOKED, ended_at);
+    }
+
+    /// Take a key back from the node that gave it up. A retired key's grant
+    /// still ends as revoked, so the node stays under the re-issue policy.
+    fn relinquish_key(&mut self, node_id: &str, key: &str) {
+        if !self.active_keys.contains(key) {
+            self.release_retired_key(node_id, key);
+            return;
+        }
+        if let Some(nodes) = self.key_to_nodes.get_mut(key) {
+            nodes.retain(|n| n != node_id);
+        }
+        self.end_grant(node_id, grant::RELINQUISHED, Utc::now().timestamp());
+    }
+
+    /// Take keys back from nodes whose lease ran out, freeing their slots.
+    /// Holders of retired keys are left alone so they stay under the re-issue
+    /// policy rather than coming back as new nodes.
+    fn expire_leases(&mut self, now: i64) -> usize {
+        let lapsed: Vec<(String, String, i64)> = self.key_to_nodes.iter()
+            .filter(|(key, _)| self.active_keys.contains(*key))
+            .flat_map(|(key, nodes)| nodes.iter().map(move |node| (key, node)))
+            .filter_map(|(key, node)| {
+                let expires_at = self.node_grants.get(node)?.lease_expires_at?;
+                (expires_at <= now).then(|| (key.clone(), node.clone(), expires_at))
+            })
+            .collect();
+
+        for (key, node, expires_at) in &lapsed {
+            if let Some(nodes) = self.key_to_nodes.get_mut(key) {
+                nodes.retain(|n| n != node);
+            }
+            self.end_grant(node, grant::LEASE_EXPIRED, *expires_at);
+            println!("Lease of {} on key {} expired", node, key_id(key));
+            self.audit_ok(AuditEvent::new(audit::SYSTEM_ACTOR, "expire_lease")
+                .node(node)
+                .key(&key_id(key)));
+        }
+        lapsed.len()
+    }
+
+    /// Move a node's current grant into its history
+    fn end_grant(&mut self, node_id: &str, reason: &str, ended_at: i64) {
+        self.node_selection.remove(node_id);
+        if let Some(mut grant) = self.node_grants.remove(node_id) {
+            grant.ended_at = Some(ended_at);
+            grant.end_reason = Some(reason.to_string());
+            self.grant_history.entry(node_id.to_string()).or_default().push(grant);
+        }
+    }
+
+    /// Every grant a node has had, oldest first, ending with the current one
+    fn node_grant_history(&self, node_id: &str) -> Vec<NodeGrant> {
+        self.grant_history.get(node_id)
+            .into_iter()
+            .flatten()
+            .chain(self.node_grants.get(node_id))
+            .cloned()
+            .collect()
+    }
+
+    /// Where a node stands without issuing it anything
+    fn grant_state(&self, node_id: &str) -> Result<String, Refusal> {
+        if let Some(ban) = bans::active_ban(&self.bans, node_id, Utc::now().timestamp()) {
+            return Err(Refusal::new(GrantStatus::Banned, format!("Node is banned: {}", ban.reason)));
+        }
+        if let Some(key) = self.find_key_for_node(node_id) {
+            if !self.active_keys.contains(&key) {
+                return Err(Refusal::new(GrantStatus::KeyRetired, self.retired_key_message(&key)));
+            }
+            if sealed::is_sealed(&key) {
+                return Err(Refusal::new(GrantStatus::IssuancePaused,
+                    "This node's key can't be decrypted by the manager right now"));
+            }
+            return Ok(key);
+        }
+        if let Some(refusal) = self.waitlist_refusal(node_id) {
+            return Err(refusal);
+        }
+        let lapsed = self.grant_history.get(node_id)
+            .and_then(|h| h.last())
+            .filter(|g| g.end_reason.as_deref() == Some(grant::LEASE_EXPIRED));
+        if let Some(grant) = lapsed {
+            return Err(Refusal::new(GrantStatus::NotGranted,
+                format!("Your lease expired at {}; request a new grant to use the pool again",
+                        format_timestamp(grant.ended_at.unwrap_or(0)))));
+        }
+        Err(Refusal::new(GrantStatus::NotGranted, "No key has been granted to this node"))
+    }
+
+    /// Where the node stands in the waitlist, if it is queued
+    fn waitlist_refusal(&self, node_id: &str) -> Option<Refusal> {
+        let position = self.waitlist.iter().position(|e| e.node_id == node_id)? as u64 + 1;
+        let mut refusal = Refusal::new(GrantStatus::Waitlisted,
+            format!("Waiting for a key at position {}", position));
+        refusal.waitlist_position = Some(position);
+        Some(refusal)
+    }
+
+    /// Index of a redeemable invite code. Invites are the admin's say-so, so
+    /// nodes redeeming one skip the rate limits.
+    fn check_invite(&mut self, node_id: &str, code: &str) -> Result<usize, Refusal> {
+        let now = Utc::now().timestamp();
+        let wanted = invites::normalize(code);
+        let result = match self.invite_codes.iter().position(|i| invites::normalize(&i.code) == wanted) {
+            None => Err("Unknown invite code".to_string()),
+            Some(index) => match invites::status(&self.invite_codes[index], now) {
+                "active" if self.invite_uses(index) >= self.invite_codes[index].max_uses as usize => {
+                    Err("Invite code is used up".to_string())
+                }
+                "active" => Ok(index),
+                status => Err(format!("Invite code is {}", status.replace('_', " "))),
+            },
+        };
+        if result.is_err() {
+            self.audit(AuditEvent::new(node_id, "redeem_invite").node(node_id), &result);
+        }
+        result.map_err(|message| Refusal::new(GrantStatus::InviteInvalid, message))
+    }
+
+    /// Issue a key to a new node, or waitlist it if the pool is full. `invite`
+    /// is the index of the invite code being redeemed, if any.
+    async fn admit_node(&mut self, node_id: &str, invite: Option<usize>) -> Result<String, Refusal> {
+        let result = self.admit(node_id, source().to_string(), invite);
+        if result.is_ok() {
+            self.name_dedicated_keys().await;
+        }
+        self.check_pool_health().await;
+        result
+    }
+
+    /// `admit_node` short of the upstream calls. `address` is where to push
+    /// the key if the node ends up waitlisted.
+    fn admit(&mut self, node_id: &str, address: String, invite: Option<usize>) -> Result<String, Refusal> {
+        // Nodes already waiting go first
+        self.serve_waitlist();
+
+        // This node may have been one of them
+        if let Some(key) = self.find_key_for_node(node_id) {
+            return Ok(key);
+        }
+
+        let result = self.issue_key(node_id);
+        let mut event = AuditEvent::new(node_id, "issue_api_key")
+            .node(node_id)
+            .param("strategy", format!("{:?}", self.selection_strategy));
+        if let Ok(ref key) = result {
+            event = event.key(&key_id(key));
+        }
+        if let Some(index) = invite {
+            let invite = &self.invite_codes[index];
+            event = event.param("invite_batch", &invite.batch_id)
+                .param_opt("campaign", &invite.campaign);
+        }
+        self.audit(event, &result);
+
+        // The code is redeemed once a key is issued, which for a waitlisted
+        // node is when it reaches the front of the queue
+        let invite_code = invite.map(|index| self.invite_codes[index].code.clone());
+        match result {
+            Ok(key) => {
+                self.waitlist.retain(|e| e.node_id != node_id);
+                if let Some(ref code) = invite_code {
+                    self.record_redemption(code, node_id, false);
+                }
+                self.record_issuance(node_id);
+                Ok(key)
+            }
+            Err(reason) => {
+                let position = self.enqueue_waitlist(node_id, address, &reason, invite_code) + 1;
+                let mut refusal = Refusal::new(GrantStatus::Waitlisted,
+                    format!("{}. Queued on the waitlist at position {}; the key will be sent when one is available",
+                            reason, position));
+                refusal.waitlist_position = Some(position as u64);
+                Err(refusal)
+            }
+        }
+    }
+
+    /// Redemptions of an invite code, counting nodes holding a waitlist spot with it
+    fn invite_uses(&self, index: usize) -> usize {
+        let invite = &self.invite_codes[index];
+        let waiting = self.waitlist.iter().filter(|e| e.invite_code.as_ref() == Some(&invite.code)).count();
+        invite.redemptions.len() + waiting
+    }
+
+    /// Count a use of an invite code
+    fn record_redemption(&mut self, code: &str, node_id: &str, waitlisted: bool) {
+        if let Some(invite) = self.invite_codes.iter_mut().find(|i| i.code == code) {
+            invite.redemptions.push(InviteRedemption {
+                node_id: node_id.to_string(),
+                redeemed_at: Utc::now().timestamp(),
+                waitlisted,
+            });
+        }
+    }
+
+    /// The node's grant for the key it holds, recorded now for assignments
+    /// made before grants were tracked
+    fn current_grant(&mut self, node_id: &str, key: &str) -> NodeGrant {
+        let key_id = key_id(key);
+        if let Some(grant) = self.node_grants.get(node_id).filter(|g| g.key_id == key_id) {
+            return grant.clone();
+        }
+
+        let grant = NodeGrant {
+            grant_id: grant::new_grant_id(),
+            key_id,
+            issued_at: self.node_issue_times.get(node_id).copied().unwrap_or(0),
+            app_name: None,
+            app_version: None,
+            reissue: false,
+            ended_at: None,
+            end_reason: None,
+            lease_expires_at: self.lease_policy.lease_secs.map(|secs| Utc::now().timestamp() + secs),
+            renewed_at: None,
+        };
+        self.node_grants.insert(node_id.to_string(), grant.clone());
+        grant
+    }
+
+    /// Describe the outcome of a key request as a grant
+    fn key_grant(&mut self, node_id: &str, result: Result<String, Refusal>) -> KeyGrant {
+        let held_key = match result {
+            Ok(ref key) => Some(key.clone()),
+            Err(ref refusal) if refusal.status == GrantStatus::KeyRetired => self.find_key_for_node(node_id),
+            Err(_) => None,
+        };
+        let grant = held_key.as_ref().map(|key| self.current_grant(node_id, key));
+        let meta = held_key.as_ref().and_then(|key| self.key_metadata.get(key));
+
+        let mut key_grant = KeyGrant {
+            protocol_version: grant::PROTOCOL_VERSION,
+            status: GrantStatus::Active,
+            grant_id: grant.as_ref().map(|g| g.grant_id.clone()),
+            api_key: None,
+            key_id: grant.as_ref().map(|g| g.key_id.clone()),
+            issued_at: grant.as_ref().map(|g| g.issued_at),
+            expires_at: meta.and_then(|m| m.expires_at),
+            spend_cap: held_key.as_ref().and_then(|key| self.effective_spend_cap(key)),
+            message: None,
+            retry_after_secs: None,
+            waitlist_position: None,
+            lease_expires_at: grant.as_ref().and_then(|g| g.lease_expires_at),
+        };
+        match result {
+            Ok(key) => key_grant.api_key = Some(key),
+            Err(refusal) => {
+                key_grant.status = refusal.status;
+                key_grant.message = Some(refusal.message);
+                key_grant.retry_after_secs = refusal.retry_after_secs;
+                key_grant.waitlist_position = refusal.waitlist_position;
+            }
+        }
+        key_grant
+    }
+
+    /// Why a new node can't have a key right now, if the rate limits say so.
+    /// Trips the burst pause when issuance has spiked.
+    fn rate_limit_denial(&mut self, node_id: &str) -> Option<rate_limit::Denial> {
+        let now = Utc::now().timestamp();
+        rate_limit::prune(&mut self.recent_issuances, now);
+        while self.recent_denials.front().map_or(false, |d| d.at <= now - rate_limit::DAY_SECS) {
+            self.recent_denials.pop_front();
+        }
+
+        if self.issuance_paused_until.map_or(true, |until| until <= now)
+            && rate_limit::is_burst(&self.rate_limits, &self.recent_issuances, self.burst_counted_from, now)
+        {
+            let pause = self.rate_limits.burst_pause_secs.unwrap_or(rate_limit::DEFAULT_BURST_PAUSE_SECS);
+            println!("Issuance burst detected, pausing new issuance for {}s", pause);
+            self.issuance_paused_until = Some(now + pause);
+            self.burst_counted_from = now + pause;
+            self.audit_ok(AuditEvent::new(audit::SYSTEM_ACTOR, "pause_issuance")
+                .param("pause_secs", pause));
+        }
+
+        let denial = match self.issuance_paused_until {
+            Some(until) if until > now => Some(rate_limit::Denial {
+                code: rate_limit::ISSUANCE_PAUSED.to_string(),
+                limit: rate_limit::BURST.to_string(),
+                retry_after_secs: Some(until - now),
+                message: "Key issuance is paused after a spike in new nodes".to_string(),
+            }),
+            _ => rate_limit::check(&self.rate_limits, &self.recent_issuances, node_id, now),
+        };
+
+        if let Some(ref denial) = denial {
+            self.recent_denials.push_back(RateLimitDenial {
+                at: now,
+                node_id: node_id.to_string(),
+                limit: denial.limit.clone(),
+            });
+        }
+        denial
+    }
+
+    fn record_issuance(&mut self, node_id: &str) {
+        // Rate limits are about new nodes; one that held a grant before isn't
+        if self.grant_history.contains_key(node_id) {
+            return;
+        }
+        self.recent_issuances.push_back(Issuance {
+            at: Utc::now().timestamp(),
+            node_id: node_id.to_string(),
+            namespace: rate_limit::parent_namespace(node_id),
+        });
+    }
+
+    /// Send a key to the address the node last requested from, tracking the
+    /// delivery. Returns the push id.
+    fn push_key(&mut self, node_id: &str, key: &str, reason: &str) -> String {
+        let now = Utc::now().timestamp();
+        let push_id = push::new_push_id();
+        let mut delivery = KeyDelivery {
+            node_id: node_id.to_string(),
+            push_id: push_id.clone(),
+            key_id: key_id(key),
+            reason: reason.to_string(),
+            sent_at: now,
+            status: "pending".to_string(),
+            delivered_at: None,
+            error: None,
+        };
+
+        let address = self.node_addresses.get(node_id).cloned().unwrap_or_default();
+        match address.parse::<Address>() {
+            Ok(target) => push::deliver(target, KeyPush {
+                push_id: push_id.clone(),
+                api_key: key.to_string(),
+                issued_at: now,
+                reason: reason.to_string(),
+            }),
+            Err(e) => {
+                // The node still gets its new key the next time it asks
+                delivery.status = "failed".to_string();
+                delivery.error = Some(format!("No usable address for node ({:?})", e));
+            }
+        }
+        self.key_deliveries.insert(node_id.to_string(), delivery);
+        push_id
+    }
+
+    fn key_loads(&self) -> Vec<KeyLoad> {
+        self.active_keys.iter()
+            .map(|key| KeyLoad {
+                nodes: self.key_to_nodes.get(key).map(|n| n.len() as u64).unwrap_or(0),
+                spent: self.key_total_cost(key, &None, &None),
+                cap: self.effective_spend_cap(key),
+                // The alert threshold stands in for keys without a hard limit
+                max_nodes: self.effective_max_nodes(key).or(self.pool_thresholds.max_nodes_per_key),
+            })
+            .collect()
+    }
+
+    /// Raise alerts for thresholds the pool has crossed and resolve the ones it
+    /// has recovered from. Newly raised alerts go to the webhook, if configured.
+    async fn check_pool_health(&mut self) {
+        let loads = self.key_loads();
+        let thresholds = self.pool_thresholds.clone();
+        let capacity = pool_health::projected_capacity(&loads);
+        let capacity_note = match capacity {
+            Some(nodes) => format!("the pool can absorb about {} more nodes", nodes),
+            None => "pool capacity is not bounded".to_string(),
+        };
+
+        let mut conditions: Vec<(&str, String)> = Vec::new();
+        if loads.is_empty() {
+            conditions.push((pool_health::POOL_EMPTY, "No active API keys; new nodes can't get a key".to_string()));
+        } else if self.active_keys.iter().all(|key| self.key_is_full(key)) {
+            conditions.push((pool_health::POOL_AT_CAPACITY,
+                format!("All {} active keys have reached their node limit; new nodes can't get a key", loads.len())));
+        }
+        if let Some(min) = thresholds.min_active_keys {
+            if !loads.is_empty() && (loads.len() as u64) < min {
+                conditions.push((pool_health::LOW_ACTIVE_KEYS,
+                    format!("{} active keys, below the minimum of {}; {}", loads.len(), min, capacity_note)));
+            }
+        }
+        if let Some(max) = thresholds.max_nodes_per_key {
+            let over = loads.iter().filter(|l| l.nodes > max).count();
+            if over > 0 {
+                conditions.push((pool_health::KEY_OVER_NODE_LIMIT,
+                    format!("{} keys carry more than {} nodes; {}", over, max, capacity_note)));
+            }
+        }
+        if let (Some(min), Some(headroom)) = (thresholds.min_spend_headroom, pool_health::spend_headroom(&loads)) {
+            if headroom < min {
+                conditions.push((pool_health::LOW_SPEND_HEADROOM,
+                    format!("${:.2} of spend headroom left, below ${:.2}; {}", headroom, min, capacity_note)));
+            }
+        }
+
+        let now = Utc::now().timestamp();
+        if let Some(until) = self.issuance_paused_until.filter(|until| *until > now) {
+            conditions.push((rate_limit::ISSUANCE_PAUSED,
+                format!("New-node issuance paused after a burst, until {}", format_timestamp(until))));
+        }
+        for alert in self.pool_alerts.iter_mut().filter(|a| a.resolved_at.is_none()) {
+            match conditions.iter().find(|(kind, _)| *kind == alert.kind) {
+                Some((_, message)) => alert.message = message.clone(),
+                None => {
+                    println!("Pool alert resolved: {}", alert.kind);
+                    alert.resolved_at = Some(now);
+                }
+            }
+        }
+
+        for (kind, message) in conditions {
+            if self.pool_alerts.iter().any(|a| a.kind == kind && a.resolved_at.is_none()) {
+                continue;
+            }
+            println!("Pool alert raised: {}: {}", kind, message);
+            let notified = self.notify_alert(kind, &message, capacity, now).await;
+            self.pool_alerts.push(PoolAlert {
+                kind: kind.to_string(),
+                message,
+                raised_at: now,
+                resolved_at: None,
+                notified,
+            });
+        }
+
+        // Keep the newest resolved alerts only
+        let resolved = self.pool_alerts.iter().filter(|a| a.resolved_at.is_some()).count();
+        let mut to_drop = resolved.saturating_sub(pool_health::RESOLVED_ALERTS_KEPT);
+        self.pool_alerts.retain(|a| {
+            if to_drop > 0 && a.resolved_at.is_some() {
+                to_drop -= 1;
+                return false;
+            }
+            true
+        });
+    }
+
+    async fn notify_alert(&self, kind: &str, message: &str, capacity: Option<u64>, raised_at: i64) -> bool {
+        let Some(ref url) = self.alert_webhook_url else {
+            return false;
+        };
+
+        let payload = serde_json::json!({
+            "node": our().node,
+            "kind": kind,
+            "message": message,
+            "projected_capacity": capacity,
+            "raised_at": raised_at,
+        });
+        match pool_health::notify_webhook(url, &payload).await {
+            Ok(()) => true,
+            Err(e) => {
+                println!("Failed to deliver pool alert: {}", e);
+                false
+            }
+        }
+    }
+
+    fn audit<T>(&mut self, event: AuditEvent, result: &Result<T, String>) {
+        let error = result.as_ref().err().cloned();
+        self.append_audit(event, error);
+    }
+
+    fn audit_ok(&mut self, event: AuditEvent) {
+        self.append_audit(event, None);
+    }
+
+    fn append_audit(&mut self, event: AuditEvent, error: Option<String>) {
+        // Without the sealing key state can't be saved either, so the entry
+        // would be lost anyway
+        match sealed::derive_subkey(audit::KEY_LABEL) {
+            Ok(key) => audit::append(&mut self.audit_log, &mut self.audit_anchor, &key, event,
+                                     Utc::now().timestamp(), error),
+            Err(e) => println!("Dropping audit entry for {}: audit key unavailable: {}", event.action, e),
+        }
+    }
+
+    /// Run a scheduled job now, recording its outcome and scheduling the next run
+    async fn run_job(&mut self, name: &str) -> Result<(), String> {
+        let started_at = Utc::now();
+        match self.scheduled_jobs.get_mut(name) {
+            Some(job) => job.running = true,
+            None => return Err(format!("Unknown job: {}", name)),
+        }
+
+        let result = match name {
+            scheduler::COST_REFRESH_JOB => self.run_cost_refresh_job().await,
+            scheduler::KEY_EXPIRY_JOB => {
+                let retired = self.sweep_expired_keys(started_at.timestamp()).await;
+                JobResult::Success(format!("Retired {} expired keys", retired))
+            }
+            scheduler::KEY_RECONCILE_JOB => self.run_reconcile_job().await,
+            scheduler::KEY_ROTATION_JOB => self.run_rotation_job(started_at.timestamp()).await,
+            scheduler::LEASE_EXPIRY_JOB => {
+                let expired = self.expire_leases(started_at.timestamp());
+                JobResult::Success(format!("Expired {} leases", expired))
+            }
+            scheduler::AUDIT_PRUNE_JOB => self.run_audit_prune_job(started_at.timestamp()),
+            _ => JobResult::Failed(format!("No handler for job {}", name)),
+        };
+
+        if let JobResult::Failed(ref e) = result {
+            println!("Scheduled job {} failed: {}", name, e);
+        }
+
+        let finished_at = Utc::now();
+        let duration_ms = (finished_at - started_at).num_milliseconds().max(0) as u64;
+        if let Some(job) = self.scheduled_jobs.get_mut(name) {
+            job.running = false;
+            job.record(started_at.timestamp(), duration_ms, result);
+            job.schedule_next(finished_at.timestamp());
+        }
+
+        Ok(())
+    }
+
+    fn run_audit_prune_job(&mut self, now: i64) -> JobResult {
+        let Some(keep) = self.audit_retention else {
+            return JobResult::Skipped("No audit retention limit set".to_string());
+        };
+        match sealed::derive_subkey(audit::KEY_LABEL) {
+            Ok(key) => {
+                let pruned = audit::prune(&mut self.audit_log, &mut self.audit_anchor, &key, keep as usize, now);
+                JobResult::Success(format!("Pruned {} audit entries", pruned))
+            }
+            Err(e) => JobResult::Failed(format!("Audit key unavailable: {}", e)),
+        }
+    }
+
+    async fn run_cost_refresh_job(&mut self) -> JobResult {
+        if self.admin_api_key.is_none() {
+            return JobResult::Skipped("Admin API key not configured".to_string());
+        }
+
+        match self.fetch_costs_from_anthropic().await {
+            Ok(costs_added) => {
+                self.last_cost_check = Some(Utc::now().timestamp());
+                JobResult::Success(format!("Added or updated {} cost records", costs_added))
+            }
+            Err(e) => JobResult::Failed(e),
+        }
+    }
+
+    async fn run_reconcile_job(&mut self) -> JobResult {
+        if self.admin_api_key.is_none() {
+            return JobResult::Skipped("Admin API key not configured".to_string());
+        }
+
+        match self.reconcile().await {
+            Ok(report) if report.drift.is_empty() => {
+                JobResult::Success(format!("Pool matches the organization ({} keys)", report.matched_keys))
+            }
+            Ok(report) => JobResult::Success(format!("{} drift items found", report.drift.len())),
+            Err(e) => JobResult::Failed(e),
+        }
+    }
+
+    async fn run_rotation_job(&mut self, now: i64) -> JobResult {
+        let Some(max_age) = self.rotation_policy.max_key_age_secs else {
+            return JobResult::Skipped("Scheduled rotation is disabled".to_string());
+        };
+        let grace_secs = self.rotation_policy.grace_secs.unwrap_or(DEFAULT_ROTATION_GRACE_SECS);
+
+        let mut due: Vec<String> = self.active_keys.iter()
+            .filter(|key| self.key_metadata.get(*key).map(|m| m.added_at + max_age <= now).unwrap_or(false))
+            .cloned()
+            .collect();
+        due.sort_by_key(|key| self.key_metadata.get(key).map(|m| m.added_at).unwrap_or(0));
+
+        let mut rotated = 0;
+        let mut deferred = 0;
+        for key in due {
+            // Only rotate when the rest of the pool can take this key's nodes
+            let nodes = self.key_to_nodes.get(&key).map(|n| n.len() as u64).unwrap_or(0);
+            let room: Option<u64> = self.active_keys.iter()
+                .filter(|other| **other != key)
+                .map(|other| self.effective_max_nodes(other).map(|max| {
+                    max.saturating_sub(self.key_to_nodes.get(other).map(|n| n.len() as u64).unwrap_or(0))
+                }))
+                .sum();
+            let has_others = self.active_keys.len() > 1;
+            if nodes > 0 && (!has_others || room.map(|room| room < nodes).unwrap_or(false)) {
+                deferred += 1;
+                continue;
+            }
+
+            let event = AuditEvent::new(audit::SYSTEM_ACTOR, "rotate_api_key")
+                .key(&key_id(&key))
+                .param("grace_secs", grace_secs)
+                .param("trigger", "scheduled");
+            let result = self.rotate_key(&key, grace_secs, "scheduled").await;
+            self.audit(event, &result);
+            if result.is_ok() {
+                rotated += 1;
+            }
+        }
+
+        if deferred > 0 {
+            JobResult::Success(format!("Rotated {} keys; {} more are due but the pool has no room for their nodes", rotated, deferred))
+        } else {
+            JobResult::Success(format!("Rotated {} keys", rotated))
+        }
+    }
+
+    async fn add_ban(&mut self, request: BanNodeReq) -> Result<BanNodeRes, String> {
+        let pattern = request.pattern.trim().to_string();
+        if pattern.is_empty() || pattern.chars().all(|c| c == '*') {
+            return Err("Ban pattern must name a node or namespace".to_string());
+        }
+        if request.reason.trim().is_empty() {
+            return Err("A ban needs a reason".to_string());
+        }
+        if matches!(request.expires_in_secs, Some(secs) if secs <= 0) {
+            return Err("Ban expiry must be in the future".to_string());
+        }
+
+        let now = Utc::now().timestamp();
+        let ban = NodeBan {
+            ban_id: format!("ban_{:016x}", rand::random::<u64>()),
+            pattern,
+            reason: request.reason.trim().to_string(),
+            banned_by: our().node.clone(),
+            banned_at: now,
+            expires_at: request.expires_in_secs.map(|secs| now + secs),
+        };
+
+        // Drop the banned nodes' assignments and remember which keys they held
+        let mut revoked_nodes = Vec::new();
+        let mut exposed_keys = Vec::new();
+        for (key, nodes) in self.key_to_nodes.iter_mut() {
+            let before = nodes.len();
+            nodes.retain(|node| {
+                let banned = bans::pattern_matches(&ban.pattern, node);
+                if banned {
+                    revoked_nodes.push(node.clone());
+                }
+                !banned
+            });
+            if nodes.len() != before {
+                exposed_keys.push(key.clone());
+            }
+        }
+        self.waitlist.retain(|e| !bans::pattern_matches(&ban.pattern, &e.node_id));
+        for node in &revoked_nodes {
+            println!("Revoked key assignment for banned node {}", node);
+            self.end_grant(node, grant::BANNED, now);
+            self.audit_ok(AuditEvent::new(audit::SYSTEM_ACTOR, "revoke_node")
+                .node(node)
+                .param("ban_id", &ban.ban_id));
+        }
+        self.bans.push(ban.clone());
+
+        // The banned nodes still hold the old keys, so there's no grace period
+        let mut rotations = Vec::new();
+        if request.rotate_keys {
+            exposed_keys.retain(|key| self.active_keys.contains(key));
+            for key in &exposed_keys {
+                let event = AuditEvent::new(audit::SYSTEM_ACTOR, "rotate_api_key")
+                    .key(&key_id(key))
+                    .param("grace_secs", 0)
+                    .param("trigger", "ban");
+                let result = self.rotate_key(key, 0, "ban").await;
+                self.audit(event, &result);
+                if let Ok(rotation) = result {
+                    rotations.push(rotation);
+                }
+            }
+        }
+
+        Ok(BanNodeRes { ban, revoked_nodes, rotations })
+    }
+
+    /// Take a key out of the pool, move its nodes onto other keys and push them
+    /// their new key. The old key stays live upstream for `grace_secs`.
+    async fn rotate_key(&mut self, old_key: &str, grace_secs: i64, trigger: &str) -> Result<KeyRotation, String> {
+        if !self.active_keys.contains(old_key) {
+            return Err("Only active keys can be rotated".to_string());
+        }
+
+        let now = Utc::now().timestamp();
+        let deactivate_after = now + grace_secs;
+
+        // Out of the pool first so no node lands back on it
+        self.key_metadata.entry(old_key.to_string()).or_default().deactivate_after = Some(deactivate_after);
+        self.retire_key(old_key, "rotated").await;
+
+        let nodes = self.key_to_nodes.remove(old_key).unwrap_or_default();
+        let mut moves = Vec::new();
+        for node_id in nodes {
+            self.end_grant(&node_id, grant::ROTATED, now);
+            match self.issue_key(&node_id) {
+                Ok(new_key) => {
+                    let push_id = self.push_key(&node_id, &new_key, "rotation");
+                    let delivery = &self.key_deliveries[&node_id];
+                    moves.push(RotationMove {
+                        node_id,
+                        new_key_id: Some(key_id(&new_key)),
+                        push_id: Some(push_id),
+                        delivery_status: delivery.status.clone(),
+                        error: delivery.error.clone(),
+                    });
+                }
+                Err(e) => {
+                    let address = self.node_addresses.get(&node_id).cloned().unwrap_or_default();
+                    self.enqueue_waitlist(&node_id, address, &e, None);
+                    moves.push(RotationMove {
+                        node_id,
+                        new_key_id: None,
+                        push_id: None,
+                        delivery_status: "waitlisted".to_string(),
+                        error: Some(e),
+                    });
+                }
+            }
+        }
+
+        println!("Rotated key {}: moved {} nodes, old key retires upstream at {}",
+                 key_id(old_key), moves.len(), format_timestamp(deactivate_after));
+
+        let rotation = KeyRotation {
+            rotation_id: format!("rot_{:016x}", rand::random::<u64>()),
+            old_key_id: key_id(old_key),
+            old_key_hint: mask_key(old_key),
+            trigger: trigger.to_string(),
+            started_at: now,
+            deactivate_after,
+            completed_at: self.key_metadata.get(old_key).filter(|m| m.deactivated_upstream).map(|_| now),
+            moves,
+        };
+        self.rotations.push(rotation.clone());
+        Ok(rotation)
+    }
+
+    async fn sweep_expired_keys(&mut self, now: i64) -> usize {
+        let expired: Vec<String> = self.active_keys.iter()
+            .filter(|key| {
+                self.key_metadata.get(*key)
+                    .and_then(|m| m.expires_at)
+                    .map(|expires_at| expires_at <= now)
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect();
+
+        for key in &expired {
+            println!("API key expired, retiring it");
+            self.retire_key(key, "expired").await;
+        }
+
+        if self.admin_api_key.is_some() {
+            for key in &self.keys_pending_deactivation(now) {
+                if let Err(e) = self.deactivate_key_upstream(key).await {
+                    println!("Retrying upstream deactivation failed: {}", e);
+                }
+            }
+        }
+
+        expired.len()
+    }
+
+    /// Keys retired while Anthropic was unreachable, and rotated-out keys whose
+    /// grace period is over. Removed keys only left the pool; they stay live upstream.
+    fn keys_pending_deactivation(&self, now: i64) -> Vec<String> {
+        self.historical_keys.iter()
+            .filter(|key| {
+                self.key_metadata.get(*key)
+                    .map(|m| m.retired_at.is_some()
+                        && m.retired_reason.as_deref() != Some("removed")
+                        && !m.deactivated_upstream
+                        && m.deactivate_after.map_or(true, |at| at <= now))
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// The key a node is assigned, which may since have been retired; callers
+    /// check `active_keys` before handing it out
+    fn find_key_for_node(&self, node_id: &str) -> Option<String> {
+        for (key, nodes) in &self.key_to_nodes {
+            if nodes.contains(&node_id.to_string()) {
+                return Some(key.clone());
+            }
+        }
+        None
+    }
+
+    /// Look up the full key behind a `key_id`, active or historical
+    fn resolve_key_id(&self, id: &str) -> Result<String, String> {
+        self.active_keys.iter()
+            .chain(self.historical_keys.iter())
+            .find(|key| key_id(key) == id)
+            .cloned()
+            .ok_or_else(|| "API key not found".to_string())
+    }
+
+    fn admin_client(&self) -> Result<AdminClient, String> {
+        if self.admin_api_key.as_deref().map_or(false, sealed::is_sealed) {
+            return Err("Admin API key could not be decrypted; set it again".to_string());
+        }
+        Ok(AdminClient::new(self.admin_api_key.as_ref())?)
+    }
+
+    fn retired_key_message(&self, key: &str) -> String {
+        let meta = self.key_metadata.get(key);
+        match (meta.and_then(|m| m.retired_reason.as_deref()), meta.and_then(|m| m.retired_at)) {
+            (Some("expired"), Some(at)) => format!("Your API key expired at {} and is no longer valid", format_timestamp(at)),
+            (Some(reason), Some(at)) => format!("Your API key was retired at {} ({}) and is no longer valid", format_timestamp(at), reason),
+            _ => "Your API key has been revoked and is no longer valid".to_string(),
+        }
+    }
+
+    /// Move a key out of the active pool, deactivating it upstream where possible.
+    /// Upstream failures are retried by the expiry sweep.
+    async fn retire_key(&mut self, key: &str, reason: &str) {
+        self.active_keys.remove(key);
+        self.historical_keys.insert(key.to_string());
+
+        let meta = self.key_metadata.entry(key.to_string()).or_default();
+        meta.retired_at = Some(Utc::now().timestamp());
+        meta.retired_reason = Some(reason.to_string());
+
+        let event = AuditEvent::new(audit::SYSTEM_ACTOR, "retire_api_key")
+            .key(&key_id(key))
+            .param("reason", reason);
+        self.audit_ok(event);
+
+        // Rotated-out keys are left to the expiry sweep until their grace period ends
+        let now = Utc::now().timestamp();
+        let hold_upstream = self.key_metadata.get(key)
+            .map(|m| m.deactivated_upstream || m.deactivate_after.map_or(false, |at| at > now))
+            .unwrap_or(false);
+        if self.admin_api_key.is_some() && !hold_upstream {
+            if let Err(e) = self.deactivate_key_upstream(key).await {
+                println!("Failed to deactivate retired key upstream: {}", e);
+            }
+        }
+    }
+
+    /// In dedicated mode, name each assigned key upstream after its node so
+    /// Anthropic's console and cost reports say who holds it. Failures are
+    /// retried on the next job run.
+    async fn name_dedicated_keys(&mut self) {
+        if self.pool_mode != PoolMode::Dedicated || self.admin_api_key.is_none() {
+            return;
+        }
+
+        let unnamed: Vec<(String, String)> = self.active_keys.iter()
+            .filter_map(|key| match self.key_to_nodes.get(key).map(|n| n.as_slice()) {
+                Some([node]) => Some((key.clone(), node.clone())),
+                _ => None,
+            })
+            .filter(|(key, node)| self.key_metadata.get(key).and_then(|m| m.upstream_name.as_ref()) != Some(node))
+            .collect();
+
+        for (key, node) in unnamed {
+            if let Err(e) = self.name_key_upstream(&key, &node).await {
+                println!("Failed to name key {} after {}: {}", key_id(&key), node, e);
+            }
+        }
+    }
+
+    async fn name_key_upstream(&mut self, key: &str, name: &str) -> Result<(), String> {
+        let anthropic_id = self.resolve_anthropic_key_id(key).await?;
+        self.admin_client()?.update_api_key(&anthropic_id, None, Some(name)).await?;
 
-        Ok(TotalCostsRes {
-            total_cost,
-            cost_by_key,
-            currency: "USD".to_string(),
-        })
+        self.key_metadata.entry(key.to_string()).or_default().upstream_name = Some(name.to_string());
+        println!("Named API key {} {} upstream", anthropic_id, name);
+        Ok(())
     }
 
-    #[http]
-    async fn get_key_costs(&self, request: KeyCostReq) -> Result<KeyCostsRes, String> {
+    async fn deactivate_key_upstream(&mut self, key: &str) -> Result<(), String> {
+        let pool_key_id = key_id(key);
+        let key_id = self.resolve_anthropic_key_id(key).await?;
+        self.admin_client()?.update_api_key(&key_id, Some("inactive"), None).await?;
 
-        // We cannot track costs per individual API key from the Anthropic API
-        let costs: Vec<CostRecord> = Vec::new();
-        let total: f64 = 0.0;
+        self.key_metadata.entry(key.to_string()).or_default().deactivated_upstream = true;
+        println!("Deactivated API key {} upstream", key_id);
 
-        Ok(KeyCostsRes {
-            api_key: request.api_key,
-            costs,
-            total,
-        })
+        let now = Utc::now().timestamp();
+        for rotation in self.rotations.iter_mut().filter(|r| r.old_key_id == pool_key_id && r.completed_at.is_none()) {
+            rotation.completed_at = Some(now);
+        }
+        Ok(())
     }
 
-    #[http]
-    async fn get_node_history(&self) -> Result<Vec<NodeAssignment>, String> {
-        let mut assignments: Vec<NodeAssignment> = Vec::new();
-
-        for (key, nodes) in &self.key_to_nodes {
-            for node in nodes {
-                let issued_at = self.node_issue_times.get(node).copied().unwrap_or(0);
-                assignments.push(NodeAssignment {
-                    node_id: node.clone(),
-                    api_key: key.clone(),
-                    issued_at,
-                });
-            }
+    /// Find Anthropic's id for a pooled key by matching the partial key hint
+    async fn resolve_anthropic_key_id(&mut self, key: &str) -> Result<String, String> {
+        if let Some(id) = self.key_metadata.get(key).and_then(|m| m.anthropic_key_id.clone()) {
+            return Ok(id);
         }
 
-        assignments.sort_by_key(|a| a.issued_at);
+        let workspace_id = self.key_workspace(key);
+        let upstream = self.admin_client()?.list_api_keys(workspace_id.as_deref(), None).await?;
+        let found = upstream.iter()
+            .find(|k| k.partial_key_hint.as_deref().map(|hint| key_matches_hint(key, hint)).unwrap_or(false))
+            .ok_or("API key not found in the Anthropic organization")?;
 
-        Ok(assignments)
+        self.key_metadata.entry(key.to_string()).or_default().anthropic_key_id = Some(found.id.clone());
+        Ok(found.id.clone())
     }
 
-    #[http]
-    async fn set_admin_key(&mut self, request: SetAdminKeyParams) -> Result<SuccessRes, String> {
-        self.admin_api_key = Some(request.admin_key.clone());
+    /// Walk every key in the organization and diff it against the pool
+    async fn reconcile(&mut self) -> Result<ReconcileReport, String> {
+        let upstream = self.admin_client()?.list_api_keys(None, None).await?;
+        println!("Reconciling pool against {} organization keys", upstream.len());
 
-        // Log for debugging
-        println!("Admin key set: {}", if request.admin_key.starts_with("sk-") { "sk-***" } else { "invalid format" });
+        let pooled: Vec<String> = self.active_keys.iter()
+            .chain(self.historical_keys.iter())
+            .cloned()
+            .collect();
 
-        Ok(SuccessRes {
-            success: true,
-            message: "Admin key set successfully".to_string(),
-        })
-    }
+        let mut matched_upstream: HashSet<String> = HashSet::new();
+        let mut drift = Vec::new();
 
-    #[http]
-    async fn check_admin_key(&self) -> Result<AdminKeyStatusRes, String> {
-        Ok(AdminKeyStatusRes {
-            has_admin_key: self.admin_api_key.is_some(),
-            key_prefix: self.admin_api_key.as_ref().map(|k| {
-                if k.starts_with("sk-") {
-                    "sk-***".to_string()
-                } else {
-                    "invalid".to_string()
+        for key in &pooled {
+            let is_active = self.active_keys.contains(key);
+            let known_id = self.key_metadata.get(key).and_then(|m| m.anthropic_key_id.clone());
+            let found = upstream.iter().find(|k| match known_id {
+                Some(ref id) => &k.id == id,
+                None => k.partial_key_hint.as_deref().map(|hint| key_matches_hint(key, hint)).unwrap_or(false),
+            });
+
+            let Some(found) = found else {
+                if is_active {
+                    drift.push(pooled_drift("missing_upstream", key, None, "retire"));
                 }
-            }),
-        })
-    }
+                continue;
+            };
+            matched_upstream.insert(found.id.clone());
+            self.key_metadata.entry(key.clone()).or_default().anthropic_key_id = Some(found.id.clone());
+
+            let upstream_active = found.status == "active";
+            if is_active && !upstream_active {
+                drift.push(pooled_drift("inactive_upstream", key, Some(found), "retire"));
+            } else if !is_active && upstream_active {
+                drift.push(pooled_drift("active_but_retired", key, Some(found), "deactivate_upstream"));
+            } else if !upstream_active {
+                // Retired here and down upstream; nothing left for the sweep to retry
+                self.key_metadata.entry(key.clone()).or_default().deactivated_upstream = true;
+            }
 
-    #[http]
-    async fn initialize_auth(&mut self) -> Result<AuthRes, String> {
-        if self.ui_auth_token.is_none() {
-            let token = BASE64.encode(format!("{:x}", rand::random::<u128>()));
-            self.ui_auth_token = Some(token.clone());
+            if is_active && upstream_active && found.workspace_id.is_some() && self.key_workspace(key) != found.workspace_id {
+                drift.push(pooled_drift("workspace_mismatch", key, Some(found), "use_upstream_workspace"));
+            }
         }
 
-        // Include admin key status in response
-        Ok(AuthRes {
-            token: self.ui_auth_token.as_ref().unwrap().clone(),
-            has_admin_key: self.admin_api_key.is_some(),
-        })
+        for key in upstream.iter().filter(|k| k.status == "active" && !matched_upstream.contains(&k.id)) {
+            drift.push(DriftItem {
+                id: format!("unpooled_upstream:{}", key.id),
+                kind: "unpooled_upstream".to_string(),
+                key_id: None,
+                key_hint: key.partial_key_hint.clone(),
+                anthropic_key_id: Some(key.id.clone()),
+                name: Some(key.name.clone()),
+                upstream_status: Some(key.status.clone()),
+                workspace_id: key.workspace_id.clone(),
+                fix: "deactivate_upstream".to_string(),
+            });
+        }
+
+        let report = ReconcileReport {
+            generated_at: Utc::now().timestamp(),
+            upstream_keys: upstream.len() as u64,
+            matched_keys: matched_upstream.len() as u64,
+            drift,
+        };
+        self.last_reconciliation = Some(report.clone());
+        Ok(report)
     }
 
-    #[http]
-    async fn get_all_costs(&self) -> Result<Vec<CostRecord>, String> {
-        println!("get_all_costs called. Returning {} cost records", self.all_costs.len());
+    async fn apply_drift_fix(&mut self, item: &DriftItem) -> Result<(), String> {
+        if item.kind == "unpooled_upstream" {
+            let anthropic_key_id = item.anthropic_key_id.as_deref().ok_or("Drift item has no Anthropic key id")?;
+            self.admin_client()?.update_api_key(anthropic_key_id, Some("inactive"), None).await?;
+            return Ok(());
+        }
 
-        // Debug: print first few records if any exist
-        if !self.all_costs.is_empty() {
-            println!("Sample cost records:");
-            for (i, cost) in self.all_costs.iter().take(3).enumerate() {
-                println!("  [{}] {} {} - {}", i, cost.amount, cost.currency, cost.description);
+        let key = self.resolve_key_id(item.key_id.as_deref().ok_or("Drift item has no key id")?)?;
+        match item.kind.as_str() {
+            "inactive_upstream" | "missing_upstream" => {
+                // Nothing to deactivate upstream
+                self.key_metadata.entry(key.clone()).or_default().deactivated_upstream = true;
+                let reason = if item.kind == "missing_upstream" { "missing upstream" } else { "inactive upstream" };
+                self.retire_key(&key, reason).await;
+                Ok(())
+            }
+            "active_but_retired" => self.deactivate_key_upstream(&key).await,
+            "workspace_mismatch" => {
+                self.key_metadata.entry(key).or_default().workspace_id = item.workspace_id.clone();
+                self.rebuild_key_costs();
+                Ok(())
             }
+            kind => Err(format!("Unknown drift kind: {}", kind)),
         }
-
-        Ok(self.all_costs.clone())
     }
 
-    #[http]
-    async fn refresh_costs(&mut self) -> Result<CostsRefreshRes, String> {
-        if self.admin_api_key.is_none() {
-            return Err("Admin API key not configured".to_string());
+    fn effective_max_nodes(&self, key: &str) -> Option<u64> {
+        if self.pool_mode == PoolMode::Dedicated {
+            return Some(1);
         }
+        self.key_metadata.get(key)
+            .and_then(|m| m.max_nodes)
+            .or(self.default_max_nodes)
+    }
 
-        let now = Utc::now().timestamp();
+    fn key_is_full(&self, key: &str) -> bool {
+        let nodes = self.key_to_nodes.get(key).map(|n| n.len() as u64).unwrap_or(0);
+        self.effective_max_nodes(key).map(|max| nodes >= max).unwrap_or(false)
+    }
 
-        // Debug logging
-        println!("refresh_costs called. Current time: {}, Last check: {:?}", now, self.last_cost_check);
+    fn key_weight(&self, key: &str) -> u32 {
+        self.key_metadata.get(key)
+            .and_then(|m| m.weight)
+            .unwrap_or(selection::DEFAULT_WEIGHT)
+    }
 
-        if let Some(last_check) = self.last_cost_check {
-            let time_since_last = now - last_check;
-            println!("Time since last check: {} seconds", time_since_last);
+    fn effective_spend_cap(&self, key: &str) -> Option<f64> {
+        self.key_metadata.get(key)
+            .and_then(|m| m.spend_cap)
+            .or(self.default_spend_cap)
+    }
 
-            // Only rate limit if less than 60 seconds (not 3600)
-            // But allow negative time_since_last (which means timestamp is in future - a bug we're fixing)
-            if time_since_last < 60 && time_since_last > 0 {
-                println!("Rate limiting: costs were refreshed {} seconds ago", time_since_last);
-                return Ok(CostsRefreshRes {
-                    success: false,
-                    message: format!("Costs were recently refreshed {} seconds ago", time_since_last),
-                    timestamp: last_check,
-                });
-            }
+    /// Retire every active key whose attributed spend has reached its cap
+    async fn enforce_spend_caps(&mut self) {
+        let over_cap: Vec<(String, f64, f64)> = self.active_keys.iter()
+            .filter_map(|key| {
+                let cap = self.effective_spend_cap(key)?;
+                let spent = self.key_total_cost(key, &None, &None);
+                (spent >= cap).then(|| (key.clone(), spent, cap))
+            })
+            .collect();
 
-            // If time_since_last is negative, clear the bad timestamp
-            if time_since_last < 0 {
-                println!("WARNING: last_cost_check is in the future! Clearing it.");
-                self.last_cost_check = None;
-            }
+        for (key, spent, cap) in over_cap {
+            println!("API key exceeded its spend cap (${:.2} of ${:.2}), retiring it", spent, cap);
+            let reason = format!("spend cap exceeded: ${:.2} spent of ${:.2} cap", spent, cap);
+            self.retire_key(&key, &reason).await;
         }
+    }
 
-        // Fetch real costs from Anthropic API
-        match self.fetch_costs_from_anthropic().await {
-            Ok(costs_added) => {
-                self.last_cost_check = Some(now);
-                Ok(CostsRefreshRes {
-                    success: true,
-                    message: format!("Costs refreshed successfully. Added {} cost records", costs_added),
-                    timestamp: now,
-                })
-            }
-            Err(e) => {
-                println!("Failed to fetch costs from Anthropic: {}", e);
-                Err(format!("Failed to fetch costs: {}", e))
+    fn key_workspace(&self, key: &str) -> Option<String> {
+        self.key_metadata.get(key).and_then(|m| m.workspace_id.clone())
+    }
+
+    fn key_total_cost(&self, key: &str, start_date: &Option<String>, end_date: &Option<String>) -> f64 {
+        self.key_costs.get(key)
+            .map(|costs| costs.iter()
+                .filter(|c| self.filter_by_date(c.timestamp, start_date, end_date))
+                .map(|c| c.amount)
+                .sum())
+            .unwrap_or(0.0)
+    }
+
+    /// Keys mapped to each workspace, in the order they were added
+    fn workspace_keys(&self) -> HashMap<String, Vec<(i64, String)>> {
+        let mut workspaces: HashMap<String, Vec<(i64, String)>> = HashMap::new();
+        for (key, meta) in &self.key_metadata {
+            if let Some(ref workspace_id) = meta.workspace_id {
+                workspaces.entry(workspace_id.clone()).or_default().push((meta.added_at, key.clone()));
             }
         }
+        for keys in workspaces.values_mut() {
+            keys.sort();
+        }
+        workspaces
     }
 
-    #[http]
-    async fn reset_costs(&mut self) -> Result<SuccessRes, String> {
-        if self.admin_api_key.is_none() {
-            return Err("Admin API key not configured".to_string());
+    /// Refuse a workspace another active key is mapped to. Retired keys keep
+    /// their workspace so a replacement can take it over.
+    fn check_workspace_free(&self, key: &str, workspace_id: &str) -> Result<(), String> {
+        let holder = self.active_keys.iter()
+            .filter(|other| other.as_str() != key)
+            .find(|other| self.key_metadata.get(*other).and_then(|m| m.workspace_id.as_deref()) == Some(workspace_id));
+        match holder {
+            Some(other) => Err(format!(
+                "workspace {} already holds pooled key {}; each pooled key needs a workspace of its own",
+                workspace_id,
+                key_id(other)
+            )),
+            None => Ok(()),
         }
-
-        // Clear all cost data
-        self.all_costs.clear();
-        self.key_costs.clear();
-        self.last_cost_query_date = None;
-        self.last_cost_check = None;
-
-        println!("Cost data reset. All historical cost data cleared.");
-
-        Ok(SuccessRes {
-            success: true,
-            message: "Cost data reset successfully. All historical data cleared.".to_string(),
-        })
     }
 
-}
-
-impl AnthropicApiKeyManagerState {
-    fn find_key_for_node(&self, node_id: &str) -> Option<String> {
-        for (key, nodes) in &self.key_to_nodes {
-            if nodes.contains(&node_id.to_string()) {
-                return Some(key.clone());
+    /// Re-derive `key_costs` from `all_costs` using the current key -> workspace
+    /// mapping. A workspace that held several keys over time bills each record
+    /// to the key added most recently before it.
+    fn rebuild_key_costs(&mut self) {
+        let workspace_keys = self.workspace_keys();
+        self.key_costs.clear();
+        for record in &self.all_costs {
+            let Some(keys) = record.workspace_id.as_ref().and_then(|ws| workspace_keys.get(ws)) else {
+                continue;
+            };
+            let key = keys.iter().rev()
+                .find(|(added_at, _)| *added_at <= record.timestamp)
+                .or(keys.first())
+                .map(|(_, key)| key);
+            if let Some(key) = key {
+                self.key_costs.entry(key.clone()).or_default().push(record.clone());
             }
         }
-        None
+    }
+
+    /// Find the workspace a pooled key was created in by matching its partial key hint
+    async fn resolve_key_workspace(&mut self, key: &str) -> Result<String, String> {
+        let upstream = self.admin_client()?.list_api_keys(None, None).await?;
+        let found = upstream.iter()
+            .find(|k| k.partial_key_hint.as_deref().map(|hint| key_matches_hint(key, hint)).unwrap_or(false))
+            .ok_or("API key not found in the Anthropic organization")?;
+
+        let workspace_id = found.workspace_id.clone().ok_or(
+            "API key is in the default workspace, whose costs can't be attributed to a single key; \
+             create it in a workspace of its own",
+        )?;
+        self.check_workspace_free(key, &workspace_id)?;
+
+        let meta = self.key_metadata.entry(key.to_string()).or_default();
+        meta.anthropic_key_id = Some(found.id.clone());
+        meta.workspace_id = Some(workspace_id.clone());
+        Ok(workspace_id)
     }
 
     fn filter_by_date(&self, timestamp: i64, start_date: &Option<String>, end_date: &Option<String>) -> bool {
@@ -557,8 +3712,7 @@ impl AnthropicApiKeyManagerState {
     }
 
     async fn fetch_costs_from_anthropic(&mut self) -> Result<usize, String> {
-        let admin_key = self.admin_api_key.as_ref()
-            .ok_or("Admin API key not configured")?;
+        let client = self.admin_client()?;
 
         let now = Utc::now();
 
@@ -594,12 +3748,6 @@ impl AnthropicApiKeyManagerState {
         let mut page_count = 0;
         let max_pages = 100; // Safety limit to prevent infinite loops
 
-        // Headers remain the same for all requests
-        let mut headers = HashMap::new();
-        headers.insert("anthropic-version".to_string(), "2023-06-01".to_string());
-        headers.insert("content-type".to_string(), "application/json".to_string());
-        headers.insert("x-api-key".to_string(), admin_key.clone());
-
         // Fetch all pages
         loop {
             page_count += 1;
@@ -608,118 +3756,37 @@ impl AnthropicApiKeyManagerState {
                 break;
             }
 
-            // Build URL with pagination
-            let url_str = if let Some(ref page_token) = next_page {
-                format!(
-                    "https://api.anthropic.com/v1/organizations/cost_report?starting_at={}&group_by[]=workspace_id&group_by[]=description&limit=30&page={}",
-                    starting_at, page_token
-                )
-            } else {
-                format!(
-                    "https://api.anthropic.com/v1/organizations/cost_report?starting_at={}&group_by[]=workspace_id&group_by[]=description&limit=30",
-                    starting_at
-                )
-            };
+            println!("Fetching costs page {} starting from {}", page_count, starting_at);
 
-            println!("Fetching costs page {} from URL: {}", page_count, url_str);
-
-            let url = Url::parse(&url_str).map_err(|e| format!("Invalid URL: {}", e))?;
-
-            // Retry logic for each page request
-            let mut attempts = 0;
-            let max_attempts = 3;
-            let mut last_error = String::new();
-            let mut page_fetched = false;
-
-            while attempts < max_attempts && !page_fetched {
-                attempts += 1;
-
-                match send_request_await_response(
-                    http::Method::GET,
-                    url.clone(),
-                    Some(headers.clone()),
-                    30000, // 30 second timeout
-                    vec![]
-                ).await {
-                    Ok(response) => {
-                        if response.status() == http::StatusCode::OK {
-                            let response_body = String::from_utf8_lossy(response.body());
-
-                            // Only log first 500 chars of response to avoid clutter
-                            if response_body.len() > 500 {
-                                println!("Anthropic API response (truncated): {}...", &response_body[..500]);
-                            } else {
-                                println!("Anthropic API response: {}", response_body);
-                            }
-
-                            match serde_json::from_str::<AnthropicCostReport>(&response_body) {
-                                Ok(cost_report) => {
-                                    println!("Successfully parsed cost report page {} with {} data entries",
-                                             page_count, cost_report.data.len());
-
-                                    // Store the next page token if available
-                                    let has_more = cost_report.has_more;
-                                    next_page = cost_report.next_page.clone();
-
-                                    // Add this page's data to our collection
-                                    all_cost_reports.push(cost_report);
-                                    page_fetched = true;
-
-                                    // Check if we need to fetch more pages
-                                    if !has_more || next_page.is_none() {
-                                        println!("Reached last page of cost reports (total pages: {})", page_count);
-                                        // Process all collected reports
-                                        return self.process_all_cost_reports(all_cost_reports, now.timestamp());
-                                    }
-                                }
-                                Err(e) => {
-                                    last_error = format!("Failed to parse response: {}. Body: {}", e, response_body);
-                                    println!("Parse error: {}", last_error);
-                                }
-                            }
-                        } else if response.status() == http::StatusCode::TOO_MANY_REQUESTS {
-                            last_error = format!("Rate limited on page {}, attempt {}/{}", page_count, attempts, max_attempts);
-                            // Wait longer for rate limiting
-                            if attempts < max_attempts {
-                                let wait_ms = 5000 * attempts as u64; // 5s, 10s, 15s
-                                println!("Rate limited, waiting {}ms before retry", wait_ms);
-                                set_timer(wait_ms, None);
-                            }
-                        } else {
-                            last_error = format!("API returned status {}: {}",
-                                response.status(),
-                                String::from_utf8_lossy(response.body())
-                            );
-                            // For non-retriable errors, break immediately
-                            if response.status() == http::StatusCode::UNAUTHORIZED ||
-                               response.status() == http::StatusCode::FORBIDDEN {
-                                return Err(last_error);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        last_error = format!("HTTP request failed: {:?}", e);
-                    }
-                }
+            // Retries and backoff are handled by the client
+            let cost_report = client.cost_report(&starting_at, next_page.as_deref()).await
+                .map_err(|e| format!("Failed to fetch page {}: {}", page_count, e))?;
 
-                // Wait before retry (exponential backoff)
-                if attempts < max_attempts && !page_fetched {
-                    let wait_ms = 1000 * (2_u64.pow(attempts - 1)); // 1s, 2s, 4s
-                    println!("Request failed for page {}, waiting {}ms before retry", page_count, wait_ms);
-                    set_timer(wait_ms, None);
-                }
-            }
+            println!("Successfully parsed cost report page {} with {} data entries",
+                     page_count, cost_report.data.len());
+
+            // Store the next page token if available
+            let has_more = cost_report.has_more;
+            next_page = cost_report.next_page.clone();
 
-            // If we couldn't fetch this page after all retries, fail
-            if !page_fetched {
-                return Err(format!("Failed to fetch page {} after {} attempts: {}",
-                                   page_count, attempts, last_error));
+            // Add this page's data to our collection
+            all_cost_reports.push(cost_report);
+
+            // Check if we need to fetch more pages
+            if !has_more || next_page.is_none() {
+                println!("Reached last page of cost reports (total pages: {})", page_count);
+                // Process all collected reports
+                let costs_added = self.process_all_cost_reports(all_cost_reports, now.timestamp())?;
+                self.enforce_spend_caps().await;
+                return Ok(costs_added);
             }
         }
 
         // If we somehow exit the loop without returning, process what we have
         if !all_cost_reports.is_empty() {
-            self.process_all_cost_reports(all_cost_reports, now.timestamp())
+            let costs_added = self.process_all_cost_reports(all_cost_reports, now.timestamp())?;
+            self.enforce_spend_caps().await;
+            Ok(costs_added)
         } else {
             Err("No cost reports fetched".to_string())
         }
@@ -731,24 +3798,32 @@ impl AnthropicApiKeyManagerState {
 
         println!("Processing {} pages of cost reports", cost_reports.len());
 
+        // Index of stored records by bucket, and the buckets already seen in this run
+        let mut index: HashMap<String, usize> = self.all_costs.iter()
+            .enumerate()
+            .map(|(i, record)| (record.bucket_key(), i))
+            .collect();
+        let mut seen: HashSet<String> = HashSet::new();
+
         for (page_num, cost_report) in cost_reports.into_iter().enumerate() {
             println!("Processing page {} with {} data entries", page_num + 1, cost_report.data.len());
 
-            // Track the latest date we've seen
+            // Track the start of the latest bucket we've seen. That bucket may
+            // still be open, so the next query starts there and upserts it.
             for data in &cost_report.data {
                 if let Some(ref current_latest) = latest_date {
-                    if data.ending_at > *current_latest {
-                        latest_date = Some(data.ending_at.clone());
+                    if data.starting_at > *current_latest {
+                        latest_date = Some(data.starting_at.clone());
                     }
                 } else {
-                    latest_date = Some(data.ending_at.clone());
+                    latest_date = Some(data.starting_at.clone());
                 }
             }
 
-            match self.process_cost_report(cost_report, timestamp) {
+            match self.process_cost_report(cost_report, timestamp, &mut index, &mut seen) {
                 Ok(costs_added) => {
                     total_costs_added += costs_added;
-                    println!("Added {} costs from page {}", costs_added, page_num + 1);
+                    println!("Added or updated {} costs from page {}", costs_added, page_num + 1);
                 }
                 Err(e) => {
                     println!("Warning: Failed to process page {}: {}", page_num + 1, e);
@@ -757,6 +3832,8 @@ impl AnthropicApiKeyManagerState {
             }
         }
 
+        self.rebuild_key_costs();
+
         // Store the latest date we've queried for next time
         if let Some(latest) = latest_date {
             // Validate this is actually a date and not something else (like a page token)
@@ -782,7 +3859,7 @@ impl AnthropicApiKeyManagerState {
                         }
                         Err(_) => {
                             // If parsing fails, don't store it
-                            println!("WARNING: Could not parse ending_at date '{}', not storing", latest);
+                            println!("WARNING: Could not parse starting_at date '{}', not storing", latest);
                             return Ok(total_costs_added); // Exit early without updating
                         }
                     }
@@ -793,13 +3870,33 @@ impl AnthropicApiKeyManagerState {
             }
         }
 
-        println!("Total costs added across all pages: {}. Total costs in system: {}",
+        println!("Total costs added or updated across all pages: {}. Total costs in system: {}",
                  total_costs_added, self.all_costs.len());
         Ok(total_costs_added)
     }
 
-    fn process_cost_report(&mut self, cost_report: AnthropicCostReport, _query_timestamp: i64) -> Result<usize, String> {
-        let mut costs_added = 0;
+    /// Collapse records for the same report line, keeping the last one stored.
+    /// Returns how many records were dropped.
+    fn dedupe_cost_records(&mut self) -> usize {
+        let before = self.all_costs.len();
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut deduped: Vec<CostRecord> = self.all_costs.drain(..)
+            .rev()
+            .filter(|record| seen.insert(record.bucket_key()))
+            .collect();
+        deduped.reverse();
+        self.all_costs = deduped;
+        before - self.all_costs.len()
+    }
+
+    fn process_cost_report(
+        &mut self,
+        cost_report: AnthropicCostReport,
+        _query_timestamp: i64,
+        index: &mut HashMap<String, usize>,
+        seen: &mut HashSet<String>,
+    ) -> Result<usize, String> {
+        let mut costs_changed = 0;
 
         println!("Processing cost report with {} data entries", cost_report.data.len());
 
@@ -825,104 +3922,503 @@ impl AnthropicApiKeyManagerState {
                 println!("Cost result: {} cents (${:.4}) {} - {} (incurred at {})",
                          amount_in_cents, amount_in_dollars, result.currency, description, data.starting_at);
 
-                // Skip zero amounts
-                if amount_in_cents == 0.0 {
-                    continue;
-                }
-
-                // Find which key this cost belongs to (if any)
-                // For now, we'll aggregate all costs since we don't have workspace mapping
                 // Store the amount in dollars for consistency
                 let record = CostRecord {
                     timestamp: cost_timestamp,  // Use the actual cost incurred timestamp
                     amount: amount_in_dollars,  // Store as dollars
                     currency: result.currency,
                     description,
+                    workspace_id: result.workspace_id,
+                    model: result.model,
+                    token_type: result.token_type,
                 };
+                let bucket_key = record.bucket_key();
+
+                match index.get(&bucket_key) {
+                    // The same line twice in one run is a split of one bucket; add it up
+                    Some(&i) if seen.contains(&bucket_key) => {
+                        if amount_in_cents != 0.0 {
+                            self.all_costs[i].amount += amount_in_dollars;
+                            costs_changed += 1;
+                        }
+                    }
+                    // Re-reported bucket from an earlier run; Anthropic's number wins
+                    Some(&i) => {
+                        if self.all_costs[i].amount != amount_in_dollars {
+                            println!("Updating cost record for {}: ${:.4} -> ${:.4}",
+                                     data.starting_at, self.all_costs[i].amount, amount_in_dollars);
+                            self.all_costs[i] = record;
+                            costs_changed += 1;
+                        }
+                    }
+                    None => {
+                        // Skip zero amounts
+                        if amount_in_cents == 0.0 {
+                            continue;
+                        }
 
-                // Add to global costs
-                self.all_costs.push(record.clone());
-                costs_added += 1;
-                println!("Added cost record: ${:.4} {} incurred at {}",
-                         amount_in_dollars, record.currency, data.starting_at);
-
-                // Note: We cannot map costs to individual API keys from the Anthropic API
-                // The workspace_id field doesn't directly map to individual API keys
-                // So we only track global costs, not per-key costs
+                        println!("Added cost record: ${:.4} {} incurred at {}",
+                                 amount_in_dollars, record.currency, data.starting_at);
+                        self.all_costs.push(record);
+                        index.insert(bucket_key.clone(), self.all_costs.len() - 1);
+                        costs_changed += 1;
+                    }
+                }
+                seen.insert(bucket_key);
             }
         }
 
-        println!("Total costs added: {}. Total costs in system: {}", costs_added, self.all_costs.len());
-        Ok(costs_added)
+        println!("Total costs added or updated: {}. Total costs in system: {}", costs_changed, self.all_costs.len());
+        Ok(costs_changed)
     }
 
-    async fn create_workspace(&self, name: String) -> Result<AnthropicWorkspace, String> {
-        let admin_key = self.admin_api_key.as_ref()
-            .ok_or("Admin API key not configured")?;
+}
 
-        let url = Url::parse("https://api.anthropic.com/v1/organizations/workspaces")
-            .map_err(|e| format!("Invalid URL: {}", e))?;
+/// Stable, non-secret identifier for a pooled key: a truncated SHA-256 of it
+fn key_id(key: &str) -> String {
+    let digest = Sha256::digest(key.as_bytes());
+    let hex: String = digest.iter().take(8).map(|b| format!("{:02x}", b)).collect();
+    format!("key_{}", hex)
+}
 
-        let mut headers = HashMap::new();
-        headers.insert("anthropic-version".to_string(), "2023-06-01".to_string());
-        headers.insert("content-type".to_string(), "application/json".to_string());
-        headers.insert("x-api-key".to_string(), admin_key.clone());
+/// Masked form for display, e.g. "sk-ant-…abcd"
+fn mask_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.len() <= 11 {
+        return "…".to_string();
+    }
+    let prefix: String = chars[..7].iter().collect();
+    let suffix: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}…{}", prefix, suffix)
+}
 
-        let body = serde_json::json!({
-            "name": name
-        });
+fn pooled_drift(kind: &str, key: &str, upstream: Option<&AnthropicApiKey>, fix: &str) -> DriftItem {
+    DriftItem {
+        id: format!("{}:{}", kind, key_id(key)),
+        kind: kind.to_string(),
+        key_id: Some(key_id(key)),
+        key_hint: Some(mask_key(key)),
+        anthropic_key_id: upstream.map(|k| k.id.clone()),
+        name: upstream.map(|k| k.name.clone()),
+        upstream_status: upstream.map(|k| k.status.clone()),
+        workspace_id: upstream.and_then(|k| k.workspace_id.clone()),
+        fix: fix.to_string(),
+    }
+}
+
+/// Anthropic only exposes a partial hint of each key ("sk-ant-api03-R2D...igAA")
+fn key_matches_hint(key: &str, hint: &str) -> bool {
+    match hint.split_once("...") {
+        Some((prefix, suffix)) => key.starts_with(prefix) && key.ends_with(suffix),
+        None => false,
+    }
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .map(|dt| dt.to_rfc3339())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+fn job_info(job: &ScheduledJob) -> JobInfo {
+    JobInfo {
+        name: job.name.clone(),
+        interval_secs: job.interval_secs,
+        jitter_secs: job.jitter_secs,
+        enabled: job.enabled,
+        running: job.running,
+        next_run_at: job.next_run_at,
+        last_run_at: job.last_run_at,
+        last_duration_ms: job.last_duration_ms,
+        last_outcome: job.last_outcome.clone(),
+        last_message: job.last_message.clone(),
+        last_error: job.last_error.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cost_report(lines: &[(&str, &str, &str)]) -> AnthropicCostReport {
+        let results: Vec<serde_json::Value> = lines.iter()
+            .map(|(workspace_id, description, amount)| serde_json::json!({
+                "currency": "USD",
+                "amount": amount,
+                "workspace_id": workspace_id,
+                "description": description,
+            }))
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "data": [{
+                "starting_at": "2025-01-01T00:00:00Z",
+                "ending_at": "2025-01-02T00:00:00Z",
+                "results": results,
+            }],
+            "has_more": false,
+            "next_page": null,
+        }))
+        .unwrap()
+    }
+
+    fn ingest(state: &mut AnthropicApiKeyManagerState, report: AnthropicCostReport) -> usize {
+        let mut index: HashMap<String, usize> = state.all_costs.iter()
+            .enumerate()
+            .map(|(i, record)| (record.bucket_key(), i))
+            .collect();
+        state.process_cost_report(report, 0, &mut index, &mut HashSet::new()).unwrap()
+    }
+
+    #[test]
+    fn cost_report_upsert_is_idempotent() {
+        let mut state = AnthropicApiKeyManagerState::default();
+        let lines = [("ws_a", "Claude Sonnet", "150"), ("ws_b", "Claude Haiku", "20")];
+
+        assert_eq!(ingest(&mut state, cost_report(&lines)), 2);
+        assert_eq!(ingest(&mut state, cost_report(&lines)), 0);
+        assert_eq!(state.all_costs.len(), 2);
+
+        // A re-reported bucket replaces the stored amount rather than adding to it
+        assert_eq!(ingest(&mut state, cost_report(&[("ws_a", "Claude Sonnet", "400")])), 1);
+        assert_eq!(state.all_costs.len(), 2);
+        let total: f64 = state.all_costs.iter().map(|c| c.amount).sum();
+        assert!((total - 4.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn split_bucket_within_one_report_is_summed() {
+        let mut state = AnthropicApiKeyManagerState::default();
+        ingest(&mut state, cost_report(&[("ws_a", "Claude Sonnet", "100"), ("ws_a", "Claude Sonnet", "50")]));
+        assert_eq!(state.all_costs.len(), 1);
+        assert!((state.all_costs[0].amount - 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn dedupe_keeps_latest_record_per_bucket() {
+        let mut state = AnthropicApiKeyManagerState::default();
+        ingest(&mut state, cost_report(&[("ws_a", "Claude Sonnet", "100"), ("ws_b", "Claude Haiku", "20")]));
+        // Simulate the old blind append of a re-reported bucket
+        let mut stale = state.all_costs[0].clone();
+        stale.amount = 3.0;
+        state.all_costs.push(stale);
+
+        assert_eq!(state.dedupe_cost_records(), 1);
+        assert_eq!(state.all_costs.len(), 2);
+        let sonnet = state.all_costs.iter().find(|c| c.description == "Claude Sonnet").unwrap();
+        assert_eq!(sonnet.amount, 3.0);
+    }
 
-        let response = send_request_await_response(
-            http::Method::POST,
-            url,
-            Some(headers),
-            30000,
-            body.to_string().into_bytes()
-        ).await.map_err(|e| format!("HTTP request failed: {:?}", e))?;
+    #[test]
+    fn workspace_is_taken_only_by_active_keys() {
+        let mut state = AnthropicApiKeyManagerState::default();
+        state.active_keys.insert("sk-ant-new".to_string());
+        state.historical_keys.insert("sk-ant-old".to_string());
+        state.key_metadata.entry("sk-ant-old".to_string()).or_default().workspace_id = Some("ws_a".to_string());
+        assert!(state.check_workspace_free("sk-ant-new", "ws_a").is_ok());
+
+        state.key_metadata.entry("sk-ant-new".to_string()).or_default().workspace_id = Some("ws_a".to_string());
+        assert!(state.check_workspace_free("sk-ant-new", "ws_a").is_ok());
+        assert!(state.check_workspace_free("sk-ant-other", "ws_a").is_err());
+    }
+
+    #[test]
+    fn rotated_workspace_costs_follow_the_key_in_use_at_the_time() {
+        let mut state = AnthropicApiKeyManagerState::default();
+        for (key, added_at) in [("sk-ant-old", 100), ("sk-ant-new", 200)] {
+            let meta = state.key_metadata.entry(key.to_string()).or_default();
+            meta.workspace_id = Some("ws_a".to_string());
+            meta.added_at = added_at;
+        }
+        for (timestamp, amount) in [(50, 1.0), (150, 2.0), (250, 4.0)] {
+            state.all_costs.push(CostRecord {
+                timestamp,
+                amount,
+                currency: "USD".to_string(),
+                description: "Claude Sonnet".to_string(),
+                workspace_id: Some("ws_a".to_string()),
+                model: None,
+                token_type: None,
+            });
+        }
 
-        if response.status() != http::StatusCode::OK && response.status() != http::StatusCode::CREATED {
-            return Err(format!("API returned status {}: {}",
-                response.status(),
-                String::from_utf8_lossy(response.body())
-            ));
+        state.rebuild_key_costs();
+        assert_eq!(state.key_total_cost("sk-ant-old", &None, &None), 3.0);
+        assert_eq!(state.key_total_cost("sk-ant-new", &None, &None), 4.0);
+    }
+
+    fn leased_state(now: i64) -> AnthropicApiKeyManagerState {
+        sealed::use_test_key(&[1; 32]);
+        let mut state = AnthropicApiKeyManagerState::default();
+        state.lease_policy.lease_secs = Some(3600);
+        for key in ["sk-ant-live", "sk-ant-retired"] {
+            state.active_keys.insert(key.to_string());
         }
+        state.key_to_nodes.insert("sk-ant-live".to_string(), vec!["lapsed.os".to_string(), "current.os".to_string()]);
+        state.key_to_nodes.insert("sk-ant-retired".to_string(), vec!["stuck.os".to_string()]);
+        for (node, key, expires_at) in [
+            ("lapsed.os", "sk-ant-live", now - 10),
+            ("current.os", "sk-ant-live", now + 10),
+            ("stuck.os", "sk-ant-retired", now - 10),
+        ] {
+            state.current_grant(node, key);
+            state.node_grants.get_mut(node).unwrap().lease_expires_at = Some(expires_at);
+        }
+        state.active_keys.remove("sk-ant-retired");
+        state.historical_keys.insert("sk-ant-retired".to_string());
+        state
+    }
+
+    #[test]
+    fn expire_leases_frees_lapsed_slots_on_active_keys() {
+        let now = 1_000_000;
+        let mut state = leased_state(now);
+
+        assert_eq!(state.expire_leases(now), 1);
+        assert_eq!(state.key_to_nodes["sk-ant-live"], vec!["current.os".to_string()]);
+        assert!(!state.node_grants.contains_key("lapsed.os"));
+        let ended = state.grant_history["lapsed.os"].last().unwrap();
+        assert_eq!(ended.end_reason.as_deref(), Some(grant::LEASE_EXPIRED));
+        assert_eq!(ended.ended_at, Some(now - 10));
+
+        // Holders of retired keys stay put for the re-issue policy to handle
+        assert!(state.node_grants.contains_key("stuck.os"));
+        assert_eq!(state.key_to_nodes["sk-ant-retired"], vec!["stuck.os".to_string()]);
+
+        assert_eq!(state.expire_leases(now), 0);
+        assert_eq!(state.audit_log.iter().filter(|e| e.action == "expire_lease").count(), 1);
+    }
+
+    #[test]
+    fn lapsed_and_relinquished_nodes_are_returning() {
+        let now = 1_000_000;
+        let mut state = leased_state(now);
+        state.expire_leases(now);
+        assert!(state.is_returning_node("lapsed.os"));
+        assert!(!state.is_returning_node("current.os"));
+
+        state.end_grant("current.os", grant::RELINQUISHED, now);
+        assert!(state.is_returning_node("current.os"));
+
+        state.end_grant("stuck.os", grant::REVOKED, now);
+        assert!(!state.is_returning_node("stuck.os"));
+        assert!(!state.is_returning_node("stranger.os"));
+    }
 
-        serde_json::from_slice(response.body())
-            .map_err(|e| format!("Failed to parse response: {}", e))
+    #[test]
+    fn returning_nodes_dont_count_against_rate_limits() {
+        let now = 1_000_000;
+        let mut state = leased_state(now);
+        state.expire_leases(now);
+        state.record_issuance("lapsed.os");
+        state.record_issuance("new.os");
+        let counted: Vec<&str> = state.recent_issuances.iter().map(|i| i.node_id.as_str()).collect();
+        assert_eq!(counted, vec!["new.os"]);
     }
 
-    async fn list_api_keys(&self, workspace_id: Option<String>) -> Result<Vec<AnthropicApiKey>, String> {
-        let admin_key = self.admin_api_key.as_ref()
-            .ok_or("Admin API key not configured")?;
+    /// Serialize `value`, read it back as `T` and check nothing was lost
+    fn assert_round_trips<T: Serialize + serde::de::DeserializeOwned>(value: &impl Serialize) {
+        let json = serde_json::to_value(value).unwrap();
+        let other: T = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&other).unwrap(), json);
+    }
 
-        let mut url_str = "https://api.anthropic.com/v1/organizations/api_keys?limit=30&status=active".to_string();
-        if let Some(ws_id) = workspace_id {
-            url_str.push_str(&format!("&workspace_id={}", ws_id));
+    // The client crate mirrors the grant protocol by hand; keep the two in step
+    #[test]
+    fn grant_protocol_matches_the_client_crate() {
+        use anthropic_api_key_manager_client::protocol as client;
+
+        assert_eq!(grant::PROTOCOL_VERSION, client::PROTOCOL_VERSION);
+
+        let statuses = [
+            GrantStatus::Active,
+            GrantStatus::Waitlisted,
+            GrantStatus::KeyRetired,
+            GrantStatus::Banned,
+            GrantStatus::RateLimited,
+            GrantStatus::IssuancePaused,
+            GrantStatus::InviteRequired,
+            GrantStatus::InviteInvalid,
+            GrantStatus::NotGranted,
+        ];
+        for status in statuses {
+            assert_round_trips::<client::KeyGrant>(&KeyGrant {
+                protocol_version: grant::PROTOCOL_VERSION,
+                status,
+                grant_id: Some("grant_1".to_string()),
+                api_key: Some("sk-ant-a".to_string()),
+                key_id: Some("abcd".to_string()),
+                issued_at: Some(1),
+                expires_at: Some(2),
+                spend_cap: Some(5.5),
+                message: Some("message".to_string()),
+                retry_after_secs: Some(3),
+                waitlist_position: Some(4),
+                lease_expires_at: Some(6),
+            });
         }
 
-        let url = Url::parse(&url_str).map_err(|e| format!("Invalid URL: {}", e))?;
+        assert_round_trips::<GrantReq>(&client::GrantReq {
+            protocol_version: client::PROTOCOL_VERSION,
+            app_name: "app".to_string(),
+            app_version: "1.0.0".to_string(),
+            invite_code: Some("code".to_string()),
+        });
+        assert_round_trips::<client::NodeSpendRes>(&NodeSpendRes {
+            key_id: "abcd".to_string(),
+            window_secs: 60,
+            recent_spend: 1.5,
+            total_spend: 2.5,
+            spend_cap: Some(10.0),
+            remaining_allowance: Some(7.5),
+            shared_with: 2,
+        });
+        assert_round_trips::<client::SuccessRes>(&SuccessRes { success: true, message: "ok".to_string() });
+
+        let push = push::KeyPush {
+            push_id: "push_1".to_string(),
+            api_key: "sk-ant-a".to_string(),
+            issued_at: 1,
+            reason: "rotation".to_string(),
+        };
+        let body = serde_json::to_vec(&serde_json::json!({ "ApiKeyPush": push })).unwrap();
+        let parsed = client::parse_push(&body).unwrap();
+        assert_eq!((parsed.push_id.as_str(), parsed.api_key.as_str()), ("push_1", "sk-ant-a"));
+    }
+
+    #[test]
+    fn ending_a_grant_forgets_how_the_key_was_picked() {
+        sealed::use_test_key(&[1; 32]);
+        let mut state = AnthropicApiKeyManagerState::default();
+        state.active_keys.insert("sk-ant-a".to_string());
 
-        let mut headers = HashMap::new();
-        headers.insert("anthropic-version".to_string(), "2023-06-01".to_string());
-        headers.insert("x-api-key".to_string(), admin_key.clone());
+        let key = state.issue_key("node.os").unwrap();
+        state.current_grant("node.os", &key);
+        assert!(state.node_selection.contains_key("node.os"));
 
-        let response = send_request_await_response(
-            http::Method::GET,
-            url,
-            Some(headers),
-            30000,
-            vec![]
-        ).await.map_err(|e| format!("HTTP request failed: {:?}", e))?;
+        state.end_grant("node.os", grant::RELINQUISHED, 0);
+        assert!(!state.node_selection.contains_key("node.os"));
+    }
 
-        if response.status() != http::StatusCode::OK {
-            return Err(format!("API returned status {}: {}",
-                response.status(),
-                String::from_utf8_lossy(response.body())
-            ));
+    #[test]
+    fn waitlisted_node_polling_for_a_freed_slot_gets_the_key_it_was_given() {
+        sealed::use_test_key(&[1; 32]);
+        let mut state = AnthropicApiKeyManagerState { default_max_nodes: Some(1), ..Default::default() };
+        for key in ["sk-ant-a", "sk-ant-b"] {
+            state.active_keys.insert(key.to_string());
         }
+        state.key_to_nodes.insert("sk-ant-a".to_string(), vec!["a.os".to_string()]);
+        state.key_to_nodes.insert("sk-ant-b".to_string(), vec!["b.os".to_string()]);
+
+        let refusal = state.admit("waiting.os", String::new(), None).unwrap_err();
+        assert_eq!(refusal.status, GrantStatus::Waitlisted);
 
-        serde_json::from_slice(response.body())
-            .map_err(|e| format!("Failed to parse response: {}", e))
+        state.key_to_nodes.get_mut("sk-ant-a").unwrap().clear();
+        assert_eq!(state.admit("waiting.os", String::new(), None).ok().as_deref(), Some("sk-ant-a"));
+
+        let slots = state.key_to_nodes.values().flatten().filter(|n| *n == "waiting.os").count();
+        assert_eq!(slots, 1);
+        assert!(state.waitlist.is_empty());
+        assert_eq!(state.recent_issuances.iter().filter(|i| i.node_id == "waiting.os").count(), 1);
+    }
+
+    #[test]
+    fn invites_are_redeemed_when_the_key_is_issued() {
+        sealed::use_test_key(&[1; 32]);
+        let mut state = AnthropicApiKeyManagerState { default_max_nodes: Some(1), ..Default::default() };
+        state.active_keys.insert("sk-ant-a".to_string());
+        state.key_to_nodes.insert("sk-ant-a".to_string(), vec!["a.os".to_string()]);
+        state.invite_codes.push(InviteCode {
+            code: "K7QM-3XPA-ZR9D".to_string(),
+            batch_id: "batch_1".to_string(),
+            campaign: None,
+            max_uses: 1,
+            created_at: 0,
+            expires_at: None,
+            revoked_at: None,
+            redemptions: Vec::new(),
+            status: String::new(),
+        });
+
+        let index = state.check_invite("waiting.os", "k7qm3xpazr9d").ok();
+        assert!(state.admit("waiting.os", String::new(), index).is_err());
+        assert!(state.invite_codes[0].redemptions.is_empty());
+        assert_eq!(state.waitlist_refusal("waiting.os").and_then(|r| r.waitlist_position), Some(1));
+        // The waiting node holds the code's only use
+        assert!(state.check_invite("other.os", "K7QM-3XPA-ZR9D").is_err());
+
+        state.key_to_nodes.get_mut("sk-ant-a").unwrap().clear();
+        state.serve_waitlist();
+        let redemptions = &state.invite_codes[0].redemptions;
+        assert_eq!(redemptions.len(), 1);
+        assert!(redemptions[0].waitlisted && redemptions[0].node_id == "waiting.os");
     }
-}
 
+    fn state_with_retired_key(retired_secs_ago: i64, policy: ReissuePolicy) -> AnthropicApiKeyManagerState {
+        let mut state = AnthropicApiKeyManagerState::default();
+        state.historical_keys.insert("sk-ant-old".to_string());
+        let meta = state.key_metadata.entry("sk-ant-old".to_string()).or_default();
+        meta.retired_at = Some(Utc::now().timestamp() - retired_secs_ago);
+        meta.retired_reason = Some("rotated".to_string());
+        state.reissue_policy = policy;
+        state
+    }
+
+    #[test]
+    fn reissue_refused_when_policy_disallows_it() {
+        let state = state_with_retired_key(0, ReissuePolicy { allow_reissue: false, ..Default::default() });
+        let refusal = state.check_reissue("node.os", "sk-ant-old").unwrap_err();
+        assert_eq!(refusal.status, GrantStatus::KeyRetired);
+        assert_eq!(refusal.retry_after_secs, None);
+    }
+
+    #[test]
+    fn reissue_waits_out_the_cooldown() {
+        let policy = ReissuePolicy { allow_reissue: true, max_reissues: None, cooldown_secs: Some(600) };
+        let state = state_with_retired_key(100, policy.clone());
+        let refusal = state.check_reissue("node.os", "sk-ant-old").unwrap_err();
+        assert_eq!(refusal.status, GrantStatus::KeyRetired);
+        assert!(matches!(refusal.retry_after_secs, Some(wait) if (499..=500).contains(&wait)));
+
+        let state = state_with_retired_key(600, policy);
+        assert!(state.check_reissue("node.os", "sk-ant-old").is_ok());
+    }
+
+    #[test]
+    fn giving_back_a_retired_key_doesnt_make_a_returning_node() {
+        let mut state = state_with_retired_key(0, ReissuePolicy { allow_reissue: false, ..Default::default() });
+        state.key_to_nodes.insert("sk-ant-old".to_string(), vec!["node.os".to_string()]);
+        state.current_grant("node.os", "sk-ant-old");
+
+        state.relinquish_key("node.os", "sk-ant-old");
+        assert_eq!(state.find_key_for_node("node.os"), None);
+        let ended = state.grant_history["node.os"].last().unwrap();
+        assert_eq!(ended.end_reason.as_deref(), Some(grant::REVOKED));
+        assert!(!state.is_returning_node("node.os"));
+    }
+
+    #[test]
+    fn removed_keys_are_not_deactivated_upstream() {
+        let mut state = state_with_retired_key(0, ReissuePolicy::default());
+        let meta = state.key_metadata.entry("sk-ant-removed".to_string()).or_default();
+        meta.retired_at = Some(0);
+        meta.retired_reason = Some("removed".to_string());
+        state.historical_keys.insert("sk-ant-removed".to_string());
+
+        assert_eq!(state.keys_pending_deactivation(Utc::now().timestamp()), vec!["sk-ant-old".to_string()]);
+    }
+
+    #[test]
+    fn reissue_stops_at_the_limit() {
+        let policy = ReissuePolicy { allow_reissue: true, max_reissues: Some(2), cooldown_secs: None };
+        let mut state = state_with_retired_key(0, policy);
+        assert!(state.check_reissue("node.os", "sk-ant-old").is_ok());
+
+        for (key, reissue) in [("sk-ant-1", false), ("sk-ant-2", true), ("sk-ant-3", true)] {
+            state.current_grant("node.os", key);
+            state.node_grants.get_mut("node.os").unwrap().reissue = reissue;
+            state.end_grant("node.os", grant::REVOKED, 0);
+        }
+        assert!(state.check_reissue("node.os", "sk-ant-old").is_err());
+        assert!(state.check_reissue("other.os", "sk-ant-old").is_ok());
+    }
+}
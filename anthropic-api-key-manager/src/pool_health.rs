@@ -0,0 +1,78 @@
+//! Pool health: how much room the active keys have left, and delivery of
+//! alert notifications when the pool crosses a configured threshold.
+
+use hyperware_process_lib::http::client::send_request_await_response;
+use std::collections::HashMap;
+use url::Url;
+
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+
+// Resolved alerts kept for the UI's history
+pub const RESOLVED_ALERTS_KEPT: usize = 100;
+
+pub const POOL_EMPTY: &str = "pool_empty";
+pub const POOL_AT_CAPACITY: &str = "pool_at_capacity";
+pub const LOW_ACTIVE_KEYS: &str = "low_active_keys";
+pub const KEY_OVER_NODE_LIMIT: &str = "key_over_node_limit";
+pub const LOW_SPEND_HEADROOM: &str = "low_spend_headroom";
+
+/// What one active key is carrying
+pub struct KeyLoad {
+    pub nodes: u64,
+    pub spent: f64,
+    pub cap: Option<f64>,
+    pub max_nodes: Option<u64>,  // None when nothing limits the key's node count
+}
+
+/// Dollars left before every active key hits its cap. None when some key is
+/// uncapped, so spend doesn't bound the pool.
+pub fn spend_headroom(loads: &[KeyLoad]) -> Option<f64> {
+    loads.iter()
+        .map(|load| load.cap.map(|cap| (cap - load.spent).max(0.0)))
+        .sum()
+}
+
+/// How many more nodes the pool can take on, or None if nothing bounds it.
+/// Node slots come from the per-key node limits; spend room is the remaining
+/// headroom divided by what an assigned node has cost so far on average.
+pub fn projected_capacity(loads: &[KeyLoad]) -> Option<u64> {
+    if loads.is_empty() {
+        return Some(0);
+    }
+
+    let node_slots: Option<u64> = loads.iter()
+        .map(|load| load.max_nodes.map(|max| max.saturating_sub(load.nodes)))
+        .sum();
+
+    let nodes: u64 = loads.iter().map(|load| load.nodes).sum();
+    let spent: f64 = loads.iter().map(|load| load.spent).sum();
+    let spend_room = match spend_headroom(loads) {
+        Some(headroom) if nodes > 0 && spent > 0.0 => Some((headroom / (spent / nodes as f64)).floor() as u64),
+        _ => None,
+    };
+
+    match (node_slots, spend_room) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// POST an alert as JSON to the configured webhook
+pub async fn notify_webhook(url: &str, payload: &serde_json::Value) -> Result<(), String> {
+    let url = Url::parse(url).map_err(|e| format!("Invalid webhook URL: {}", e))?;
+    let mut headers = HashMap::new();
+    headers.insert("content-type".to_string(), "application/json".to_string());
+
+    let response = send_request_await_response(
+        http::Method::POST,
+        url,
+        Some(headers),
+        WEBHOOK_TIMEOUT_SECS * 1000,
+        payload.to_string().into_bytes(),
+    ).await.map_err(|e| format!("Webhook request failed: {:?}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("Webhook returned {}", response.status()));
+    }
+    Ok(())
+}
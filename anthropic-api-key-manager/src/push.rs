@@ -0,0 +1,59 @@
+//! Unprompted delivery of keys to nodes over p2p, for nodes that were not
+//! waiting on a request/response when their key became available.
+//!
+//! Sends run in a spawned task so a slow or offline node doesn't hold up the
+//! process; the task reports the outcome back through a local request.
+
+use hyperware_process_lib::{
+    our,
+    println,
+    hyperapp::{send, spawn},
+    Address, Request,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::KeyDeliveryReport;
+
+const PUSH_TIMEOUT_SECS: u64 = 30;
+
+/// Sent to the node as `{"ApiKeyPush": KeyPush}`. The outcome comes back to
+/// us as `{"RecordKeyDelivery": KeyDeliveryReport}`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyPush {
+    pub push_id: String,
+    pub api_key: String,
+    pub issued_at: i64,
+    pub reason: String,  // "waitlist" or "rotation"
+}
+
+pub fn new_push_id() -> String {
+    format!("push_{:016x}", rand::random::<u64>())
+}
+
+pub fn deliver(target: Address, push: KeyPush) {
+    spawn(async move {
+        let body = serde_json::json!({ "ApiKeyPush": push });
+        let request = Request::to(&target)
+            .body(serde_json::to_vec(&body).unwrap())
+            .expects_response(PUSH_TIMEOUT_SECS);
+
+        // Any reply counts as delivered; what the node does with the key is up to it
+        let error = match send::<serde_json::Value>(request).await {
+            Ok(_) => None,
+            Err(e) => Some(format!("{:?}", e)),
+        };
+
+        let report = KeyDeliveryReport {
+            node_id: target.node().to_string(),
+            push_id: push.push_id,
+            error,
+        };
+        let body = serde_json::json!({ "RecordKeyDelivery": report });
+        if let Err(e) = Request::to(our())
+            .body(serde_json::to_vec(&body).unwrap())
+            .send()
+        {
+            println!("Failed to record key delivery: {:?}", e);
+        }
+    });
+}
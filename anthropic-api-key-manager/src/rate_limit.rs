@@ -0,0 +1,219 @@
+//! Limits on how fast new nodes can get keys, so a swarm of freshly
+//! registered names can't drain the pool.
+//!
+//! Limits count issuances to new nodes over sliding windows, globally and per
+//! parent namespace. Names directly under a top-level namespace (`alice.os`)
+//! share that namespace's bucket, optionally with limits of their own. A
+//! burst of issuances inside the burst window pauses new issuance altogether
+//! until an admin resumes it or the pause runs out.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use crate::{Issuance, RateLimits};
+
+pub const HOUR_SECS: i64 = 3600;
+pub const DAY_SECS: i64 = 24 * 3600;
+
+// Used when a burst limit is set without a pause length
+pub const DEFAULT_BURST_PAUSE_SECS: i64 = 3600;
+
+// Names of the limits, reported in denials
+pub const GLOBAL_PER_HOUR: &str = "global_per_hour";
+pub const GLOBAL_PER_DAY: &str = "global_per_day";
+pub const NAMESPACE_PER_HOUR: &str = "namespace_per_hour";
+pub const NAMESPACE_PER_DAY: &str = "namespace_per_day";
+pub const TOP_LEVEL_PER_HOUR: &str = "top_level_per_hour";
+pub const TOP_LEVEL_PER_DAY: &str = "top_level_per_day";
+pub const BURST: &str = "burst";
+
+// Denial codes. ISSUANCE_PAUSED is also the pool alert raised while paused
+pub const RATE_LIMITED: &str = "rate_limited";
+pub const ISSUANCE_PAUSED: &str = "issuance_paused";
+
+/// Returned to the node, JSON-encoded, as the error of a refused key request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Denial {
+    pub code: String,       // RATE_LIMITED or ISSUANCE_PAUSED
+    pub limit: String,      // Which limit refused the request
+    pub retry_after_secs: Option<i64>,
+    pub message: String,
+}
+
+impl Denial {
+    pub fn to_error(&self) -> String {
+        serde_json::to_string(self).expect("denial serializes")
+    }
+}
+
+/// The namespace a node's name was minted under: `bot7.spam.os` belongs to
+/// `spam.os`, and `alice.os` to `os`
+pub fn parent_namespace(node_id: &str) -> String {
+    match node_id.split_once('.') {
+        Some((_, parent)) => parent.to_string(),
+        None => node_id.to_string(),
+    }
+}
+
+/// Whether a namespace is a top-level one like `os`, shared by every name minted directly under it
+pub fn is_top_level(namespace: &str) -> bool {
+    !namespace.contains('.')
+}
+
+/// Forget issuances older than the longest window
+pub fn prune(issuances: &mut VecDeque<Issuance>, now: i64) {
+    while issuances.front().map_or(false, |i| i.at <= now - DAY_SECS) {
+        issuances.pop_front();
+    }
+}
+
+fn in_window<'a>(issuances: &'a VecDeque<Issuance>, window: i64, now: i64) -> impl Iterator<Item = &'a Issuance> {
+    issuances.iter().filter(move |i| i.at > now - window)
+}
+
+/// Seconds until a full window frees a slot: when its oldest issuance ages out
+fn retry_after<'a>(mut counted: impl Iterator<Item = &'a Issuance>, window: i64, now: i64) -> Option<i64> {
+    counted.next().map(|oldest| (oldest.at + window - now).max(1))
+}
+
+/// The first limit a new issuance to `node_id` would break
+pub fn check(limits: &RateLimits, issuances: &VecDeque<Issuance>, node_id: &str, now: i64) -> Option<Denial> {
+    let namespace = parent_namespace(node_id);
+    // Top-level namespaces hold every ordinary name, so they can be given
+    // looser limits; without them the per-namespace limits apply
+    let (per_hour, per_day) = if is_top_level(&namespace) {
+        (
+            limits.top_level_per_hour.map(|max| (TOP_LEVEL_PER_HOUR, max))
+                .or(limits.namespace_per_hour.map(|max| (NAMESPACE_PER_HOUR, max))),
+            limits.top_level_per_day.map(|max| (TOP_LEVEL_PER_DAY, max))
+                .or(limits.namespace_per_day.map(|max| (NAMESPACE_PER_DAY, max))),
+        )
+    } else {
+        (
+            limits.namespace_per_hour.map(|max| (NAMESPACE_PER_HOUR, max)),
+            limits.namespace_per_day.map(|max| (NAMESPACE_PER_DAY, max)),
+        )
+    };
+    let checks = [
+        (limits.global_per_hour.map(|max| (GLOBAL_PER_HOUR, max)), HOUR_SECS, None),
+        (limits.global_per_day.map(|max| (GLOBAL_PER_DAY, max)), DAY_SECS, None),
+        (per_hour, HOUR_SECS, Some(&namespace)),
+        (per_day, DAY_SECS, Some(&namespace)),
+    ];
+
+    for (limit, window, scope) in checks {
+        let Some((name, max)) = limit else { continue };
+        let counted = || in_window(issuances, window, now)
+            .filter(|i| scope.map_or(true, |ns| i.namespace == *ns));
+        if counted().count() as u64 >= max {
+            let scope_note = scope.map(|ns| format!(" for {}", ns)).unwrap_or_default();
+            return Some(Denial {
+                code: RATE_LIMITED.to_string(),
+                limit: name.to_string(),
+                retry_after_secs: retry_after(counted(), window, now),
+                message: format!("Issuance limit of {} per {}{} reached",
+                                 max, if window == HOUR_SECS { "hour" } else { "day" }, scope_note),
+            });
+        }
+    }
+    None
+}
+
+/// Whether issuances inside the burst window have reached the burst limit.
+/// Issuances before `counted_from` don't count, so a spike that already
+/// caused a pause doesn't cause another once issuance resumes.
+pub fn is_burst(limits: &RateLimits, issuances: &VecDeque<Issuance>, counted_from: i64, now: i64) -> bool {
+    match (limits.burst_max, limits.burst_window_secs) {
+        (Some(max), Some(window)) => {
+            in_window(issuances, window, now).filter(|i| i.at >= counted_from).count() as u64 >= max
+        }
+        _ => false,
+    }
+}
+
+/// Issuance counts per namespace over the last day, busiest first
+pub fn namespace_counts(issuances: &VecDeque<Issuance>, now: i64) -> Vec<(String, u64)> {
+    let mut counts: HashMap<&str, u64> = HashMap::new();
+    for issuance in in_window(issuances, DAY_SECS, now) {
+        *counts.entry(&issuance.namespace).or_default() += 1;
+    }
+    let mut counts: Vec<(String, u64)> = counts.into_iter()
+        .map(|(namespace, count)| (namespace.to_string(), count))
+        .collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+pub fn count_in_window(issuances: &VecDeque<Issuance>, window: i64, now: i64) -> u64 {
+    in_window(issuances, window, now).count() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 100 * DAY_SECS;
+
+    fn issued(nodes: &[(&str, i64)]) -> VecDeque<Issuance> {
+        nodes.iter()
+            .map(|(node_id, ago)| Issuance {
+                at: NOW - ago,
+                node_id: node_id.to_string(),
+                namespace: parent_namespace(node_id),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parent_namespace_of_nested_and_top_level_names() {
+        assert_eq!(parent_namespace("bot7.spam.os"), "spam.os");
+        assert_eq!(parent_namespace("alice.os"), "os");
+        assert_eq!(parent_namespace("localhost"), "localhost");
+        assert!(is_top_level(&parent_namespace("alice.os")));
+        assert!(!is_top_level(&parent_namespace("bot7.spam.os")));
+    }
+
+    #[test]
+    fn global_limit_counts_only_the_window() {
+        let limits = RateLimits { global_per_hour: Some(2), ..Default::default() };
+        let issuances = issued(&[("a.os", HOUR_SECS + 1), ("b.os", 600)]);
+        assert!(check(&limits, &issuances, "c.os", NOW).is_none());
+
+        let issuances = issued(&[("a.os", 1200), ("b.os", 600)]);
+        let denial = check(&limits, &issuances, "c.os", NOW).unwrap();
+        assert_eq!(denial.limit, GLOBAL_PER_HOUR);
+        assert_eq!(denial.retry_after_secs, Some(HOUR_SECS - 1200));
+    }
+
+    #[test]
+    fn namespace_limit_is_per_namespace() {
+        let limits = RateLimits { namespace_per_day: Some(2), ..Default::default() };
+        let issuances = issued(&[("bot1.spam.os", 60), ("bot2.spam.os", 30)]);
+        assert_eq!(check(&limits, &issuances, "bot3.spam.os", NOW).unwrap().limit, NAMESPACE_PER_DAY);
+        assert!(check(&limits, &issuances, "node.other.os", NOW).is_none());
+    }
+
+    #[test]
+    fn top_level_names_share_a_bucket() {
+        let limits = RateLimits { namespace_per_hour: Some(2), ..Default::default() };
+        let issuances = issued(&[("sybil1.os", 60), ("sybil2.os", 30)]);
+        assert_eq!(check(&limits, &issuances, "sybil3.os", NOW).unwrap().limit, NAMESPACE_PER_HOUR);
+
+        // A separate top-level limit replaces the namespace one for them
+        let limits = RateLimits { namespace_per_hour: Some(2), top_level_per_hour: Some(3), ..Default::default() };
+        assert!(check(&limits, &issuances, "sybil3.os", NOW).is_none());
+        let issuances = issued(&[("sybil1.os", 60), ("sybil2.os", 30), ("sybil3.os", 10)]);
+        assert_eq!(check(&limits, &issuances, "sybil4.os", NOW).unwrap().limit, TOP_LEVEL_PER_HOUR);
+    }
+
+    #[test]
+    fn burst_needs_both_settings_and_ignores_counted_issuances() {
+        let issuances = issued(&[("a.os", 50), ("b.os", 40), ("c.os", 30)]);
+        assert!(!is_burst(&RateLimits { burst_max: Some(3), ..Default::default() }, &issuances, 0, NOW));
+
+        let limits = RateLimits { burst_max: Some(3), burst_window_secs: Some(60), ..Default::default() };
+        assert!(is_burst(&limits, &issuances, 0, NOW));
+        assert!(!is_burst(&limits, &issuances, NOW - 45, NOW));
+        assert!(!is_burst(&limits, &issuances, 0, NOW + 15));
+    }
+}
@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// How often the background task asks the process to run due jobs
+pub const SCHEDULER_TICK_MS: u64 = 30000;
+
+pub const COST_REFRESH_JOB: &str = "cost_refresh";
+pub const KEY_EXPIRY_JOB: &str = "key_expiry";
+pub const KEY_RECONCILE_JOB: &str = "key_reconcile";
+pub const KEY_ROTATION_JOB: &str = "key_rotation";
+pub const LEASE_EXPIRY_JOB: &str = "lease_expiry";
+pub const AUDIT_PRUNE_JOB: &str = "audit_prune";
+
+pub const MIN_INTERVAL_SECS: u64 = 60;
+pub const MAX_INTERVAL_SECS: u64 = 30 * 24 * 3600;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScheduledJob {
+    pub name: String,
+    pub interval_secs: u64,
+    pub jitter_secs: u64,  // Up to this many seconds are added to each interval
+    pub enabled: bool,
+    pub next_run_at: i64,
+    #[serde(default)]
+    pub last_run_at: Option<i64>,
+    #[serde(default)]
+    pub last_duration_ms: Option<u64>,
+    #[serde(default)]
+    pub last_outcome: Option<String>,  // "success", "skipped" or "failed"
+    #[serde(default)]
+    pub last_message: Option<String>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    #[serde(skip)]
+    pub running: bool,
+}
+
+pub enum JobResult {
+    Success(String),
+    Skipped(String),
+    Failed(String),
+}
+
+impl ScheduledJob {
+    fn new(name: &str, interval_secs: u64, jitter_secs: u64, now: i64) -> Self {
+        let mut job = ScheduledJob {
+            name: name.to_string(),
+            interval_secs,
+            jitter_secs,
+            enabled: true,
+            next_run_at: now,
+            last_run_at: None,
+            last_duration_ms: None,
+            last_outcome: None,
+            last_message: None,
+            last_error: None,
+            running: false,
+        };
+        job.schedule_next(now);
+        job
+    }
+
+    pub fn is_due(&self, now: i64) -> bool {
+        self.enabled && !self.running && self.next_run_at <= now
+    }
+
+    pub fn schedule_next(&mut self, now: i64) {
+        // Persisted settings predate validation, so don't trust them not to overflow
+        let jitter = match self.jitter_secs.checked_add(1) {
+            Some(bound) if self.jitter_secs > 0 => rand::random::<u64>() % bound,
+            _ => 0,
+        };
+        let delay = i64::try_from(self.interval_secs.saturating_add(jitter)).unwrap_or(i64::MAX);
+        self.next_run_at = now.saturating_add(delay);
+    }
+
+    pub fn record(&mut self, started_at: i64, duration_ms: u64, result: JobResult) {
+        self.last_run_at = Some(started_at);
+        self.last_duration_ms = Some(duration_ms);
+        let (outcome, message, error) = match result {
+            JobResult::Success(message) => ("success", Some(message), None),
+            JobResult::Skipped(message) => ("skipped", Some(message), None),
+            JobResult::Failed(error) => ("failed", None, Some(error)),
+        };
+        self.last_outcome = Some(outcome.to_string());
+        self.last_message = message;
+        self.last_error = error;
+    }
+}
+
+/// Check a job's timing settings before they are applied
+pub fn validate_timing(interval_secs: u64, jitter_secs: u64) -> Result<(), String> {
+    if interval_secs < MIN_INTERVAL_SECS {
+        return Err(format!("Interval must be at least {} seconds", MIN_INTERVAL_SECS));
+    }
+    if interval_secs > MAX_INTERVAL_SECS {
+        return Err(format!("Interval must be at most {} seconds", MAX_INTERVAL_SECS));
+    }
+    if jitter_secs > interval_secs {
+        return Err("Jitter must not exceed the interval".to_string());
+    }
+    Ok(())
+}
+
+/// Add any built-in jobs missing from persisted state, keeping existing settings
+pub fn ensure_default_jobs(jobs: &mut HashMap<String, ScheduledJob>, now: i64) {
+    let defaults = [
+        (COST_REFRESH_JOB, 3600, 300),
+        (KEY_EXPIRY_JOB, 300, 30),
+        (KEY_RECONCILE_JOB, 21600, 600),
+        (KEY_ROTATION_JOB, 3600, 300),
+        (LEASE_EXPIRY_JOB, 60, 10),
+        (AUDIT_PRUNE_JOB, 86400, 3600),
+    ];
+
+    for (name, interval_secs, jitter_secs) in defaults {
+        jobs.entry(name.to_string())
+            .or_insert_with(|| ScheduledJob::new(name, interval_secs, jitter_secs, now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule_next_stays_within_jitter() {
+        let mut job = ScheduledJob::new("test", 60, 10, 0);
+        for _ in 0..100 {
+            job.schedule_next(1000);
+            assert!((1060..=1070).contains(&job.next_run_at));
+        }
+    }
+
+    #[test]
+    fn schedule_next_saturates_on_extreme_settings() {
+        let mut job = ScheduledJob::new("test", 60, 0, 0);
+        job.interval_secs = u64::MAX;
+        job.jitter_secs = u64::MAX;
+        job.schedule_next(1000);
+        assert_eq!(job.next_run_at, i64::MAX);
+    }
+
+    #[test]
+    fn validate_timing_bounds() {
+        assert!(validate_timing(MIN_INTERVAL_SECS, 0).is_ok());
+        assert!(validate_timing(MAX_INTERVAL_SECS, MAX_INTERVAL_SECS).is_ok());
+        assert!(validate_timing(MIN_INTERVAL_SECS - 1, 0).is_err());
+        assert!(validate_timing(MAX_INTERVAL_SECS + 1, 0).is_err());
+        assert!(validate_timing(3600, 3601).is_err());
+    }
+}
@@ -0,0 +1,376 @@
+//! Encryption at rest for secrets in the persisted state.
+//!
+//! The admin key and pooled API keys are sealed with AES-256-GCM-SIV under a
+//! node-local key that lives in this package's VFS drive, outside the state
+//! blob. Sealing is deterministic (the nonce is a MAC of the plaintext), so the
+//! same secret always serializes the same way. That keeps `SaveOptions::OnDiff`
+//! from seeing a change on every save, and lets sealed keys be used as map keys;
+//! GCM-SIV stays secure when a nonce repeats for the same plaintext.
+//!
+//! Values without a sealed prefix are treated as legacy plaintext on load and
+//! get sealed the next time state is saved. `enc:v1:` values, from before the
+//! switch to GCM-SIV, can still be opened and are re-sealed as `enc:v2:`.
+//!
+//! A value that can't be unsealed (wrong or missing sealing key, corruption)
+//! doesn't fail the load: it is kept sealed, so it round-trips unchanged and
+//! the rest of the state survives. Callers check `is_sealed` before using one.
+
+use hyperware_process_lib::{our, println, vfs};
+use aes_gcm_siv::aead::{self, Aead};
+use aes_gcm_siv::{Aes256GcmSiv, Nonce};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::Sha256;
+use std::cell::{Cell, RefCell};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SEALED_PREFIX: &str = "enc:v2:";
+const LEGACY_SEALED_PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+const LEGACY_NONCE_LEN: usize = 16;
+const KEY_DRIVE: &str = "secrets";
+const KEY_FILE: &str = "state.key";
+const VFS_TIMEOUT_SECS: u64 = 5;
+
+// Bumped whenever the sealed format changes, so init can force a re-save
+pub const SEALING_VERSION: u32 = 2;
+
+struct SealingKeys {
+    enc: [u8; 32],
+    mac: [u8; 32],
+}
+
+thread_local! {
+    static SEALING_KEYS: RefCell<Option<SealingKeys>> = const { RefCell::new(None) };
+    static UNSEAL_FAILURES: Cell<usize> = const { Cell::new(0) };
+}
+
+fn derive(master: &[u8], label: &[u8]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(master).expect("HMAC accepts any key length");
+    mac.update(label);
+    mac.finalize().into_bytes().into()
+}
+
+/// Read the node-local master key, creating it only when the drive is known
+/// not to hold one. Any other failure is an error: writing a fresh key over
+/// an existing one would make every sealed secret unrecoverable.
+fn load_master_key() -> Result<Vec<u8>, String> {
+    let drive = vfs::create_drive(our().package_id(), KEY_DRIVE, Some(VFS_TIMEOUT_SECS))
+        .map_err(|e| format!("Failed to open secrets drive: {}", e))?;
+    let path = format!("{}/{}", drive, KEY_FILE);
+
+    let entries = vfs::open_dir(&drive, false, Some(VFS_TIMEOUT_SECS))
+        .and_then(|dir| dir.read())
+        .map_err(|e| format!("Failed to list secrets drive: {}", e))?;
+    let exists = entries.iter()
+        .any(|entry| entry.path.trim_start_matches('/') == path.trim_start_matches('/'));
+
+    if exists {
+        let key = vfs::open_file(&path, false, Some(VFS_TIMEOUT_SECS))
+            .and_then(|file| file.read())
+            .map_err(|e| format!("Failed to read sealing key: {}", e))?;
+        if key.len() == 32 {
+            return Ok(key);
+        }
+        return Err(format!("Sealing key at {} is corrupt ({} bytes)", path, key.len()));
+    }
+
+    println!("No sealing key found, generating one at {}", path);
+    let key: [u8; 32] = rand::random();
+    let file = vfs::create_file(&path, Some(VFS_TIMEOUT_SECS))
+        .map_err(|e| format!("Failed to create sealing key: {}", e))?;
+    file.write(&key).map_err(|e| format!("Failed to write sealing key: {}", e))?;
+    Ok(key.to_vec())
+}
+
+// Fully qualified: `hmac::Mac` has a `new_from_slice` of its own
+fn cipher(enc_key: &[u8; 32]) -> Aes256GcmSiv {
+    <Aes256GcmSiv as aead::KeyInit>::new(enc_key.into())
+}
+
+/// Seal under a fixed key instead of the one in the VFS, which tests can't reach
+#[cfg(test)]
+pub fn use_test_key(master: &[u8]) {
+    SEALING_KEYS.with(|keys| {
+        *keys.borrow_mut() = Some(SealingKeys {
+            enc: derive(master, b"anthropic-api-key-manager/enc"),
+            mac: derive(master, b"anthropic-api-key-manager/mac"),
+        });
+    });
+}
+
+fn with_keys<T>(f: impl FnOnce(&SealingKeys) -> Result<T, String>) -> Result<T, String> {
+    SEALING_KEYS.with(|keys| {
+        let mut keys = keys.borrow_mut();
+        if keys.is_none() {
+            let master = load_master_key()?;
+            *keys = Some(SealingKeys {
+                enc: derive(&master, b"anthropic-api-key-manager/enc"),
+                mac: derive(&master, b"anthropic-api-key-manager/mac"),
+            });
+        }
+        f(keys.as_ref().unwrap())
+    })
+}
+
+/// Make sure the sealing key exists. Called from init so a broken VFS shows
+/// up at startup rather than on the first save.
+pub fn ensure_key() -> Result<(), String> {
+    with_keys(|_| Ok(()))
+}
+
+/// A key for integrity checks on data that isn't secret, derived from the
+/// sealing key so it never has to be stored on its own
+pub fn derive_subkey(label: &[u8]) -> Result<[u8; 32], String> {
+    with_keys(|keys| Ok(derive(&keys.mac, label)))
+}
+
+/// Whether a value is still sealed, i.e. it couldn't be opened on load
+pub fn is_sealed(value: &str) -> bool {
+    value.starts_with(SEALED_PREFIX) || value.starts_with(LEGACY_SEALED_PREFIX)
+}
+
+/// How many values failed to unseal since the last call
+pub fn take_unseal_failures() -> usize {
+    UNSEAL_FAILURES.with(|failures| failures.replace(0))
+}
+
+pub fn seal(plaintext: &str) -> Result<String, String> {
+    // A value we couldn't open is written back exactly as it was loaded
+    if is_sealed(plaintext) {
+        return Ok(plaintext.to_string());
+    }
+
+    with_keys(|keys| {
+        let mut mac = HmacSha256::new_from_slice(&keys.mac).expect("HMAC accepts any key length");
+        mac.update(plaintext.as_bytes());
+        let tag = mac.finalize().into_bytes();
+        let nonce = Nonce::from_slice(&tag[..NONCE_LEN]);
+
+        let ciphertext = cipher(&keys.enc).encrypt(nonce, plaintext.as_bytes())
+            .map_err(|_| "Failed to seal value".to_string())?;
+
+        let mut out = nonce.to_vec();
+        out.extend(ciphertext);
+        Ok(format!("{}{}", SEALED_PREFIX, BASE64.encode(out)))
+    })
+}
+
+pub fn unseal(value: &str) -> Result<String, String> {
+    if let Some(encoded) = value.strip_prefix(LEGACY_SEALED_PREFIX) {
+        return unseal_legacy(encoded);
+    }
+    let Some(encoded) = value.strip_prefix(SEALED_PREFIX) else {
+        // Legacy plaintext from before encryption at rest
+        return Ok(value.to_string());
+    };
+
+    let raw = BASE64.decode(encoded).map_err(|e| format!("Corrupt sealed value: {}", e))?;
+    if raw.len() < NONCE_LEN {
+        return Err("Corrupt sealed value: too short".to_string());
+    }
+    let (nonce, ciphertext) = raw.split_at(NONCE_LEN);
+
+    with_keys(|keys| {
+        let data = cipher(&keys.enc).decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| "Sealed value failed authentication (wrong sealing key?)".to_string())?;
+        String::from_utf8(data).map_err(|e| format!("Corrupt sealed value: {}", e))
+    })
+}
+
+/// Open an `enc:v1:` value. Only kept so state sealed before the switch to
+/// GCM-SIV can be read once and re-sealed; nothing is sealed this way any more.
+fn unseal_legacy(encoded: &str) -> Result<String, String> {
+    let raw = BASE64.decode(encoded).map_err(|e| format!("Corrupt sealed value: {}", e))?;
+    if raw.len() < LEGACY_NONCE_LEN {
+        return Err("Corrupt sealed value: too short".to_string());
+    }
+    let (nonce, ciphertext) = raw.split_at(LEGACY_NONCE_LEN);
+
+    with_keys(|keys| {
+        let mut data = ciphertext.to_vec();
+        for (counter, chunk) in data.chunks_mut(32).enumerate() {
+            let mut mac = HmacSha256::new_from_slice(&keys.enc).expect("HMAC accepts any key length");
+            mac.update(nonce);
+            mac.update(&(counter as u32).to_be_bytes());
+            let block = mac.finalize().into_bytes();
+            for (byte, k) in chunk.iter_mut().zip(block.iter()) {
+                *byte ^= k;
+            }
+        }
+
+        let mut mac = HmacSha256::new_from_slice(&keys.mac).expect("HMAC accepts any key length");
+        mac.update(&data);
+        mac.verify_truncated_left(nonce)
+            .map_err(|_| "Sealed value failed authentication (wrong sealing key?)".to_string())?;
+
+        String::from_utf8(data).map_err(|e| format!("Corrupt sealed value: {}", e))
+    })
+}
+
+/// Unseal for deserialization: a failure is logged and counted, and the value
+/// stays sealed rather than failing the whole state
+fn unseal_or_keep(value: String) -> String {
+    match unseal(&value) {
+        Ok(plaintext) => plaintext,
+        Err(e) => {
+            println!("Failed to unseal a stored secret, keeping it sealed: {}", e);
+            UNSEAL_FAILURES.with(|failures| failures.set(failures.get() + 1));
+            value
+        }
+    }
+}
+
+/// `#[serde(with = "sealed::option")]` for `Option<String>`
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+        value.as_deref()
+            .map(seal)
+            .transpose()
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+        Ok(Option::<String>::deserialize(deserializer)?.map(unseal_or_keep))
+    }
+}
+
+/// `#[serde(with = "sealed::set")]` for `HashSet<String>`
+pub mod set {
+    use super::*;
+    use std::collections::HashSet;
+
+    pub fn serialize<S: Serializer>(value: &HashSet<String>, serializer: S) -> Result<S::Ok, S::Error> {
+        let sealed: Vec<String> = value.iter()
+            .map(|v| seal(v))
+            .collect::<Result<_, _>>()
+            .map_err(serde::ser::Error::custom)?;
+        sealed.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashSet<String>, D::Error> {
+        Ok(Vec::<String>::deserialize(deserializer)?
+            .into_iter()
+            .map(unseal_or_keep)
+            .collect())
+    }
+}
+
+/// `#[serde(with = "sealed::map_keys")]` for maps keyed by a secret
+pub mod map_keys {
+    use super::*;
+    use std::collections::HashMap;
+
+    pub fn serialize<S, V>(value: &HashMap<String, V>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        V: Serialize,
+    {
+        let sealed: HashMap<String, &V> = value.iter()
+            .map(|(k, v)| seal(k).map(|k| (k, v)))
+            .collect::<Result<_, _>>()
+            .map_err(serde::ser::Error::custom)?;
+        sealed.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D, V>(deserializer: D) -> Result<HashMap<String, V>, D::Error>
+    where
+        D: Deserializer<'de>,
+        V: Deserialize<'de>,
+    {
+        Ok(HashMap::<String, V>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(k, v)| (unseal_or_keep(k), v))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_round_trips_deterministically() {
+        use_test_key(&[1; 32]);
+        let sealed = seal("sk-ant-api03-secret").unwrap();
+        assert!(sealed.starts_with(SEALED_PREFIX));
+        assert_eq!(sealed, seal("sk-ant-api03-secret").unwrap());
+        assert_ne!(sealed, seal("sk-ant-api03-other").unwrap());
+        assert_eq!(unseal(&sealed).unwrap(), "sk-ant-api03-secret");
+    }
+
+    #[test]
+    fn plaintext_is_accepted_as_legacy() {
+        use_test_key(&[1; 32]);
+        assert_eq!(unseal("sk-ant-api03-plain").unwrap(), "sk-ant-api03-plain");
+    }
+
+    #[test]
+    fn tampered_value_fails_authentication() {
+        use_test_key(&[1; 32]);
+        let sealed = seal("sk-ant-api03-secret").unwrap();
+        let mut raw = BASE64.decode(sealed.strip_prefix(SEALED_PREFIX).unwrap()).unwrap();
+        *raw.last_mut().unwrap() ^= 1;
+        let tampered = format!("{}{}", SEALED_PREFIX, BASE64.encode(raw));
+        assert!(unseal(&tampered).is_err());
+    }
+
+    #[test]
+    fn legacy_v1_values_still_open() {
+        use_test_key(&[1; 32]);
+        let (enc, mac_key) = SEALING_KEYS.with(|keys| {
+            let keys = keys.borrow();
+            let keys = keys.as_ref().unwrap();
+            (keys.enc, keys.mac)
+        });
+
+        // Seal the way v1 did: HMAC-SHA256 tag as nonce, HMAC-CTR keystream
+        let plaintext = b"sk-ant-REDACTED";
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&mac_key).unwrap();
+        mac.update(plaintext);
+        let nonce = mac.finalize().into_bytes()[..LEGACY_NONCE_LEN].to_vec();
+        let mut data = plaintext.to_vec();
+        for (counter, chunk) in data.chunks_mut(32).enumerate() {
+            let mut mac = <HmacSha256 as Mac>::new_from_slice(&enc).unwrap();
+            mac.update(&nonce);
+            mac.update(&(counter as u32).to_be_bytes());
+            for (byte, k) in chunk.iter_mut().zip(mac.finalize().into_bytes().iter()) {
+                *byte ^= k;
+            }
+        }
+        let legacy = format!("{}{}", LEGACY_SEALED_PREFIX, BASE64.encode([nonce, data].concat()));
+
+        assert_eq!(unseal(&legacy).unwrap().as_bytes(), plaintext);
+    }
+
+    #[test]
+    fn undecryptable_values_stay_sealed_without_failing_the_load() {
+        use_test_key(&[1; 32]);
+        let sealed = seal("sk-ant-api03-secret").unwrap();
+        let json = serde_json::json!({ "admin": sealed, "keys": [sealed, "sk-ant-api03-plain"] });
+
+        #[derive(Deserialize)]
+        struct State {
+            #[serde(with = "option")]
+            admin: Option<String>,
+            #[serde(with = "set")]
+            keys: std::collections::HashSet<String>,
+        }
+
+        use_test_key(&[2; 32]);
+        take_unseal_failures();
+        let state: State = serde_json::from_value(json).unwrap();
+        assert_eq!(take_unseal_failures(), 2);
+        assert!(state.admin.as_deref().map_or(false, is_sealed));
+        assert!(state.keys.contains("sk-ant-api03-plain"));
+
+        // Written back unchanged, so the original key can still open it later
+        assert_eq!(seal(state.admin.as_deref().unwrap()).unwrap(), sealed);
+    }
+}
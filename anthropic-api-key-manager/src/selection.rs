@@ -0,0 +1,176 @@
+//! Strategies for choosing which pooled key a new node gets.
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::SelectionStrategyKind;
+
+// Spend window used by the lowest-recent-spend strategy
+pub const RECENT_SPEND_WINDOW_SECS: i64 = 7 * 24 * 3600;
+
+pub const DEFAULT_WEIGHT: u32 = 1;
+
+/// An active key that could take the node
+pub struct Candidate {
+    pub key: String,
+    pub nodes: u64,
+    pub recent_spend: f64,
+    pub weight: u32,
+}
+
+pub trait Strategy {
+    /// Index of the chosen candidate. `cursor` is persisted between calls for
+    /// strategies that need to remember where they left off.
+    fn pick(&self, candidates: &[Candidate], cursor: &mut u64) -> Option<usize>;
+}
+
+struct Random;
+struct LeastAssigned;
+struct RoundRobin;
+struct LowestRecentSpend;
+struct Weighted;
+
+/// Random choice among the candidates that share the lowest score
+fn pick_lowest(candidates: &[Candidate], score: impl Fn(&Candidate) -> f64) -> Option<usize> {
+    let lowest = candidates.iter().map(&score).fold(f64::INFINITY, f64::min);
+    let tied: Vec<usize> = (0..candidates.len())
+        .filter(|&i| score(&candidates[i]) <= lowest)
+        .collect();
+    tied.choose(&mut rand::thread_rng()).copied()
+}
+
+impl Strategy for Random {
+    fn pick(&self, candidates: &[Candidate], _cursor: &mut u64) -> Option<usize> {
+        (!candidates.is_empty()).then(|| rand::thread_rng().gen_range(0..candidates.len()))
+    }
+}
+
+impl Strategy for LeastAssigned {
+    fn pick(&self, candidates: &[Candidate], _cursor: &mut u64) -> Option<usize> {
+        pick_lowest(candidates, |c| c.nodes as f64)
+    }
+}
+
+impl Strategy for RoundRobin {
+    fn pick(&self, candidates: &[Candidate], cursor: &mut u64) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+        let index = (*cursor % candidates.len() as u64) as usize;
+        *cursor = cursor.wrapping_add(1);
+        Some(index)
+    }
+}
+
+impl Strategy for LowestRecentSpend {
+    fn pick(&self, candidates: &[Candidate], _cursor: &mut u64) -> Option<usize> {
+        pick_lowest(candidates, |c| c.recent_spend)
+    }
+}
+
+impl Strategy for Weighted {
+    /// Random choice with probability proportional to weight. Zero-weight
+    /// keys are skipped while any candidate has weight, and picked uniformly
+    /// when none does, so nodes aren't waitlisted while keys are free.
+    fn pick(&self, candidates: &[Candidate], cursor: &mut u64) -> Option<usize> {
+        let total: u64 = candidates.iter().map(|c| c.weight as u64).sum();
+        if total == 0 {
+            return Random.pick(candidates, cursor);
+        }
+        let mut roll = rand::thread_rng().gen_range(0..total);
+        for (i, candidate) in candidates.iter().enumerate() {
+            if roll < candidate.weight as u64 {
+                return Some(i);
+            }
+            roll -= candidate.weight as u64;
+        }
+        None
+    }
+}
+
+pub fn strategy(kind: &SelectionStrategyKind) -> Box<dyn Strategy> {
+    match kind {
+        SelectionStrategyKind::Random => Box::new(Random),
+        SelectionStrategyKind::LeastAssigned => Box::new(LeastAssigned),
+        SelectionStrategyKind::RoundRobin => Box::new(RoundRobin),
+        SelectionStrategyKind::LowestRecentSpend => Box::new(LowestRecentSpend),
+        SelectionStrategyKind::Weighted => Box::new(Weighted),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(key: &str, nodes: u64, recent_spend: f64, weight: u32) -> Candidate {
+        Candidate { key: key.to_string(), nodes, recent_spend, weight }
+    }
+
+    const ALL: [SelectionStrategyKind; 5] = [
+        SelectionStrategyKind::Random,
+        SelectionStrategyKind::LeastAssigned,
+        SelectionStrategyKind::RoundRobin,
+        SelectionStrategyKind::LowestRecentSpend,
+        SelectionStrategyKind::Weighted,
+    ];
+
+    #[test]
+    fn no_candidates_no_pick() {
+        for kind in ALL {
+            assert_eq!(strategy(&kind).pick(&[], &mut 0), None);
+        }
+    }
+
+    #[test]
+    fn least_assigned_picks_the_emptiest_key() {
+        let candidates = [candidate("a", 3, 0.0, 1), candidate("b", 1, 0.0, 1), candidate("c", 2, 0.0, 1)];
+        for _ in 0..20 {
+            assert_eq!(strategy(&SelectionStrategyKind::LeastAssigned).pick(&candidates, &mut 0), Some(1));
+        }
+    }
+
+    #[test]
+    fn lowest_recent_spend_breaks_ties_among_the_cheapest() {
+        let candidates = [candidate("a", 0, 2.0, 1), candidate("b", 0, 0.5, 1), candidate("c", 0, 0.5, 1)];
+        for _ in 0..20 {
+            let pick = strategy(&SelectionStrategyKind::LowestRecentSpend).pick(&candidates, &mut 0);
+            assert!(matches!(pick, Some(1) | Some(2)));
+        }
+    }
+
+    #[test]
+    fn round_robin_walks_the_candidates_in_order() {
+        let candidates = [candidate("a", 0, 0.0, 1), candidate("b", 0, 0.0, 1), candidate("c", 0, 0.0, 1)];
+        let mut cursor = 0;
+        let picks: Vec<Option<usize>> = (0..4)
+            .map(|_| strategy(&SelectionStrategyKind::RoundRobin).pick(&candidates, &mut cursor))
+            .collect();
+        assert_eq!(picks, vec![Some(0), Some(1), Some(2), Some(0)]);
+        assert_eq!(cursor, 4);
+    }
+
+    #[test]
+    fn weighted_never_picks_zero_weight_keys() {
+        let candidates = [candidate("a", 0, 0.0, 0), candidate("b", 0, 0.0, 3), candidate("c", 0, 0.0, 0)];
+        for _ in 0..50 {
+            assert_eq!(strategy(&SelectionStrategyKind::Weighted).pick(&candidates, &mut 0), Some(1));
+        }
+
+    }
+
+    #[test]
+    fn weighted_picks_uniformly_when_every_weight_is_zero() {
+        let all_zero = [candidate("a", 0, 0.0, 0), candidate("b", 0, 0.0, 0)];
+        for _ in 0..20 {
+            assert!(matches!(strategy(&SelectionStrategyKind::Weighted).pick(&all_zero, &mut 0), Some(0) | Some(1)));
+        }
+    }
+
+    #[test]
+    fn random_stays_in_range() {
+        let candidates = [candidate("a", 0, 0.0, 1), candidate("b", 0, 0.0, 1)];
+        for _ in 0..20 {
+            assert!(matches!(strategy(&SelectionStrategyKind::Random).pick(&candidates, &mut 0), Some(0) | Some(1)));
+        }
+    }
+}
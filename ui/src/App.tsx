@@ -7,6 +7,50 @@ import { ChartDataPoint } from './types/api-key-manager';
 type CostRecord = AnthropicApiKeyManager.CostRecord;
 type NodeAssignment = AnthropicApiKeyManager.NodeAssignment;
 type ApiKey = AnthropicApiKeyManager.ApiKeyInfo;
+type JobInfo = AnthropicApiKeyManager.JobInfo;
+type AuditEntry = AnthropicApiKeyManager.AuditEntry;
+type ReconcileReport = AnthropicApiKeyManager.ReconcileReport;
+type PoolHealth = AnthropicApiKeyManager.PoolHealthRes;
+type SelectionStrategy = AnthropicApiKeyManager.SelectionStrategyKind;
+type PoolMode = AnthropicApiKeyManager.PoolMode;
+type WaitlistEntry = AnthropicApiKeyManager.WaitlistEntry;
+type KeyDelivery = AnthropicApiKeyManager.KeyDelivery;
+type KeyRotation = AnthropicApiKeyManager.KeyRotation;
+type RotationPolicy = AnthropicApiKeyManager.RotationPolicy;
+type ReissuePolicy = AnthropicApiKeyManager.ReissuePolicy;
+type NodeGrant = AnthropicApiKeyManager.NodeGrant;
+type LeasePolicy = AnthropicApiKeyManager.LeasePolicy;
+type NodeBan = AnthropicApiKeyManager.NodeBan;
+type RateLimits = AnthropicApiKeyManager.RateLimits;
+type RateLimitStatus = AnthropicApiKeyManager.RateLimitStatus;
+type InviteCode = AnthropicApiKeyManager.InviteCode;
+
+const RATE_LIMIT_FIELDS: { field: keyof RateLimits; label: string }[] = [
+  { field: 'global_per_hour', label: 'New nodes per hour' },
+  { field: 'global_per_day', label: 'New nodes per day' },
+  { field: 'namespace_per_hour', label: 'Per namespace per hour' },
+  { field: 'namespace_per_day', label: 'Per namespace per day' },
+  { field: 'top_level_per_hour', label: 'Top-level names (e.g. .os) per hour' },
+  { field: 'top_level_per_day', label: 'Top-level names (e.g. .os) per day' },
+  { field: 'burst_max', label: 'Burst: issuances' },
+  { field: 'burst_window_secs', label: 'Burst: window (seconds)' },
+  { field: 'burst_pause_secs', label: 'Burst: pause (seconds, default 3600)' },
+];
+
+const POOL_MODES: { value: PoolMode; label: string }[] = [
+  { value: 'Shared' as PoolMode, label: 'Shared: nodes share keys' },
+  { value: 'Dedicated' as PoolMode, label: 'Dedicated: one key per node' },
+];
+
+const SELECTION_STRATEGIES: { value: SelectionStrategy; label: string }[] = [
+  { value: 'LeastAssigned' as SelectionStrategy, label: 'Least assigned' },
+  { value: 'RoundRobin' as SelectionStrategy, label: 'Round robin' },
+  { value: 'LowestRecentSpend' as SelectionStrategy, label: 'Lowest recent spend' },
+  { value: 'Weighted' as SelectionStrategy, label: 'Weighted' },
+  { value: 'Random' as SelectionStrategy, label: 'Random' },
+];
+
+const POOL_HEALTH_POLL_MS = 60000;
 import {
   LineChart, Line, XAxis, YAxis, CartesianGrid, Tooltip,
   ResponsiveContainer, ReferenceLine, Label, Dot
@@ -75,11 +119,11 @@ const ConnectedNodesModal: React.FC<{ isOpen: boolean; onClose: () => void }> =
   const nodesByKey = new Map<string, { nodes: string[], keyInfo: ApiKey | undefined }>();
   
   nodeHistory.forEach(assignment => {
-    if (!nodesByKey.has(assignment.api_key)) {
-      const keyInfo = apiKeys.find(k => k.key === assignment.api_key);
-      nodesByKey.set(assignment.api_key, { nodes: [], keyInfo });
+    if (!nodesByKey.has(assignment.key_id)) {
+      const keyInfo = apiKeys.find(k => k.key_id === assignment.key_id);
+      nodesByKey.set(assignment.key_id, { nodes: [], keyInfo });
     }
-    const entry = nodesByKey.get(assignment.api_key);
+    const entry = nodesByKey.get(assignment.key_id);
     if (entry && !entry.nodes.includes(assignment.node_id)) {
       entry.nodes.push(assignment.node_id);
     }
@@ -106,7 +150,7 @@ const ConnectedNodesModal: React.FC<{ isOpen: boolean; onClose: () => void }> =
               }}>
                 <div style={{ display: 'flex', justifyContent: 'space-between', alignItems: 'center' }}>
                   <code style={{ fontSize: '0.875rem', color: 'var(--text-primary)' }}>
-                    {key.substring(0, 20)}...
+                    {data.keyInfo?.key_hint || nodeHistory.find(n => n.key_id === key)?.key_hint || key}
                   </code>
                   <span className={`status ${data.keyInfo?.status || 'unknown'}`}>
                     {data.keyInfo?.status || 'unknown'}
@@ -115,7 +159,8 @@ const ConnectedNodesModal: React.FC<{ isOpen: boolean; onClose: () => void }> =
               </div>
               <ul className="node-list">
                 {data.nodes.map(nodeId => {
-                  const assignment = nodeHistory.find(n => n.node_id === nodeId && n.api_key === key);
+                  const matching = nodeHistory.filter(n => n.node_id === nodeId && n.key_id === key);
+                  const assignment = matching.find(n => !n.expired) ?? matching[matching.length - 1];
                   return (
                     <li key={nodeId} className="node-item">
                       <div className="node-info">
@@ -125,6 +170,16 @@ const ConnectedNodesModal: React.FC<{ isOpen: boolean; onClose: () => void }> =
                             Connected: {new Date(assignment.issued_at * 1000).toLocaleString()}
                           </div>
                         )}
+                        {assignment?.lease_expires_at != null && (
+                          <div className="node-date">
+                            {assignment.expired ? 'Lease expired' : 'Lease runs to'}: {new Date(assignment.lease_expires_at * 1000).toLocaleString()}
+                          </div>
+                        )}
+                        {assignment?.cost != null && (
+                          <div className="node-date">
+                            Spend since connecting: ${assignment.cost.toFixed(2)}
+                          </div>
+                        )}
                       </div>
                     </li>
                   );
@@ -138,6 +193,37 @@ const ConnectedNodesModal: React.FC<{ isOpen: boolean; onClose: () => void }> =
   );
 };
 
+// Open pool alerts, shown above the dashboard
+const PoolAlerts: React.FC = () => {
+  const [health, setHealth] = useState<PoolHealth | null>(null);
+  
+  useEffect(() => {
+    const loadHealth = async () => {
+      try {
+        setHealth(await AnthropicApiKeyManager.get_pool_health());
+      } catch (error) {
+        console.error('Failed to load pool health:', error);
+      }
+    };
+    loadHealth();
+    const interval = setInterval(loadHealth, POOL_HEALTH_POLL_MS);
+    return () => clearInterval(interval);
+  }, []);
+  
+  if (!health || health.open_alerts.length === 0) return null;
+  
+  return (
+    <div className="error">
+      {health.open_alerts.map((alert) => (
+        <div key={alert.kind}>
+          <strong>{alert.kind.replace(/_/g, ' ')}</strong>: {alert.message}
+          {' '}(since {new Date(alert.raised_at * 1000).toLocaleString()})
+        </div>
+      ))}
+    </div>
+  );
+};
+
 // Dashboard Component
 const Dashboard: React.FC = () => {
   const { apiKeys, nodeHistory, totalCosts, costData, loading, error } = useApiKeyManagerStore();
@@ -168,6 +254,7 @@ const Dashboard: React.FC = () => {
   return (
     <div className="dashboard">
       <ConnectedNodesModal isOpen={showNodesModal} onClose={() => setShowNodesModal(false)} />
+      <PoolAlerts />
       
       <div className="stats-grid">
         <div className="stat-card">
@@ -184,7 +271,7 @@ const Dashboard: React.FC = () => {
           style={{ cursor: 'pointer' }}
         >
           <h3>Connected Nodes</h3>
-          <p className="stat-value">{nodeHistory.length}</p>
+          <p className="stat-value">{nodeHistory.filter(n => !n.expired).length}</p>
         </div>
       </div>
       
@@ -237,28 +324,96 @@ const Dashboard: React.FC = () => {
   );
 };
 
+// Per-key cost chart, shown when a key is selected in the key list
+const KeyCostChart: React.FC = () => {
+  const { apiKeys, selectedKey, keyCosts, nodeHistory } = useApiKeyManagerStore();
+  
+  if (!selectedKey || !keyCosts || keyCosts.key_id !== selectedKey) return null;
+  
+  const keyNodes = nodeHistory.filter(n => n.key_id === selectedKey);
+  const keyHint = apiKeys.find(k => k.key_id === selectedKey)?.key_hint || selectedKey;
+  const data = prepareChartData(keyCosts.costs, keyNodes);
+  
+  return (
+    <div className="chart-container">
+      <h3>Cost Over Time for {keyHint} (${keyCosts.total.toFixed(2)})</h3>
+      {data.length > 0 ? (
+        <ResponsiveContainer width="100%" height={300}>
+          <LineChart data={data} margin={{ top: 20, right: 30, left: 20, bottom: 20 }}>
+            <CartesianGrid strokeDasharray="3 3" stroke="var(--chart-grid)" />
+            <XAxis 
+              dataKey="timestamp"
+              type="number"
+              domain={['dataMin', 'dataMax']}
+              tickFormatter={(timestamp) => new Date(timestamp * 1000).toLocaleDateString()}
+              stroke="var(--chart-text)"
+            />
+            <YAxis stroke="var(--chart-text)" />
+            <Tooltip content={<CustomTooltip />} />
+            <Line 
+              type="monotone" 
+              dataKey="totalCost" 
+              stroke="#8884d8" 
+              strokeWidth={2}
+              dot={<CustomDot />}
+            />
+            {keyNodes.map((node, index) => (
+              <ReferenceLine 
+                key={index}
+                x={node.issued_at} 
+                stroke="green" 
+                strokeDasharray="5 5"
+                opacity={0.5}
+              />
+            ))}
+          </LineChart>
+        </ResponsiveContainer>
+      ) : (
+        <p style={{ textAlign: 'center', color: 'var(--text-secondary)', padding: '2rem' }}>
+          No costs attributed to this key yet. Costs are attributed through the key's workspace.
+        </p>
+      )}
+    </div>
+  );
+};
+
 // KeyList Component
 const KeyList: React.FC = () => {
-  const { apiKeys, setSelectedKey } = useApiKeyManagerStore();
+  const { apiKeys, selectedKey, setSelectedKey } = useApiKeyManagerStore();
   const [newKey, setNewKey] = useState('');
+  const [newKeyWorkspace, setNewKeyWorkspace] = useState('');
+  const [newKeyTtlDays, setNewKeyTtlDays] = useState('');
+  const [newKeySpendCap, setNewKeySpendCap] = useState('');
+  const [newKeyMaxNodes, setNewKeyMaxNodes] = useState('');
+  const [revealed, setRevealed] = useState<{ key_id: string; api_key: string } | null>(null);
   
   const handleAddKey = async () => {
     if (!newKey.trim()) return;
     try {
-      const response = await AnthropicApiKeyManager.add_api_key({ api_key: newKey });
+      const response = await AnthropicApiKeyManager.add_api_key({
+        api_key: newKey,
+        workspace_id: newKeyWorkspace.trim() || null,
+        ttl_seconds: newKeyTtlDays.trim() ? Math.round(parseFloat(newKeyTtlDays) * 86400) : null,
+        spend_cap: newKeySpendCap.trim() ? parseFloat(newKeySpendCap) : null,
+        max_nodes: newKeyMaxNodes.trim() ? parseInt(newKeyMaxNodes, 10) : null
+      });
       if (!response.success) {
         throw new Error(response.message || 'Failed to add key');
       }
       setNewKey('');
+      setNewKeyWorkspace('');
+      setNewKeyTtlDays('');
+      setNewKeySpendCap('');
+      setNewKeyMaxNodes('');
       await refreshKeys();
     } catch (error) {
       console.error('Failed to add key:', error);
     }
   };
   
-  const handleRemoveKey = async (key: string) => {
+  const handleRemoveKey = async (keyId: string) => {
     try {
-      const response = await AnthropicApiKeyManager.remove_api_key({ api_key: key });
+      const response = await AnthropicApiKeyManager.remove_api_key({ key_id: keyId });
       if (!response.success) {
         throw new Error(response.message || 'Failed to remove key');
       }
@@ -268,6 +423,66 @@ const KeyList: React.FC = () => {
     }
   };
   
+  const handleRevealKey = async (keyId: string) => {
+    const reason = window.prompt('Revealing the full key is recorded. Reason (optional):');
+    if (reason === null) return;
+    try {
+      const response = await AnthropicApiKeyManager.reveal_api_key({ key_id: keyId, reason: reason.trim() || null });
+      setRevealed(response);
+    } catch (error) {
+      console.error('Failed to reveal key:', error);
+    }
+  };
+  
+  const handleSetMaxNodes = async (keyId: string, current: number | null | undefined) => {
+    const input = window.prompt('Maximum nodes on this key (empty for the default):', current?.toString() ?? '');
+    if (input === null) return;
+    try {
+      await AnthropicApiKeyManager.set_max_nodes({ key_id: keyId, max_nodes: input.trim() ? parseInt(input, 10) : null });
+      await refreshKeys();
+    } catch (error) {
+      console.error('Failed to set node limit:', error);
+    }
+  };
+  
+  const handleSetWeight = async (keyId: string, current: number) => {
+    const input = window.prompt('Weight for this key under the weighted strategy:', current.toString());
+    if (input === null) return;
+    try {
+      await AnthropicApiKeyManager.set_key_weight({ key_id: keyId, weight: input.trim() ? parseInt(input, 10) : null });
+      await refreshKeys();
+    } catch (error) {
+      console.error('Failed to set key weight:', error);
+    }
+  };
+  
+  const handleRotateKey = async (keyId: string) => {
+    const input = window.prompt('Move this key\'s nodes to other keys. Hours the old key keeps working (empty for the policy default, 0 to cut it off now):');
+    if (input === null) return;
+    try {
+      const rotation = await AnthropicApiKeyManager.rotate_api_key({
+        key_id: keyId,
+        grace_secs: input.trim() ? Math.round(parseFloat(input) * 3600) : null
+      });
+      const waitlisted = rotation.moves.filter((move) => move.new_key_id == null).length;
+      window.alert(`Moved ${rotation.moves.length - waitlisted} nodes${waitlisted > 0 ? `, ${waitlisted} waitlisted` : ''}`);
+      await refreshKeys();
+    } catch (error) {
+      console.error('Failed to rotate key:', error);
+      window.alert(`Failed to rotate key: ${error}`);
+    }
+  };
+  
+  const handleSelectKey = async (keyId: string) => {
+    setSelectedKey(keyId);
+    try {
+      const response = await AnthropicApiKeyManager.get_key_costs({ key_id: keyId, start_date: null, end_date: null });
+      useApiKeyManagerStore.getState().setKeyCosts(response);
+    } catch (error) {
+      console.error('Failed to load key costs:', error);
+    }
+  };
+  
   const refreshKeys = async () => {
     try {
       const response = await AnthropicApiKeyManager.list_keys();
@@ -288,6 +503,38 @@ const KeyList: React.FC = () => {
           placeholder="Enter API key..."
           className="key-input"
         />
+        <input
+          type="text"
+          value={newKeyWorkspace}
+          onChange={(e) => setNewKeyWorkspace(e.target.value)}
+          placeholder="Workspace ID (optional, looked up if empty)"
+          className="key-input"
+        />
+        <input
+          type="number"
+          min="0"
+          value={newKeyTtlDays}
+          onChange={(e) => setNewKeyTtlDays(e.target.value)}
+          placeholder="Lifetime in days (optional)"
+          className="key-input"
+        />
+        <input
+          type="number"
+          min="0"
+          step="0.01"
+          value={newKeySpendCap}
+          onChange={(e) => setNewKeySpendCap(e.target.value)}
+          placeholder="Spend cap in $ (optional)"
+          className="key-input"
+        />
+        <input
+          type="number"
+          min="1"
+          value={newKeyMaxNodes}
+          onChange={(e) => setNewKeyMaxNodes(e.target.value)}
+          placeholder="Max nodes (optional)"
+          className="key-input"
+        />
         <button onClick={handleAddKey} className="btn btn-primary">Add Key</button>
       </div>
       
@@ -296,23 +543,82 @@ const KeyList: React.FC = () => {
           <tr>
             <th>API Key</th>
             <th>Status</th>
+            <th>Workspace</th>
             <th>Nodes</th>
+            <th>Cost</th>
+            <th>Expires</th>
+            <th>Weight</th>
             <th>Actions</th>
           </tr>
         </thead>
         <tbody>
           {apiKeys.map((key) => (
-            <tr key={key.key}>
+            <tr
+              key={key.key_id}
+              onClick={() => handleSelectKey(key.key_id)}
+              className={selectedKey === key.key_id ? 'selected' : ''}
+              style={{ cursor: 'pointer' }}
+            >
               <td className="key-cell">
-                <code>{key.key.substring(0, 20)}...</code>
+                <code>{revealed?.key_id === key.key_id ? revealed.api_key : key.key_hint}</code>
               </td>
               <td>
                 <span className={`status ${key.status}`}>{key.status}</span>
               </td>
-              <td>{key.assigned_nodes.length}</td>
+              <td><code>{key.workspace_id || '—'}</code></td>
+              <td
+                onClick={(e) => { e.stopPropagation(); handleSetMaxNodes(key.key_id, key.max_nodes); }}
+                title="Click to change the node limit"
+              >
+                {key.assigned_nodes.length}{key.max_nodes != null && ` / ${key.max_nodes}`}
+                {key.max_nodes != null && (
+                  <div className="fill-bar">
+                    <div
+                      className={`fill-bar-level${key.assigned_nodes.length >= key.max_nodes ? ' full' : ''}`}
+                      style={{ width: `${Math.min(100, (key.assigned_nodes.length / key.max_nodes) * 100)}%` }}
+                    />
+                  </div>
+                )}
+              </td>
+              <td>
+                ${key.total_cost.toFixed(2)}
+                {key.spend_cap != null && ` / $${key.spend_cap.toFixed(2)}`}
+              </td>
+              <td>{key.expires_at ? new Date(key.expires_at * 1000).toLocaleString() : 'Never'}</td>
               <td>
+                <button
+                  onClick={(e) => { e.stopPropagation(); handleSetWeight(key.key_id, key.weight); }}
+                  className="btn btn-sm"
+                >
+                  {key.weight}
+                </button>
+              </td>
+              <td>
+                {revealed?.key_id === key.key_id ? (
+                  <button
+                    onClick={(e) => { e.stopPropagation(); setRevealed(null); }}
+                    className="btn btn-sm"
+                  >
+                    Hide
+                  </button>
+                ) : (
+                  <button
+                    onClick={(e) => { e.stopPropagation(); handleRevealKey(key.key_id); }}
+                    className="btn btn-sm"
+                  >
+                    Reveal
+                  </button>
+                )}
+                {key.status === 'active' && (
+                  <button
+                    onClick={(e) => { e.stopPropagation(); handleRotateKey(key.key_id); }}
+                    className="btn btn-sm"
+                  >
+                    Rotate
+                  </button>
+                )}
                 <button 
-                  onClick={() => handleRemoveKey(key.key)}
+                  onClick={(e) => { e.stopPropagation(); handleRemoveKey(key.key_id); }}
                   className="btn btn-sm btn-danger"
                 >
                   Remove
@@ -322,11 +628,1200 @@ const KeyList: React.FC = () => {
           ))}
         </tbody>
       </table>
+      
+      <KeyCostChart />
+    </div>
+  );
+};
+
+// Scheduled jobs (cost refresh, key expiry, ...) with their last run status
+const JobsPanel: React.FC = () => {
+  const [jobs, setJobs] = useState<JobInfo[]>([]);
+  const [jobMessage, setJobMessage] = useState('');
+  
+  const loadJobs = async () => {
+    try {
+      const response = await AnthropicApiKeyManager.list_jobs();
+      setJobs(response);
+    } catch (error) {
+      console.error('Failed to load jobs:', error);
+    }
+  };
+  
+  const handleRunJob = async (name: string) => {
+    try {
+      const job = await AnthropicApiKeyManager.trigger_job({ name });
+      setJobMessage(`${job.name}: ${job.last_outcome}${job.last_error ? ` (${job.last_error})` : ''}`);
+      await loadJobs();
+    } catch (error) {
+      console.error('Failed to run job:', error);
+      setJobMessage(`Failed to run ${name}`);
+    }
+  };
+  
+  useEffect(() => {
+    loadJobs();
+  }, []);
+  
+  return (
+    <div className="admin-actions">
+      <h3>Scheduled Jobs</h3>
+      <table className="key-table">
+        <thead>
+          <tr>
+            <th>Job</th>
+            <th>Interval</th>
+            <th>Last Run</th>
+            <th>Outcome</th>
+            <th>Next Run</th>
+            <th>Actions</th>
+          </tr>
+        </thead>
+        <tbody>
+          {jobs.map((job) => (
+            <tr key={job.name}>
+              <td><code>{job.name}</code>{!job.enabled && ' (disabled)'}</td>
+              <td>{Math.round(job.interval_secs / 60)} min</td>
+              <td>
+                {job.last_run_at ? new Date(job.last_run_at * 1000).toLocaleString() : 'Never'}
+                {job.last_duration_ms != null && ` (${job.last_duration_ms} ms)`}
+              </td>
+              <td title={job.last_error || job.last_message || ''}>{job.running ? 'running' : job.last_outcome || '—'}</td>
+              <td>{new Date(job.next_run_at * 1000).toLocaleString()}</td>
+              <td>
+                <button
+                  onClick={() => handleRunJob(job.name)}
+                  className="btn btn-sm btn-primary"
+                  disabled={job.running}
+                >
+                  Run now
+                </button>
+              </td>
+            </tr>
+          ))}
+        </tbody>
+      </table>
+      {jobMessage && <p className="admin-message">{jobMessage}</p>}
     </div>
   );
 };
 
 // AdminPanel Component
+const WaitlistPanel: React.FC = () => {
+  const [waitlist, setWaitlist] = useState<WaitlistEntry[]>([]);
+  const [deliveries, setDeliveries] = useState<KeyDelivery[]>([]);
+  const [pruneDays, setPruneDays] = useState('');
+  const [waitlistMessage, setWaitlistMessage] = useState('');
+  
+  const loadWaitlist = async () => {
+    try {
+      setWaitlist(await AnthropicApiKeyManager.get_waitlist());
+      setDeliveries(await AnthropicApiKeyManager.get_key_deliveries());
+    } catch (error) {
+      console.error('Failed to load waitlist:', error);
+    }
+  };
+  
+  const handleMove = async (nodeId: string, position: number) => {
+    try {
+      setWaitlist(await AnthropicApiKeyManager.move_waitlist_entry({ node_id: nodeId, position: Math.max(0, position) }));
+    } catch (error) {
+      console.error('Failed to move waitlist entry:', error);
+    }
+  };
+  
+  const handlePrune = async (nodeIds: string[] | null) => {
+    try {
+      const response = await AnthropicApiKeyManager.prune_waitlist({
+        node_ids: nodeIds,
+        older_than_secs: nodeIds === null && pruneDays.trim() ? Math.round(parseFloat(pruneDays) * 86400) : null
+      });
+      setWaitlistMessage(response.message);
+      await loadWaitlist();
+    } catch (error) {
+      console.error('Failed to prune waitlist:', error);
+      setWaitlistMessage(`Failed to prune waitlist: ${error}`);
+    }
+  };
+  
+  useEffect(() => {
+    loadWaitlist();
+  }, []);
+  
+  return (
+    <div className="admin-actions">
+      <h3>Waitlist ({waitlist.length})</h3>
+      {waitlist.length === 0 ? (
+        <p>No nodes are waiting for a key.</p>
+      ) : (
+        <table className="key-table">
+          <thead>
+            <tr>
+              <th>#</th>
+              <th>Node</th>
+              <th>Waiting Since</th>
+              <th>Reason</th>
+              <th>Actions</th>
+            </tr>
+          </thead>
+          <tbody>
+            {waitlist.map((entry, index) => (
+              <tr key={entry.node_id}>
+                <td>{index + 1}</td>
+                <td><code>{entry.node_id}</code></td>
+                <td>{new Date(entry.requested_at * 1000).toLocaleString()}</td>
+                <td>{entry.reason}</td>
+                <td>
+                  <button className="btn btn-sm" disabled={index === 0} onClick={() => handleMove(entry.node_id, index - 1)}>Up</button>
+                  <button className="btn btn-sm" disabled={index === waitlist.length - 1} onClick={() => handleMove(entry.node_id, index + 1)}>Down</button>
+                  <button className="btn btn-sm btn-danger" onClick={() => handlePrune([entry.node_id])}>Remove</button>
+                </td>
+              </tr>
+            ))}
+          </tbody>
+        </table>
+      )}
+      <input
+        type="number"
+        min="0"
+        value={pruneDays}
+        onChange={(e) => setPruneDays(e.target.value)}
+        placeholder="Prune entries older than (days)"
+        className="key-input"
+      />
+      <button onClick={() => handlePrune(null)} className="btn btn-danger" disabled={!pruneDays.trim()}>Prune</button>
+      {deliveries.length > 0 && (
+        <table className="key-table">
+          <thead>
+            <tr>
+              <th>Node</th>
+              <th>Key</th>
+              <th>Reason</th>
+              <th>Sent</th>
+              <th>Delivery</th>
+            </tr>
+          </thead>
+          <tbody>
+            {deliveries.map((delivery) => (
+              <tr key={delivery.node_id}>
+                <td><code>{delivery.node_id}</code></td>
+                <td><code>{delivery.key_id}</code></td>
+                <td>{delivery.reason}</td>
+                <td>{new Date(delivery.sent_at * 1000).toLocaleString()}</td>
+                <td title={delivery.error || ''}>{delivery.status}</td>
+              </tr>
+            ))}
+          </tbody>
+        </table>
+      )}
+      {waitlistMessage && <p className="admin-message">{waitlistMessage}</p>}
+    </div>
+  );
+};
+
+const RotationPanel: React.FC = () => {
+  const [rotations, setRotations] = useState<KeyRotation[]>([]);
+  const [graceHours, setGraceHours] = useState('');
+  const [maxAgeDays, setMaxAgeDays] = useState('');
+  const [rotationMessage, setRotationMessage] = useState('');
+  
+  const loadRotations = async () => {
+    try {
+      const policy: RotationPolicy = await AnthropicApiKeyManager.get_rotation_policy();
+      setGraceHours(policy.grace_secs != null ? (policy.grace_secs / 3600).toString() : '');
+      setMaxAgeDays(policy.max_key_age_secs != null ? (policy.max_key_age_secs / 86400).toString() : '');
+      setRotations(await AnthropicApiKeyManager.get_rotations());
+    } catch (error) {
+      console.error('Failed to load rotations:', error);
+    }
+  };
+  
+  const handleSavePolicy = async () => {
+    try {
+      const response = await AnthropicApiKeyManager.set_rotation_policy({
+        grace_secs: graceHours.trim() ? Math.round(parseFloat(graceHours) * 3600) : null,
+        max_key_age_secs: maxAgeDays.trim() ? Math.round(parseFloat(maxAgeDays) * 86400) : null
+      });
+      setRotationMessage(response.message);
+    } catch (error) {
+      console.error('Failed to set rotation policy:', error);
+      setRotationMessage(`Failed to set rotation policy: ${error}`);
+    }
+  };
+  
+  useEffect(() => {
+    loadRotations();
+  }, []);
+  
+  return (
+    <div className="admin-actions">
+      <h3>Key Rotation</h3>
+      <p>Rotated keys keep working upstream for the grace period so nodes can pick up their new key.</p>
+      <input
+        type="number"
+        min="0"
+        value={graceHours}
+        onChange={(e) => setGraceHours(e.target.value)}
+        placeholder="Grace period in hours (default 24)"
+        className="key-input"
+      />
+      <input
+        type="number"
+        min="0"
+        value={maxAgeDays}
+        onChange={(e) => setMaxAgeDays(e.target.value)}
+        placeholder="Rotate keys older than (days, empty for never)"
+        className="key-input"
+      />
+      <button onClick={handleSavePolicy} className="btn btn-primary">Save Policy</button>
+      {rotations.length > 0 && (
+        <table className="key-table">
+          <thead>
+            <tr>
+              <th>Old Key</th>
+              <th>Trigger</th>
+              <th>Started</th>
+              <th>Retires Upstream</th>
+              <th>Nodes</th>
+            </tr>
+          </thead>
+          <tbody>
+            {rotations.map((rotation) => (
+              <tr key={rotation.rotation_id}>
+                <td><code>{rotation.old_key_hint}</code></td>
+                <td>{rotation.trigger}</td>
+                <td>{new Date(rotation.started_at * 1000).toLocaleString()}</td>
+                <td>
+                  {rotation.completed_at
+                    ? `Done ${new Date(rotation.completed_at * 1000).toLocaleString()}`
+                    : new Date(rotation.deactivate_after * 1000).toLocaleString()}
+                </td>
+                <td>
+                  {rotation.moves.length === 0 ? 'None' : rotation.moves.map((move) => (
+                    <div key={move.node_id} title={move.error || ''}>
+                      <code>{move.node_id}</code> → {move.new_key_id ?? 'waitlist'} ({move.delivery_status})
+                    </div>
+                  ))}
+                </td>
+              </tr>
+            ))}
+          </tbody>
+        </table>
+      )}
+      {rotationMessage && <p className="admin-message">{rotationMessage}</p>}
+    </div>
+  );
+};
+
+const ReissuePanel: React.FC = () => {
+  const [allowReissue, setAllowReissue] = useState(false);
+  const [maxReissues, setMaxReissues] = useState('');
+  const [cooldownHours, setCooldownHours] = useState('');
+  const [historyNode, setHistoryNode] = useState('');
+  const [history, setHistory] = useState<NodeGrant[] | null>(null);
+  const [reissueMessage, setReissueMessage] = useState('');
+  
+  useEffect(() => {
+    AnthropicApiKeyManager.get_reissue_policy()
+      .then((policy: ReissuePolicy) => {
+        setAllowReissue(policy.allow_reissue);
+        setMaxReissues(policy.max_reissues?.toString() ?? '');
+        setCooldownHours(policy.cooldown_secs != null ? (policy.cooldown_secs / 3600).toString() : '');
+      })
+      .catch((error) => console.error('Failed to load re-issue policy:', error));
+  }, []);
+  
+  const handleSavePolicy = async () => {
+    try {
+      const response = await AnthropicApiKeyManager.set_reissue_policy({
+        allow_reissue: allowReissue,
+        max_reissues: maxReissues.trim() ? parseInt(maxReissues, 10) : null,
+        cooldown_secs: cooldownHours.trim() ? Math.round(parseFloat(cooldownHours) * 3600) : null
+      });
+      setReissueMessage(response.message);
+    } catch (error) {
+      console.error('Failed to set re-issue policy:', error);
+      setReissueMessage(`Failed to set re-issue policy: ${error}`);
+    }
+  };
+  
+  const handleLoadHistory = async () => {
+    if (!historyNode.trim()) return;
+    try {
+      setHistory(await AnthropicApiKeyManager.get_grant_history({ node_id: historyNode.trim() }));
+    } catch (error) {
+      console.error('Failed to load grant history:', error);
+      setReissueMessage('Failed to load grant history');
+    }
+  };
+  
+  return (
+    <div className="admin-actions">
+      <h3>Re-issuing Revoked Keys</h3>
+      <p>Whether a node whose key was removed or expired may get a replacement.</p>
+      <label>
+        <input type="checkbox" checked={allowReissue} onChange={(e) => setAllowReissue(e.target.checked)} />
+        {' '}Allow re-issue
+      </label>
+      <input
+        type="number"
+        min="0"
+        value={maxReissues}
+        onChange={(e) => setMaxReissues(e.target.value)}
+        placeholder="Replacements per node (empty for unlimited)"
+        className="key-input"
+      />
+      <input
+        type="number"
+        min="0"
+        value={cooldownHours}
+        onChange={(e) => setCooldownHours(e.target.value)}
+        placeholder="Cooldown after revocation in hours"
+        className="key-input"
+      />
+      <button onClick={handleSavePolicy} className="btn btn-primary">Save Policy</button>
+      <div>
+        <input
+          type="text"
+          value={historyNode}
+          onChange={(e) => setHistoryNode(e.target.value)}
+          placeholder="Node id"
+          className="key-input"
+        />
+        <button onClick={handleLoadHistory} className="btn btn-sm">Grant History</button>
+      </div>
+      {history && (history.length === 0 ? <p>No grants for this node.</p> : (
+        <table className="key-table">
+          <thead>
+            <tr>
+              <th>Key</th>
+              <th>Issued</th>
+              <th>Ended</th>
+              <th>App</th>
+            </tr>
+          </thead>
+          <tbody>
+            {history.map((grant) => (
+              <tr key={grant.grant_id}>
+                <td><code>{grant.key_id}</code>{grant.reissue && ' (re-issue)'}</td>
+                <td>{new Date(grant.issued_at * 1000).toLocaleString()}</td>
+                <td>
+                  {grant.ended_at != null
+                    ? `${new Date(grant.ended_at * 1000).toLocaleString()} (${grant.end_reason})`
+                    : 'Current'}
+                </td>
+                <td>{grant.app_name ? `${grant.app_name} ${grant.app_version ?? ''}` : '-'}</td>
+              </tr>
+            ))}
+          </tbody>
+        </table>
+      ))}
+      {reissueMessage && <p className="admin-message">{reissueMessage}</p>}
+    </div>
+  );
+};
+
+const LeasePanel: React.FC = () => {
+  const [leaseHours, setLeaseHours] = useState('');
+  const [leaseMessage, setLeaseMessage] = useState('');
+  
+  useEffect(() => {
+    AnthropicApiKeyManager.get_lease_policy()
+      .then((policy: LeasePolicy) => setLeaseHours(policy.lease_secs != null ? (policy.lease_secs / 3600).toString() : ''))
+      .catch((error) => console.error('Failed to load lease policy:', error));
+  }, []);
+  
+  const handleSave = async () => {
+    try {
+      const response = await AnthropicApiKeyManager.set_lease_policy({
+        lease_secs: leaseHours.trim() ? Math.round(parseFloat(leaseHours) * 3600) : null
+      });
+      setLeaseMessage(response.message);
+    } catch (error) {
+      console.error('Failed to set lease policy:', error);
+      setLeaseMessage(`Failed to set lease policy: ${error}`);
+    }
+  };
+  
+  return (
+    <div className="admin-actions">
+      <h3>Grant Leases</h3>
+      <p>Nodes must renew their grant within the lease or their key is taken back for others.</p>
+      <input
+        type="number"
+        min="0"
+        value={leaseHours}
+        onChange={(e) => setLeaseHours(e.target.value)}
+        placeholder="Lease in hours (empty for permanent grants)"
+        className="key-input"
+      />
+      <button onClick={handleSave} className="btn btn-primary">Save Policy</button>
+      {leaseMessage && <p className="admin-message">{leaseMessage}</p>}
+    </div>
+  );
+};
+
+const BansPanel: React.FC = () => {
+  const [bans, setBans] = useState<NodeBan[]>([]);
+  const [pattern, setPattern] = useState('');
+  const [reason, setReason] = useState('');
+  const [expiresDays, setExpiresDays] = useState('');
+  const [rotateKeys, setRotateKeys] = useState(false);
+  const [banMessage, setBanMessage] = useState('');
+  
+  const loadBans = async () => {
+    try {
+      setBans(await AnthropicApiKeyManager.list_bans());
+    } catch (error) {
+      console.error('Failed to load bans:', error);
+    }
+  };
+  
+  const handleBan = async () => {
+    if (!pattern.trim() || !reason.trim()) return;
+    try {
+      const response = await AnthropicApiKeyManager.ban_node({
+        pattern: pattern.trim(),
+        reason: reason.trim(),
+        expires_in_secs: expiresDays.trim() ? Math.round(parseFloat(expiresDays) * 86400) : null,
+        rotate_keys: rotateKeys
+      });
+      setPattern('');
+      setReason('');
+      setExpiresDays('');
+      setRotateKeys(false);
+      setBanMessage(`Banned ${response.ban.pattern}: revoked ${response.revoked_nodes.length} nodes, rotated ${response.rotations.length} keys`);
+      await loadBans();
+    } catch (error) {
+      console.error('Failed to ban node:', error);
+      setBanMessage(`Failed to ban node: ${error}`);
+    }
+  };
+  
+  const handleUnban = async (banId: string) => {
+    try {
+      const response = await AnthropicApiKeyManager.unban_node({ ban_id: banId });
+      setBanMessage(response.message);
+      await loadBans();
+    } catch (error) {
+      console.error('Failed to lift ban:', error);
+    }
+  };
+  
+  useEffect(() => {
+    loadBans();
+  }, []);
+  
+  const now = Date.now() / 1000;
+  
+  return (
+    <div className="admin-actions">
+      <h3>Banned Nodes</h3>
+      <p>Ban an exact node id, or a namespace with a wildcard such as <code>*.example.os</code>.</p>
+      <input
+        type="text"
+        value={pattern}
+        onChange={(e) => setPattern(e.target.value)}
+        placeholder="Node id or pattern"
+        className="key-input"
+      />
+      <input
+        type="text"
+        value={reason}
+        onChange={(e) => setReason(e.target.value)}
+        placeholder="Reason"
+        className="key-input"
+      />
+      <input
+        type="number"
+        min="0"
+        value={expiresDays}
+        onChange={(e) => setExpiresDays(e.target.value)}
+        placeholder="Expires in days (empty for never)"
+        className="key-input"
+      />
+      <label>
+        <input type="checkbox" checked={rotateKeys} onChange={(e) => setRotateKeys(e.target.checked)} />
+        {' '}Rotate the keys they held
+      </label>
+      <button onClick={handleBan} className="btn btn-danger" disabled={!pattern.trim() || !reason.trim()}>Ban</button>
+      {bans.length > 0 && (
+        <table className="key-table">
+          <thead>
+            <tr>
+              <th>Pattern</th>
+              <th>Reason</th>
+              <th>Banned</th>
+              <th>Expires</th>
+              <th>Actions</th>
+            </tr>
+          </thead>
+          <tbody>
+            {bans.map((ban) => (
+              <tr key={ban.ban_id}>
+                <td><code>{ban.pattern}</code></td>
+                <td>{ban.reason}</td>
+                <td>{new Date(ban.banned_at * 1000).toLocaleString()}</td>
+                <td>
+                  {ban.expires_at == null
+                    ? 'Never'
+                    : `${new Date(ban.expires_at * 1000).toLocaleString()}${ban.expires_at <= now ? ' (expired)' : ''}`}
+                </td>
+                <td>
+                  <button className="btn btn-sm" onClick={() => handleUnban(ban.ban_id)}>Lift</button>
+                </td>
+              </tr>
+            ))}
+          </tbody>
+        </table>
+      )}
+      {banMessage && <p className="admin-message">{banMessage}</p>}
+    </div>
+  );
+};
+
+const RateLimitsPanel: React.FC = () => {
+  const [status, setStatus] = useState<RateLimitStatus | null>(null);
+  const [form, setForm] = useState<Record<string, string>>({});
+  const [limitsMessage, setLimitsMessage] = useState('');
+  
+  const loadStatus = async () => {
+    try {
+      const response = await AnthropicApiKeyManager.get_rate_limits();
+      setStatus(response);
+      const values: Record<string, string> = {};
+      RATE_LIMIT_FIELDS.forEach(({ field }) => {
+        values[field] = response.limits[field]?.toString() ?? '';
+      });
+      setForm(values);
+    } catch (error) {
+      console.error('Failed to load rate limits:', error);
+    }
+  };
+  
+  const handleSave = async () => {
+    const limits = {} as RateLimits;
+    RATE_LIMIT_FIELDS.forEach(({ field }) => {
+      limits[field] = form[field]?.trim() ? parseInt(form[field], 10) : null;
+    });
+    try {
+      const response = await AnthropicApiKeyManager.set_rate_limits(limits);
+      setLimitsMessage(response.message);
+      await loadStatus();
+    } catch (error) {
+      console.error('Failed to set rate limits:', error);
+      setLimitsMessage(`Failed to set rate limits: ${error}`);
+    }
+  };
+  
+  const handleResume = async () => {
+    try {
+      const response = await AnthropicApiKeyManager.resume_issuance();
+      setLimitsMessage(response.message);
+      await loadStatus();
+    } catch (error) {
+      console.error('Failed to resume issuance:', error);
+    }
+  };
+  
+  useEffect(() => {
+    loadStatus();
+  }, []);
+  
+  return (
+    <div className="admin-actions">
+      <h3>Rate Limits</h3>
+      {status && (
+        <p>
+          {status.issued_last_hour} new nodes in the last hour, {status.issued_last_day} in the last day.
+          {status.denials_last_day.length > 0 && ` Denied today: ${status.denials_last_day.map(([limit, count]) => `${limit} ${count}`).join(', ')}.`}
+        </p>
+      )}
+      {status?.paused_until && (
+        <p className="admin-message">
+          Issuance paused after a burst until {new Date(status.paused_until * 1000).toLocaleString()}.
+          {' '}<button onClick={handleResume} className="btn btn-sm">Resume now</button>
+        </p>
+      )}
+      {RATE_LIMIT_FIELDS.map(({ field, label }) => (
+        <input
+          key={field}
+          type="number"
+          min="0"
+          value={form[field] ?? ''}
+          onChange={(e) => setForm({ ...form, [field]: e.target.value })}
+          placeholder={`${label} (empty for no limit)`}
+          className="key-input"
+        />
+      ))}
+      <button onClick={handleSave} className="btn btn-primary">Save Limits</button>
+      {status && status.namespaces.length > 0 && (
+        <table className="key-table">
+          <thead>
+            <tr>
+              <th>Namespace</th>
+              <th>New Nodes (24h)</th>
+            </tr>
+          </thead>
+          <tbody>
+            {status.namespaces.slice(0, 10).map(([namespace, count]) => (
+              <tr key={namespace}>
+                <td><code>{namespace}</code></td>
+                <td>{count}</td>
+              </tr>
+            ))}
+          </tbody>
+        </table>
+      )}
+      {limitsMessage && <p className="admin-message">{limitsMessage}</p>}
+    </div>
+  );
+};
+
+const InvitesPanel: React.FC = () => {
+  const [invites, setInvites] = useState<InviteCode[]>([]);
+  const [required, setRequired] = useState(false);
+  const [count, setCount] = useState('10');
+  const [maxUses, setMaxUses] = useState('');
+  const [expiresDays, setExpiresDays] = useState('');
+  const [campaign, setCampaign] = useState('');
+  const [inviteMessage, setInviteMessage] = useState('');
+  
+  const loadInvites = async () => {
+    try {
+      setRequired(await AnthropicApiKeyManager.get_invite_required());
+      setInvites(await AnthropicApiKeyManager.list_invite_codes({ batch_id: null, campaign: null, status: null }));
+    } catch (error) {
+      console.error('Failed to load invite codes:', error);
+    }
+  };
+  
+  const handleToggleRequired = async () => {
+    try {
+      const response = await AnthropicApiKeyManager.set_invite_required({ required: !required });
+      setRequired(!required);
+      setInviteMessage(response.message);
+    } catch (error) {
+      console.error('Failed to change invite requirement:', error);
+    }
+  };
+  
+  const handleMint = async () => {
+    try {
+      const minted = await AnthropicApiKeyManager.mint_invite_codes({
+        count: parseInt(count, 10),
+        max_uses: maxUses.trim() ? parseInt(maxUses, 10) : null,
+        expires_in_secs: expiresDays.trim() ? Math.round(parseFloat(expiresDays) * 86400) : null,
+        campaign: campaign.trim() || null
+      });
+      setInviteMessage(`Minted ${minted.length} codes`);
+      await loadInvites();
+    } catch (error) {
+      console.error('Failed to mint invite codes:', error);
+      setInviteMessage(`Failed to mint invite codes: ${error}`);
+    }
+  };
+  
+  const handleRevoke = async (codes: string[] | null, batchId: string | null) => {
+    try {
+      const response = await AnthropicApiKeyManager.revoke_invite_codes({ codes, batch_id: batchId });
+      setInviteMessage(response.message);
+      await loadInvites();
+    } catch (error) {
+      console.error('Failed to revoke invite codes:', error);
+    }
+  };
+  
+  const handleExport = async () => {
+    try {
+      const csv = await AnthropicApiKeyManager.export_invite_codes({ batch_id: null, campaign: null, status: null });
+      const url = URL.createObjectURL(new Blob([csv], { type: 'text/csv' }));
+      const link = document.createElement('a');
+      link.href = url;
+      link.download = 'invite-codes.csv';
+      link.click();
+      URL.revokeObjectURL(url);
+    } catch (error) {
+      console.error('Failed to export invite codes:', error);
+    }
+  };
+  
+  useEffect(() => {
+    loadInvites();
+  }, []);
+  
+  return (
+    <div className="admin-actions">
+      <h3>Invite Codes</h3>
+      <p>
+        {required ? 'New nodes need an invite code to get a key.' : 'Any node can request a key; invite codes are optional.'}
+        {' '}<button onClick={handleToggleRequired} className="btn btn-sm">{required ? 'Open to all' : 'Require invites'}</button>
+      </p>
+      <input
+        type="number"
+        min="1"
+        value={count}
+        onChange={(e) => setCount(e.target.value)}
+        placeholder="Number of codes"
+        className="key-input"
+      />
+      <input
+        type="number"
+        min="1"
+        value={maxUses}
+        onChange={(e) => setMaxUses(e.target.value)}
+        placeholder="Uses per code (default 1)"
+        className="key-input"
+      />
+      <input
+        type="number"
+        min="0"
+        value={expiresDays}
+        onChange={(e) => setExpiresDays(e.target.value)}
+        placeholder="Expires in days (optional)"
+        className="key-input"
+      />
+      <input
+        type="text"
+        value={campaign}
+        onChange={(e) => setCampaign(e.target.value)}
+        placeholder="Campaign (optional)"
+        className="key-input"
+      />
+      <button onClick={handleMint} className="btn btn-primary" disabled={!count.trim()}>Mint</button>
+      <button onClick={handleExport} className="btn" disabled={invites.length === 0}>Export CSV</button>
+      {invites.length > 0 && (
+        <table className="key-table">
+          <thead>
+            <tr>
+              <th>Code</th>
+              <th>Campaign</th>
+              <th>Uses</th>
+              <th>Status</th>
+              <th>Redeemed By</th>
+              <th>Actions</th>
+            </tr>
+          </thead>
+          <tbody>
+            {invites.map((invite) => (
+              <tr key={invite.code}>
+                <td><code>{invite.code}</code></td>
+                <td>{invite.campaign ?? ''}</td>
+                <td>{invite.redemptions.length} / {invite.max_uses}</td>
+                <td>{invite.status}</td>
+                <td>{invite.redemptions.map((r) => r.node_id).join(', ')}</td>
+                <td>
+                  {invite.revoked_at == null && (
+                    <>
+                      <button className="btn btn-sm btn-danger" onClick={() => handleRevoke([invite.code], null)}>Revoke</button>
+                      <button className="btn btn-sm btn-danger" onClick={() => handleRevoke(null, invite.batch_id)}>Revoke Batch</button>
+                    </>
+                  )}
+                </td>
+              </tr>
+            ))}
+          </tbody>
+        </table>
+      )}
+      {inviteMessage && <p className="admin-message">{inviteMessage}</p>}
+    </div>
+  );
+};
+
+const SelectionStrategyPanel: React.FC = () => {
+  const [strategy, setStrategy] = useState<SelectionStrategy | null>(null);
+  const [poolMode, setPoolMode] = useState<PoolMode | null>(null);
+  const [strategyMessage, setStrategyMessage] = useState('');
+  
+  useEffect(() => {
+    AnthropicApiKeyManager.get_selection_strategy()
+      .then(setStrategy)
+      .catch((error) => console.error('Failed to load selection strategy:', error));
+    AnthropicApiKeyManager.get_pool_health()
+      .then((health) => setPoolMode(health.pool_mode))
+      .catch((error) => console.error('Failed to load pool mode:', error));
+  }, []);
+  
+  const handleModeChange = async (value: PoolMode) => {
+    try {
+      const response = await AnthropicApiKeyManager.set_pool_mode({ mode: value });
+      setPoolMode(value);
+      setStrategyMessage(response.message);
+    } catch (error) {
+      console.error('Failed to set pool mode:', error);
+      setStrategyMessage('Failed to set pool mode');
+    }
+  };
+  
+  const handleChange = async (value: SelectionStrategy) => {
+    try {
+      const response = await AnthropicApiKeyManager.set_selection_strategy({ strategy: value });
+      setStrategy(value);
+      setStrategyMessage(response.message);
+    } catch (error) {
+      console.error('Failed to set selection strategy:', error);
+      setStrategyMessage('Failed to set selection strategy');
+    }
+  };
+  
+  return (
+    <div className="admin-actions">
+      <h3>Key Selection</h3>
+      <p>Whether nodes share keys, or each node gets a key of its own from the reserve.</p>
+      <select
+        value={poolMode ?? ''}
+        onChange={(e) => handleModeChange(e.target.value as PoolMode)}
+        className="key-input"
+      >
+        {POOL_MODES.map(({ value, label }) => (
+          <option key={value} value={value}>{label}</option>
+        ))}
+      </select>
+      <p>How a key is chosen for each newly connecting node.</p>
+      <select
+        value={strategy ?? ''}
+        onChange={(e) => handleChange(e.target.value as SelectionStrategy)}
+        className="key-input"
+      >
+        {SELECTION_STRATEGIES.map(({ value, label }) => (
+          <option key={value} value={value}>{label}</option>
+        ))}
+      </select>
+      {strategyMessage && <p className="admin-message">{strategyMessage}</p>}
+    </div>
+  );
+};
+
+const PoolThresholdsPanel: React.FC = () => {
+  const [health, setHealth] = useState<PoolHealth | null>(null);
+  const [minActiveKeys, setMinActiveKeys] = useState('');
+  const [maxNodesPerKey, setMaxNodesPerKey] = useState('');
+  const [minHeadroom, setMinHeadroom] = useState('');
+  const [webhookUrl, setWebhookUrl] = useState('');
+  const [defaultMaxNodes, setDefaultMaxNodes] = useState('');
+  const [thresholdMessage, setThresholdMessage] = useState('');
+  
+  const loadHealth = async () => {
+    try {
+      const response = await AnthropicApiKeyManager.get_pool_health();
+      setHealth(response);
+      setMinActiveKeys(response.thresholds.min_active_keys?.toString() ?? '');
+      setMaxNodesPerKey(response.thresholds.max_nodes_per_key?.toString() ?? '');
+      setMinHeadroom(response.thresholds.min_spend_headroom?.toString() ?? '');
+      setWebhookUrl(response.alert_webhook_url ?? '');
+      setDefaultMaxNodes(response.default_max_nodes?.toString() ?? '');
+    } catch (error) {
+      console.error('Failed to load pool health:', error);
+    }
+  };
+  
+  const handleSave = async () => {
+    try {
+      await AnthropicApiKeyManager.set_pool_thresholds({
+        thresholds: {
+          min_active_keys: minActiveKeys.trim() ? parseInt(minActiveKeys, 10) : null,
+          max_nodes_per_key: maxNodesPerKey.trim() ? parseInt(maxNodesPerKey, 10) : null,
+          min_spend_headroom: minHeadroom.trim() ? parseFloat(minHeadroom) : null
+        },
+        alert_webhook_url: webhookUrl.trim() || null
+      });
+      await AnthropicApiKeyManager.set_max_nodes({
+        key_id: null,
+        max_nodes: defaultMaxNodes.trim() ? parseInt(defaultMaxNodes, 10) : null
+      });
+      setThresholdMessage('Pool thresholds saved');
+      await loadHealth();
+    } catch (error) {
+      console.error('Failed to save pool thresholds:', error);
+      setThresholdMessage(`Failed to save pool thresholds: ${error}`);
+    }
+  };
+  
+  useEffect(() => {
+    loadHealth();
+  }, []);
+  
+  return (
+    <div className="admin-actions">
+      <h3>Pool Health</h3>
+      {health && (
+        <p>
+          {health.active_keys} active keys serving {health.assigned_nodes} nodes.
+          {' '}Spend headroom: {health.spend_headroom != null ? `$${health.spend_headroom.toFixed(2)}` : 'unbounded'}.
+          {' '}Room for {health.projected_capacity != null ? `about ${health.projected_capacity}` : 'an unbounded number of'} more nodes.
+        </p>
+      )}
+      <input
+        type="number"
+        min="0"
+        value={minActiveKeys}
+        onChange={(e) => setMinActiveKeys(e.target.value)}
+        placeholder="Minimum active keys"
+        className="key-input"
+      />
+      <input
+        type="number"
+        min="1"
+        value={maxNodesPerKey}
+        onChange={(e) => setMaxNodesPerKey(e.target.value)}
+        placeholder="Alert above this many nodes per key"
+        className="key-input"
+      />
+      <input
+        type="number"
+        min="1"
+        value={defaultMaxNodes}
+        onChange={(e) => setDefaultMaxNodes(e.target.value)}
+        placeholder="Default node limit per key (hard)"
+        className="key-input"
+      />
+      <input
+        type="number"
+        min="0"
+        step="0.01"
+        value={minHeadroom}
+        onChange={(e) => setMinHeadroom(e.target.value)}
+        placeholder="Minimum spend headroom in $"
+        className="key-input"
+      />
+      <input
+        type="text"
+        value={webhookUrl}
+        onChange={(e) => setWebhookUrl(e.target.value)}
+        placeholder="Alert webhook URL (optional)"
+        className="key-input"
+      />
+      <button onClick={handleSave} className="btn btn-primary">Save Thresholds</button>
+      {health && health.resolved_alerts.length > 0 && (
+        <table className="key-table">
+          <thead>
+            <tr>
+              <th>Resolved Alert</th>
+              <th>Raised</th>
+              <th>Resolved</th>
+            </tr>
+          </thead>
+          <tbody>
+            {health.resolved_alerts.slice(0, 10).map((alert) => (
+              <tr key={`${alert.kind}-${alert.raised_at}`} title={alert.message}>
+                <td><code>{alert.kind}</code></td>
+                <td>{new Date(alert.raised_at * 1000).toLocaleString()}</td>
+                <td>{alert.resolved_at ? new Date(alert.resolved_at * 1000).toLocaleString() : '—'}</td>
+              </tr>
+            ))}
+          </tbody>
+        </table>
+      )}
+      {thresholdMessage && <p className="admin-message">{thresholdMessage}</p>}
+    </div>
+  );
+};
+
+const ReconcilePanel: React.FC = () => {
+  const [report, setReport] = useState<ReconcileReport | null>(null);
+  const [reconcileMessage, setReconcileMessage] = useState('');
+  const [busy, setBusy] = useState(false);
+  
+  const loadReport = async () => {
+    try {
+      const response = await AnthropicApiKeyManager.get_reconciliation();
+      setReport(response ?? null);
+    } catch (error) {
+      console.error('Failed to load reconciliation report:', error);
+    }
+  };
+  
+  const handleCheck = async () => {
+    setBusy(true);
+    try {
+      const response = await AnthropicApiKeyManager.reconcile_keys();
+      setReport(response);
+      setReconcileMessage('');
+    } catch (error) {
+      console.error('Failed to reconcile keys:', error);
+      setReconcileMessage(`Reconciliation failed: ${error}`);
+    } finally {
+      setBusy(false);
+    }
+  };
+  
+  const handleApply = async (driftIds: string[] | null) => {
+    setBusy(true);
+    try {
+      const response = await AnthropicApiKeyManager.apply_reconciliation({ drift_ids: driftIds });
+      setReport(response.report);
+      const failures = response.failed.map(([id, error]) => `${id}: ${error}`).join('; ');
+      setReconcileMessage(`Applied ${response.applied.length} fixes${failures ? `, failed: ${failures}` : ''}`);
+    } catch (error) {
+      console.error('Failed to apply fixes:', error);
+      setReconcileMessage(`Applying fixes failed: ${error}`);
+    } finally {
+      setBusy(false);
+    }
+  };
+  
+  useEffect(() => {
+    loadReport();
+  }, []);
+  
+  return (
+    <div className="admin-actions">
+      <h3>Organization Reconciliation</h3>
+      <div>
+        <button onClick={handleCheck} className="btn btn-primary" disabled={busy}>Check now</button>
+        {report && report.drift.some(d => d.kind !== 'unpooled_upstream') && (
+          <button onClick={() => handleApply(null)} className="btn btn-danger" disabled={busy}>Apply safe fixes</button>
+        )}
+      </div>
+      {report && (
+        <p>
+          Checked {new Date(report.generated_at * 1000).toLocaleString()}: {report.matched_keys} of {report.upstream_keys} organization keys matched to the pool, {report.drift.length} drift items.
+        </p>
+      )}
+      {report && report.drift.length > 0 && (
+        <table className="key-table">
+          <thead>
+            <tr>
+              <th>Drift</th>
+              <th>Key</th>
+              <th>Upstream</th>
+              <th>Fix</th>
+              <th>Actions</th>
+            </tr>
+          </thead>
+          <tbody>
+            {report.drift.map((item) => (
+              <tr key={item.id}>
+                <td><code>{item.kind}</code></td>
+                <td><code>{item.key_hint || item.key_id || '—'}</code></td>
+                <td>{item.name ? `${item.name} (${item.upstream_status})` : item.upstream_status || '—'}</td>
+                <td><code>{item.fix}</code></td>
+                <td>
+                  <button onClick={() => handleApply([item.id])} className="btn btn-sm btn-primary" disabled={busy}>
+                    Apply
+                  </button>
+                </td>
+              </tr>
+            ))}
+          </tbody>
+        </table>
+      )}
+      {reconcileMessage && <p className="admin-message">{reconcileMessage}</p>}
+    </div>
+  );
+};
+
+const AUDIT_PAGE_SIZE = 25;
+
+const AuditLogPanel: React.FC = () => {
+  const [entries, setEntries] = useState<AuditEntry[]>([]);
+  const [total, setTotal] = useState(0);
+  const [chainValid, setChainValid] = useState(true);
+  const [firstInvalid, setFirstInvalid] = useState<number | null>(null);
+  const [page, setPage] = useState(0);
+  const [actionFilter, setActionFilter] = useState('');
+  const [retention, setRetention] = useState('');
+  const [retentionMessage, setRetentionMessage] = useState('');
+  
+  const loadAuditLog = async () => {
+    try {
+      const response = await AnthropicApiKeyManager.get_audit_log({
+        offset: page * AUDIT_PAGE_SIZE,
+        limit: AUDIT_PAGE_SIZE,
+        action: actionFilter.trim() || null,
+        actor: null,
+        key_id: null,
+        node_id: null,
+        since: null,
+        until: null
+      });
+      setEntries(response.entries);
+      setTotal(response.total);
+      setChainValid(response.chain_valid);
+      setFirstInvalid(response.first_invalid_seq ?? null);
+      setRetention(response.retention?.toString() ?? '');
+    } catch (error) {
+      console.error('Failed to load audit log:', error);
+    }
+  };
+  
+  const handleSaveRetention = async () => {
+    try {
+      const response = await AnthropicApiKeyManager.set_audit_retention({
+        max_entries: retention.trim() ? parseInt(retention, 10) : null
+      });
+      setRetentionMessage(response.message);
+    } catch (error) {
+      console.error('Failed to set audit retention:', error);
+      setRetentionMessage(`Failed to set audit retention: ${error}`);
+    }
+  };
+  
+  useEffect(() => {
+    loadAuditLog();
+  }, [page, actionFilter]);
+  
+  const pages = Math.max(1, Math.ceil(total / AUDIT_PAGE_SIZE));
+  
+  return (
+    <div className="admin-actions">
+      <h3>Audit Log</h3>
+      {!chainValid && (
+        <p className="admin-message">
+          Audit log integrity check failed at entry #{firstInvalid}; entries from there on may have been altered.
+        </p>
+      )}
+      <input
+        type="text"
+        value={actionFilter}
+        onChange={(e) => { setActionFilter(e.target.value); setPage(0); }}
+        placeholder="Filter by action (e.g. add_api_key)"
+        className="key-input"
+      />
+      <table className="key-table">
+        <thead>
+          <tr>
+            <th>#</th>
+            <th>Time</th>
+            <th>Actor</th>
+            <th>Action</th>
+            <th>Target</th>
+            <th>Result</th>
+          </tr>
+        </thead>
+        <tbody>
+          {entries.map((entry) => (
+            <tr key={entry.seq} title={entry.params.map(([k, v]) => `${k}=${v}`).join(', ')}>
+              <td>{entry.seq}</td>
+              <td>{new Date(entry.timestamp * 1000).toLocaleString()}</td>
+              <td><code>{entry.actor}</code></td>
+              <td><code>{entry.action}</code></td>
+              <td><code>{entry.target_key || entry.target_node || '—'}</code></td>
+              <td title={entry.error || ''}>{entry.result}</td>
+            </tr>
+          ))}
+        </tbody>
+      </table>
+      <div>
+        <button className="btn btn-sm" disabled={page === 0} onClick={() => setPage(page - 1)}>Newer</button>
+        <span> Page {page + 1} of {pages} </span>
+        <button className="btn btn-sm" disabled={page + 1 >= pages} onClick={() => setPage(page + 1)}>Older</button>
+      </div>
+      <div>
+        <input
+          type="number"
+          value={retention}
+          onChange={(e) => setRetention(e.target.value)}
+          placeholder="Entries to keep (blank keeps everything)"
+          className="key-input"
+        />
+        <button className="btn btn-sm" onClick={handleSaveRetention}>Set Retention</button>
+      </div>
+      {retentionMessage && <p className="admin-message">{retentionMessage}</p>}
+    </div>
+  );
+};
+
 const AdminPanel: React.FC = () => {
   const { adminKeySet } = useApiKeyManagerStore();
   const [adminKey, setAdminKey] = useState('');
@@ -466,6 +1961,19 @@ const AdminPanel: React.FC = () => {
           {message && <p className="admin-message">{message}</p>}
         </div>
       )}
+      
+      <PoolThresholdsPanel />
+      <SelectionStrategyPanel />
+      <WaitlistPanel />
+      <RotationPanel />
+      <ReissuePanel />
+      <LeasePanel />
+      <BansPanel />
+      <RateLimitsPanel />
+      <InvitesPanel />
+      <JobsPanel />
+      <ReconcilePanel />
+      <AuditLogPanel />
     </div>
   );
 };
@@ -520,16 +2028,9 @@ function App() {
     const init = async () => {
       setLoading(true);
       try {
-        // Initialize auth and check admin key status
-        const authResponse = await AnthropicApiKeyManager.initialize_auth();
-        const authData = authResponse;
-        
-        useApiKeyManagerStore.getState().setAuthToken(authData.token);
-        
-        // Set admin key status if provided
-        if (authData.has_admin_key !== undefined) {
-          useApiKeyManagerStore.getState().setAdminKeySet(authData.has_admin_key);
-        }
+        // Check admin key status
+        const adminStatus = await AnthropicApiKeyManager.check_admin_key();
+        useApiKeyManagerStore.getState().setAdminKeySet(adminStatus.has_admin_key);
         
         // Load initial data
         const [keysResponse, historyResponse] = await Promise.all([
@@ -564,7 +2065,11 @@ function App() {
           }
         }
       } catch (error) {
-        setError(error instanceof Error ? error.message : 'Failed to initialize');
+        const message = error instanceof Error ? error.message : 'Failed to initialize';
+        // The API only accepts requests from the node owner's logged-in session
+        setError(message.includes('401')
+          ? 'Not authenticated. Log in to your node to manage API keys.'
+          : message);
       } finally {
         setLoading(false);
       }
@@ -13,7 +13,6 @@ interface ApiKeyManagerStore {
   nodeHistory: NodeAssignment[];
   selectedKey: string | null;
   adminKeySet: boolean;
-  authToken: string | null;
   loading: boolean;
   error: string | null;
   totalCosts: CostData | null;
@@ -25,7 +24,6 @@ interface ApiKeyManagerStore {
   setNodeHistory: (history: NodeAssignment[]) => void;
   setSelectedKey: (key: string | null) => void;
   setAdminKeySet: (set: boolean) => void;
-  setAuthToken: (token: string | null) => void;
   setLoading: (loading: boolean) => void;
   setError: (error: string | null) => void;
   setTotalCosts: (costs: CostData | null) => void;
@@ -39,7 +37,6 @@ const initialState = {
   nodeHistory: [],
   selectedKey: null,
   adminKeySet: false,
-  authToken: null,
   loading: false,
   error: null,
   totalCosts: null,
@@ -54,7 +51,6 @@ export const useApiKeyManagerStore = create<ApiKeyManagerStore>((set) => ({
   setNodeHistory: (history) => set({ nodeHistory: history }),
   setSelectedKey: (key) => set({ selectedKey: key }),
   setAdminKeySet: (isSet) => set({ adminKeySet: isSet }),
-  setAuthToken: (token) => set({ authToken: token }),
   setLoading: (loading) => set({ loading }),
   setError: (error) => set({ error }),
   setTotalCosts: (costs) => set({ totalCosts: costs }),
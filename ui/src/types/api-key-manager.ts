@@ -1,5 +1,6 @@
 export interface ApiKey {
-  key: string;
+  key_id: string;
+  key_hint: string;
   status: string;
   total_cost: number;
   assigned_nodes: string[];
@@ -15,7 +16,8 @@ export interface CostRecord {
 
 export interface NodeAssignment {
   node_id: string;  // Keep as snake_case to match what backend sends
-  api_key: string;
+  key_id: string;
+  key_hint: string;
   issued_at: number;
 }
 
@@ -26,7 +28,7 @@ export interface CostData {
 }
 
 export interface KeyCostData {
-  api_key: string;
+  key_id: string;
   costs: CostRecord[];
   total: number;
 }